@@ -58,6 +58,26 @@
 //! These can also be combined. eg. `/api/v0/recipes/list?limit=10&offset=40` to implement different page
 //! lengths.
 //!
+//! ## Recipe branches
+//!
+//! Each user can keep their own recipe history and workspace in a separate git branch. The branch is
+//! selected with the `X-Bdcs-Branch` header, or the `branch` parameter, eg.
+//! `/api/v0/recipes/list?branch=bcl`, and defaults to `master`. A new branch starts with the recipes
+//! from `master`, and is created when a recipe is first written to it.
+//!
+//! ## Strict mode
+//!
+//! Routes that write a recipe, like `/recipes/new`, accept a `strict` parameter. With
+//! `?strict=true` the recipe is checked the same way as `/recipes/validate`, and it is rejected with
+//! a 400 and an error for each problem instead of being saved.
+//!
+//! ## Recipe includes
+//!
+//! A recipe can include other recipes from the same branch with `include = ["base"]`. The
+//! included modules, packages and groups are used when the recipe is depsolved, frozen or
+//! composed. `/recipes/info` returns the recipe as it was written, or with `?flatten=true` it
+//! returns the recipe with its includes merged into it.
+//!
 //! # Responses
 //!
 //! All responses will be JSON objects. Responses to GET requests will have the response included
@@ -70,16 +90,35 @@
 //! ## Error Responses
 //!
 //! In addition to the HTTP Error codes, extra information will be included in a JSON response object with
-//! `{"id": "internal error id", "msg": "Human readable message, suitable for passing to users"}`
+//! `{"status": false, "errors": [{"id": "internal error id", "msg": "Human readable message, suitable for passing to users"}]}`
+//!
+//! * 400 Bad Request - The request, or the recipe in it, is not valid. eg. an invalid branch name.
+//! * 401 Unauthorized - The request changes the recipes, and it has no valid credentials.
+//! * 403 Forbidden - The request changes the recipes, and the user's token is read-only.
+//! * 404 Not Found - The recipe, branch, commit, or compose does not exist.
+//! * 409 Conflict - The request conflicts with the current state. eg. a recipe merge conflict.
+//! * 500 Internal Server Error - Everything else.
+//!
+//! Routes that take a list of names, eg. `/api/v0/recipes/info/<recipes>`, return the names they
+//! could find, and an `errors` list with an entry for each of the others.
 //!
 //! # Authentication
 //!
-//! This is still TBD.
+//! When the server is started with `--tokens`, the routes that change the recipe repository or
+//! the compose queue require credentials. They are `/recipes/new`, `/recipes/workspace`,
+//! `/recipes/delete`, `/recipes/undo`, `/recipes/tag`, `/recipes/merge`, `/compose` and
+//! `/compose/cancel`. The authenticated user is the author of the recipe commits.
+//! Without `--tokens` no credentials are needed. See the [auth](../auth/index.html) module.
 //!
 //! ## Authorization: Bearer tokens
 //!
+//! `Authorization: Bearer <token>`, using the token from the token file.
+//!
 //! ## Basic Auth tokens
 //!
+//! `Authorization: Basic <credentials>`, using the user's login and token as the username and
+//! password.
+//!
 
 // Copyright (C) 2016-2017 Red Hat, Inc.
 //
@@ -97,16 +136,29 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+use std::cell::RefCell;
 use std::fmt;
 use std::error::Error as StdError;
+use std::sync::Arc;
 
+use git2;
+use hyper;
 use hyper::method::Method;
+use rocket::{config, Outcome, Rocket};
 use rocket::http::hyper::header;
 use rocket::http::Status;
+use rocket::http::uri::URI;
+use rocket::request::{self, FromRequest, Request, State};
 use rocket::response::{self, Responder, Response};
+use rocket_contrib::JSON;
 use rusqlite;
 
-use recipe::RecipeError;
+use auth::{Auth, Credentials, User};
+use compose::ComposeError;
+use depclose::DepError;
+use recipe::{Author, RecipeError};
+use validate::RecipeProblem;
+use workspace::WorkspaceError;
 
 pub mod v0;
 pub mod mock;
@@ -127,7 +179,8 @@ pub static LIMIT: i64 = 20;
 #[derive(Debug, Serialize, FromForm)]
 pub struct Filter {
     pub offset: Option<i64>,
-    pub limit: Option<i64>
+    pub limit: Option<i64>,
+    pub branch: Option<String>
 }
 
 /// This is used for requesting a specific format for the result
@@ -136,12 +189,389 @@ pub struct Filter {
 ///
 #[derive(Debug, Serialize, FromForm)]
 pub struct Format {
-    pub format: String
+    pub format: String,
+    pub branch: Option<String>,
+    pub flatten: Option<String>
+}
+
+/// This is used for selecting the package architectures to depsolve for
+///
+/// Pass it to the handler as `arch: Arch` and it will contain the ?arch=<string> argument, a comma
+/// separated list of architectures like `x86_64,i686`.
+///
+#[derive(Debug, Serialize, FromForm)]
+pub struct Arch {
+    pub arch: String,
+    pub branch: Option<String>
+}
+
+impl Arch {
+    /// Return the list of architectures
+    pub fn arches(&self) -> Vec<String> {
+        split_arches(&self.arch)
+    }
+}
+
+/// This is used for requesting a specific format for the result, and the package architectures
+///
+/// Pass it to the handler as `format: FormatArch` and it will contain the ?format=<string> and
+/// ?arch=<string> arguments.
+///
+#[derive(Debug, Serialize, FromForm)]
+pub struct FormatArch {
+    pub format: String,
+    pub arch: String,
+    pub branch: Option<String>
+}
+
+impl FormatArch {
+    /// Return the list of architectures
+    pub fn arches(&self) -> Vec<String> {
+        split_arches(&self.arch)
+    }
+}
+
+// Split a comma separated list of architectures
+fn split_arches(arch: &str) -> Vec<String> {
+    arch.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()).map(String::from).collect()
+}
+
+/// This is used for selecting the branches to merge a recipe between
+///
+/// Pass it to the handler as `branches: MergeBranches` and it will contain the
+/// ?from=<branch>&to=<branch> arguments.
+///
+#[derive(Debug, Serialize, FromForm)]
+pub struct MergeBranches {
+    pub from: String,
+    pub to: String
+}
+
+/// The recipe branch used by a request
+///
+/// Pass it to a handler as `branch: RecipeBranch`. It is set by the `X-Bdcs-Branch` header, or the
+/// URL-encoded `?branch=<name>` query parameter, and defaults to `master`. Requests with an invalid
+/// branch name fail with a 400 Bad Request.
+///
+/// The query parameter is also a field of the other query structs, so that it can be combined with
+/// them, eg. `?offset=0&branch=bcl`.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecipeBranch(String);
+
+impl RecipeBranch {
+    /// Return the branch name
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check a branch name
+    ///
+    /// The name is used as a git branch, a tag prefix and a workspace directory, so only letters,
+    /// numbers, '-', '_' and '.' are allowed, and it cannot start with a '.' or a '-'.
+    pub fn is_valid(name: &str) -> bool {
+        !name.is_empty() && name.len() <= 64
+            && !name.starts_with('.') && !name.starts_with('-')
+            && !name.ends_with(".lock") && !name.contains("..")
+            && name.chars().all(|c| c.is_ascii() && (c.is_alphanumeric() || "-_.".contains(c)))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RecipeBranch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RecipeBranch, ()> {
+        let query_branch = query_value(request, "branch");
+
+        let branch = match request.headers().get_one("X-Bdcs-Branch") {
+            Some(header) => header.to_string(),
+            None => query_branch.unwrap_or_else(|| "master".to_string())
+        };
+
+        if RecipeBranch::is_valid(&branch) {
+            Outcome::Success(RecipeBranch(branch))
+        } else {
+            Outcome::Failure((Status::BadRequest, ()))
+        }
+    }
+}
+
+/// Strict mode for requests that write recipes
+///
+/// Pass it to a handler as `strict: StrictMode`. It is set by the `?strict=true` query parameter,
+/// and defaults to off. When it is on the recipe is checked with
+/// [validate_recipe](../validate/fn.validate_recipe.html) before it is saved.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrictMode(bool);
+
+impl StrictMode {
+    /// Return true if the recipe should be validated
+    pub fn is_strict(&self) -> bool {
+        self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for StrictMode {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<StrictMode, ()> {
+        match query_flag(request, "strict") {
+            Ok(strict) => Outcome::Success(StrictMode(strict)),
+            Err(_) => Outcome::Failure((Status::BadRequest, ()))
+        }
+    }
+}
+
+/// Return recipes with their included recipes flattened into them
+///
+/// Pass it to a handler as `flatten: FlattenMode`. It is set by the `?flatten=true` query
+/// parameter, and defaults to off, returning the recipe as it was written.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlattenMode(bool);
+
+impl FlattenMode {
+    /// Return true if the recipe's includes should be flattened
+    pub fn is_flat(&self) -> bool {
+        self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for FlattenMode {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<FlattenMode, ()> {
+        match query_flag(request, "flatten") {
+            Ok(flatten) => Outcome::Success(FlattenMode(flatten)),
+            Err(_) => Outcome::Failure((Status::BadRequest, ()))
+        }
+    }
+}
+
+/// Follow the results of a query recursively
+///
+/// Pass it to a handler as `recursive: RecursiveMode`. It is set by the `?recursive=true` query
+/// parameter, and defaults to off, eg. `/modules/whatrequires` only returns the direct requirements.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecursiveMode(bool);
+
+impl RecursiveMode {
+    /// Return true if the query should be recursive
+    pub fn is_recursive(&self) -> bool {
+        self.0
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RecursiveMode {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RecursiveMode, ()> {
+        match query_flag(request, "recursive") {
+            Ok(recursive) => Outcome::Success(RecursiveMode(recursive)),
+            Err(_) => Outcome::Failure((Status::BadRequest, ()))
+        }
+    }
+}
+
+/// The user making a request that changes the recipes
+///
+/// Pass it to a handler as `user: WriteUser`, and use its [author](#method.author) for the
+/// commit. When authentication is enabled the request is rejected with a 401 if it has no valid
+/// credentials, and with a 403 if the user is read-only. When it is disabled there is no user, and
+/// the server's default author is used.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteUser(Option<User>);
+
+impl WriteUser {
+    /// Return the git author for the request's commits
+    pub fn author(&self) -> Author {
+        match self.0 {
+            Some(ref user) => user.author(),
+            None => Author::default()
+        }
+    }
+
+    /// Return the user's login, or an empty string when authentication is disabled
+    pub fn login(&self) -> &str {
+        self.0.as_ref().map(|u| u.login.as_str()).unwrap_or("")
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for WriteUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<WriteUser, ()> {
+        let auth = match State::<Auth>::from_request(request) {
+            Outcome::Success(auth) => auth,
+            _ => return Outcome::Failure((Status::InternalServerError, ()))
+        };
+        if !auth.is_enabled() {
+            return Outcome::Success(WriteUser(None));
+        }
+
+        let user = request.headers().get_one("Authorization")
+                          .and_then(Credentials::from_header)
+                          .and_then(|credentials| auth.authenticate(&credentials));
+        match user {
+            None => Outcome::Failure((Status::Unauthorized, ())),
+            Some(ref user) if user.read_only => Outcome::Failure((Status::Forbidden, ())),
+            Some(user) => Outcome::Success(WriteUser(Some(user)))
+        }
+    }
+}
+
+// Return the value of a boolean query parameter, false if it is missing
+fn query_flag(request: &Request, name: &str) -> Result<bool, ()> {
+    match query_value(request, name).as_ref().map(String::as_str) {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(_) => Err(())
+    }
+}
+
+// Return the value of a query parameter, for request guards that also read the query string
+fn query_value(request: &Request, name: &str) -> Option<String> {
+    request.uri().query().and_then(|query| query_param(query, name))
+}
+
+// Find a parameter in a query string and decode its value the way Rocket decodes form values
+//
+// A value that is not valid UTF-8 once decoded is returned as it is, so that the guard rejects it.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&')
+         .filter_map(|param| {
+             let mut parts = param.splitn(2, '=');
+             match (parts.next(), parts.next()) {
+                 (Some(key), Some(value)) if key == name => {
+                     let value = value.replace('+', " ");
+                     match URI::percent_decode(value.as_bytes()) {
+                         Ok(decoded) => Some(decoded.into_owned()),
+                         Err(_) => Some(value.clone())
+                     }
+                 },
+                 _ => None
+             }
+         })
+         .next()
+}
+
+/// The CORS settings
+///
+/// These are read from the `cors_origins`, `cors_credentials` and `cors_max_age` settings in the
+/// `[global]` section of the Rocket config. The default allows any origin, without credentials.
+///
+/// Credentials are only allowed for the origins that are listed by name, never for `*`.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsPolicy {
+    /// The origins allowed to make requests, eg. `https://welder.example.com`, or `*` for any
+    pub origins: Vec<String>,
+    /// Allow requests with credentials, ie. cookies or an Authorization header
+    pub credentials: bool,
+    /// How long, in seconds, a client may cache the result of a preflight request
+    pub max_age: Option<u32>
+}
+
+impl Default for CorsPolicy {
+    fn default() -> CorsPolicy {
+        CorsPolicy {
+            origins:     vec!["*".to_string()],
+            credentials: false,
+            max_age:     None
+        }
+    }
+}
+
+impl CorsPolicy {
+    /// Read the policy from the active Rocket config
+    pub fn from_config() -> CorsPolicy {
+        let mut policy = CorsPolicy::default();
+        let config = match config::active() {
+            Some(config) => config,
+            None => return policy
+        };
+
+        if let Ok(origins) = config.get_slice("cors_origins") {
+            policy.origins = origins.iter().filter_map(|o| o.as_str()).map(String::from).collect();
+        }
+        if let Ok(credentials) = config.get_bool("cors_credentials") {
+            policy.credentials = credentials;
+        }
+        match config.get_int("cors_max_age") {
+            Ok(max_age) if max_age > 0 => policy.max_age = Some(max_age as u32),
+            _ => ()
+        }
+        policy
+    }
+
+    /// Return the Access-Control-Allow-Origin header for a request's origin
+    ///
+    /// This is None when the origin is not allowed, and the CORS headers should be left out.
+    /// Origins that are listed by name are echoed, and only they may send credentials. Any other
+    /// origin gets `*` if it is allowed, which browsers do not accept with credentials.
+    pub fn allow_origin(&self, origin: Option<&str>) -> Option<header::AccessControlAllowOrigin> {
+        match origin {
+            Some(origin) if self.origins.iter().any(|o| o == origin) => Some(header::AccessControlAllowOrigin::Value(origin.to_string())),
+            _ if self.origins.iter().any(|o| o == "*") => Some(header::AccessControlAllowOrigin::Any),
+            _ => None
+        }
+    }
+}
+
+// The Origin of the request being handled by this thread, and the server's CORS policy
+thread_local!(static REQUEST_CORS: RefCell<Option<(Option<String>, Arc<CorsPolicy>)>> = RefCell::new(None));
+
+/// Record the Origin of the request being handled by this thread, and the policy for its CORS headers
+///
+/// Rocket's responders can't see the request, and each request is handled on a single thread,
+/// so [CorsHandler](struct.CorsHandler.html) records it here before dispatching the request.
+pub fn set_request_cors(origin: Option<String>, policy: Arc<CorsPolicy>) {
+    REQUEST_CORS.with(|c| *c.borrow_mut() = Some((origin, policy)));
+}
+
+// Requests that didn't come through CorsHandler, eg. in the tests, use the default policy
+fn request_cors() -> (Option<String>, Arc<CorsPolicy>) {
+    REQUEST_CORS.with(|c| c.borrow().clone())
+                .unwrap_or_else(|| (None, Arc::new(CorsPolicy::default())))
+}
+
+/// Hyper handler that records the request's Origin and then passes the request to Rocket
+///
+/// The [CorsPolicy](struct.CorsPolicy.html) is read once, when the server is launched.
+pub struct CorsHandler(pub Rocket, pub Arc<CorsPolicy>);
+
+impl hyper::server::Handler for CorsHandler {
+    fn handle<'a, 'k>(&'a self, req: hyper::server::Request<'a, 'k>, res: hyper::server::Response<'a>) {
+        let origin = req.headers.get_raw("Origin")
+                                .and_then(|values| values.first())
+                                .and_then(|value| String::from_utf8(value.clone()).ok());
+        set_request_cors(origin, self.1.clone());
+        hyper::server::Handler::handle(&self.0, req, res)
+    }
+}
+
+/// Launch the server
+///
+/// Use this instead of Rocket's `launch()` so that the CORS headers can use the request's
+/// Origin. The address, port and number of workers are read from the Rocket config.
+pub fn launch(rocket: Rocket) {
+    let config = config::active().expect("Rocket has not been configured");
+    let address = format!("{}:{}", config.address, config.port);
+    let server = hyper::Server::http(address.as_str()).expect("Error binding to the address");
+
+    let policy = CorsPolicy::from_config();
+    info!("BDCS API listening"; "address" => address, "cors" => format!("{:?}", policy));
+    server.handle_threads(CorsHandler(rocket, Arc::new(policy)), config.workers as usize).unwrap();
 }
 
 /// Response wrapper that adds CORS headers to the response
 ///
-/// Based on the JSON response from Rocket's contrib library.
+/// Based on the JSON response from Rocket's contrib library. The headers are set using the
+/// [CorsPolicy](struct.CorsPolicy.html) that [launch](fn.launch.html) read from the config, and
+/// are left out if the request's origin is not allowed.
 #[derive(Debug)]
 pub struct CORS<R>(pub R);
 
@@ -154,8 +584,27 @@ impl<'r, R: Responder<'r>> CORS<R> {
 
 impl<'r, R: Responder<'r>> Responder<'r> for CORS<R> {
     fn respond(self) -> response::Result<'r> {
-        Response::build_from(try!(self.0.respond()))
-            .header(header::AccessControlAllowOrigin::Any)
+        let response = try!(self.0.respond());
+        let (origin, policy) = request_cors();
+        let allow_origin = match policy.allow_origin(origin.as_ref().map(String::as_str)) {
+            Some(allow_origin) => allow_origin,
+            None => return Ok(response)
+        };
+
+        let mut builder = Response::build_from(response);
+        // The response depends on the origin when it is echoed back
+        if let header::AccessControlAllowOrigin::Value(_) = allow_origin {
+            builder.raw_header("Vary", "Origin");
+            if policy.credentials {
+                builder.header(header::AccessControlAllowCredentials);
+            }
+        }
+        if let Some(max_age) = policy.max_age {
+            builder.header(header::AccessControlMaxAge(max_age));
+        }
+
+        builder
+            .header(allow_origin)
             .header(header::AccessControlAllowMethods(vec![
                 Method::Get, Method::Post, Method::Delete, Method::Options]))
             .header(header::AccessControlAllowHeaders(vec![
@@ -166,26 +615,150 @@ impl<'r, R: Responder<'r>> Responder<'r> for CORS<R> {
                 "X-Requested-With".into(),
                 "Content-Type".into(),
                 "Accept".into(),
+                "Authorization".into(),
+                "X-Bdcs-Branch".into(),
             ]))
             .ok()
     }
 }
 
 
+/// One error in an error response
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiErrorMsg {
+    pub id: String,
+    pub msg: String
+}
+
+/// Each problem found by recipe validation is reported as a separate error, using the problem's id
+impl<'a> From<&'a RecipeProblem> for ApiErrorMsg {
+    fn from(problem: &RecipeProblem) -> ApiErrorMsg {
+        ApiErrorMsg {
+            id: problem.id().to_string(),
+            msg: problem.to_string()
+        }
+    }
+}
+
+/// JSON body of an error response
+///
+/// eg. `{"status": false, "errors": [{"id": "NotFound", "msg": "Unknown compose id 1234"}]}`
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiErrorResponse {
+    pub status: bool,
+    pub errors: Vec<ApiErrorMsg>
+}
+
+impl<'a> From<&'a ApiError> for ApiErrorResponse {
+    fn from(err: &ApiError) -> ApiErrorResponse {
+        ApiErrorResponse {
+            status: false,
+            errors: match *err {
+                ApiError::ValidationError(ref problems) => problems.iter().map(ApiErrorMsg::from).collect(),
+                _ => vec![err.to_msg()]
+            }
+        }
+    }
+}
+
+
 /// API Error handler
+///
+/// The error is returned with its HTTP status code, the CORS headers, and an
+/// [ApiErrorResponse](struct.ApiErrorResponse.html) body.
 #[derive(Debug)]
 pub enum ApiError {
-    NotFound,
-    InternalServerError,
-    SQLiteError,
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    InternalServerError(String),
+    RecipeError(RecipeError),
+    WorkspaceError(WorkspaceError),
+    SQLiteError(rusqlite::Error),
+    DepsolveError(DepError),
+    ValidationError(Vec<RecipeProblem>),
+}
+
+impl ApiError {
+    /// Return the HTTP status code for the error
+    pub fn status(&self) -> Status {
+        match *self {
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::Forbidden(_) => Status::Forbidden,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::InternalServerError(_) => Status::InternalServerError,
+            ApiError::RecipeError(ref e) => recipe_error_status(e),
+            ApiError::WorkspaceError(WorkspaceError::RecipeError(ref e)) => recipe_error_status(e),
+            ApiError::WorkspaceError(_) => Status::InternalServerError,
+            ApiError::SQLiteError(_) => Status::InternalServerError,
+            ApiError::DepsolveError(DepError::Other(_)) => Status::InternalServerError,
+            ApiError::DepsolveError(_) => Status::BadRequest,
+            ApiError::ValidationError(_) => Status::BadRequest,
+        }
+    }
+
+    /// Return the error id used in the response
+    pub fn id(&self) -> &'static str {
+        match *self {
+            ApiError::BadRequest(_) => "BadRequest",
+            ApiError::Unauthorized(_) => "Unauthorized",
+            ApiError::Forbidden(_) => "Forbidden",
+            ApiError::NotFound(_) => "NotFound",
+            ApiError::Conflict(_) => "Conflict",
+            ApiError::InternalServerError(_) => "InternalServerError",
+            ApiError::RecipeError(_) => "RecipeError",
+            ApiError::WorkspaceError(_) => "WorkspaceError",
+            ApiError::SQLiteError(_) => "SQLiteError",
+            ApiError::DepsolveError(_) => "DepsolveError",
+            ApiError::ValidationError(_) => "ValidationError",
+        }
+    }
+
+    /// Return the id and message for the error
+    pub fn to_msg(&self) -> ApiErrorMsg {
+        ApiErrorMsg {
+            id: self.id().to_string(),
+            msg: self.to_string()
+        }
+    }
+}
+
+/// Missing recipes and branches are not found, and bad recipes are bad requests
+fn recipe_error_status(err: &RecipeError) -> Status {
+    match *err {
+        RecipeError::Git2(ref e) if e.code() == git2::ErrorCode::NotFound => Status::NotFound,
+        RecipeError::Git2(ref e) if e.code() == git2::ErrorCode::InvalidSpec => Status::BadRequest,
+        RecipeError::Branch => Status::NotFound,
+        RecipeError::RecipeName |
+        RecipeError::ParseTOML |
+        RecipeError::Include(_) |
+        RecipeError::TomlDe(_) |
+        RecipeError::SemVerError(_) => Status::BadRequest,
+        _ => Status::InternalServerError,
+    }
 }
 
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ApiError::NotFound => f.write_str("NotFound"),
-            ApiError::InternalServerError => f.write_str("InternalServerError"),
-            ApiError::SQLiteError => f.write_str("SQLiteError"),
+            ApiError::BadRequest(ref msg) |
+            ApiError::Unauthorized(ref msg) |
+            ApiError::Forbidden(ref msg) |
+            ApiError::NotFound(ref msg) |
+            ApiError::Conflict(ref msg) |
+            ApiError::InternalServerError(ref msg) => f.write_str(msg),
+            ApiError::RecipeError(ref e) => write!(f, "{}", e),
+            ApiError::WorkspaceError(ref e) => write!(f, "{}", e),
+            ApiError::SQLiteError(ref e) => write!(f, "SQLite error: {}", e),
+            ApiError::DepsolveError(ref e) => write!(f, "{}", e),
+            ApiError::ValidationError(ref problems) => {
+                let msgs: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+                write!(f, "{}", msgs.join(", "))
+            }
         }
     }
 }
@@ -193,31 +766,144 @@ impl fmt::Display for ApiError {
 impl StdError for ApiError {
     fn description(&self) -> &str {
         match *self {
-            ApiError::NotFound => "Not found",
-            ApiError::InternalServerError => "Internal server error",
-            ApiError::SQLiteError => "SQLite error",
+            ApiError::BadRequest(_) => "Bad request",
+            ApiError::Unauthorized(_) => "Unauthorized",
+            ApiError::Forbidden(_) => "Forbidden",
+            ApiError::NotFound(_) => "Not found",
+            ApiError::Conflict(_) => "Conflict",
+            ApiError::InternalServerError(_) => "Internal server error",
+            ApiError::RecipeError(_) => "Recipe error",
+            ApiError::WorkspaceError(_) => "Workspace error",
+            ApiError::SQLiteError(_) => "SQLite error",
+            ApiError::DepsolveError(_) => "Depsolve error",
+            ApiError::ValidationError(_) => "Validation error",
         }
     }
 }
 
 impl<'r> Responder<'r> for ApiError {
-    fn respond(self) -> Result<Response<'r>, Status> {
-        match self {
-            ApiError::NotFound => Err(Status::NotFound),
-            _ => Err(Status::InternalServerError),
-        }
+    fn respond(self) -> response::Result<'r> {
+        error!("API error"; "id" => self.id(), "msg" => self.to_string());
+        let status = self.status();
+        Response::build_from(try!(CORS(JSON(ApiErrorResponse::from(&self))).respond()))
+            .status(status)
+            .ok()
     }
 }
 
-// Map all RecipeErrors to NotFound for now
 impl From<RecipeError> for ApiError {
-    fn from(_err: RecipeError) -> ApiError {
-        ApiError::NotFound
+    fn from(err: RecipeError) -> ApiError {
+        ApiError::RecipeError(err)
+    }
+}
+
+impl From<WorkspaceError> for ApiError {
+    fn from(err: WorkspaceError) -> ApiError {
+        ApiError::WorkspaceError(err)
     }
 }
 
 impl From<rusqlite::Error> for ApiError {
-    fn from(_err: rusqlite::Error) -> ApiError {
-        ApiError::SQLiteError
+    fn from(err: rusqlite::Error) -> ApiError {
+        ApiError::SQLiteError(err)
+    }
+}
+
+impl From<DepError> for ApiError {
+    fn from(err: DepError) -> ApiError {
+        ApiError::DepsolveError(err)
+    }
+}
+
+impl From<ComposeError> for ApiError {
+    fn from(err: ComposeError) -> ApiError {
+        match err {
+            ComposeError::NotFound(_) => ApiError::NotFound(err.to_string()),
+            ComposeError::Finished(_) => ApiError::BadRequest(err.to_string()),
+            _ => ApiError::InternalServerError(err.to_string()),
+        }
+    }
+}
+
+
+/// Return a JSON error response for requests rejected with a 400 before reaching a handler
+///
+/// eg. an invalid branch name, or badly formed query parameters.
+#[error(400)]
+pub fn bad_request(req: &Request) -> ApiError {
+    ApiError::BadRequest(format!("Bad request: {}", req.uri()))
+}
+
+/// Return a JSON error response for requests that need credentials, and have no valid ones
+#[error(401)]
+pub fn unauthorized(req: &Request) -> ApiError {
+    ApiError::Unauthorized(format!("Authentication required: {}", req.uri()))
+}
+
+/// Return a JSON error response for requests from read-only users that change the recipes
+#[error(403)]
+pub fn forbidden(req: &Request) -> ApiError {
+    ApiError::Forbidden(format!("Read-only user: {}", req.uri()))
+}
+
+/// Return a JSON error response for requests that do not match any route
+#[error(404)]
+pub fn not_found(req: &Request) -> ApiError {
+    ApiError::NotFound(format!("Not found: {}", req.uri()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use api::*;
+    use rocket::http::hyper::header::AccessControlAllowOrigin;
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param("branch=bcl&strict=true", "branch"), Some("bcl".to_string()));
+        assert_eq!(query_param("branch=bcl&strict=true", "strict"), Some("true".to_string()));
+        assert_eq!(query_param("branch=bcl", "format"), None);
+        assert_eq!(query_param("branch", "branch"), None);
+
+        // Values are percent-decoded, and + is a space
+        assert_eq!(query_param("branch=feature%2Fx", "branch"), Some("feature/x".to_string()));
+        assert_eq!(query_param("branch=my%2Dbranch", "branch"), Some("my-branch".to_string()));
+        assert_eq!(query_param("msg=two+words", "msg"), Some("two words".to_string()));
+
+        // Values that don't decode to UTF-8 are left alone
+        assert_eq!(query_param("branch=%FF", "branch"), Some("%FF".to_string()));
+    }
+
+    #[test]
+    fn test_cors_allow_origin() {
+        // The default allows anything
+        let policy = CorsPolicy::default();
+        assert_eq!(policy.allow_origin(None), Some(AccessControlAllowOrigin::Any));
+        assert_eq!(policy.allow_origin(Some("https://example.com")), Some(AccessControlAllowOrigin::Any));
+
+        // Only the listed origins are echoed
+        let policy = CorsPolicy {
+            origins:     vec!["https://welder.example.com".to_string()],
+            credentials: true,
+            max_age:     Some(600)
+        };
+        assert_eq!(policy.allow_origin(Some("https://welder.example.com")),
+                   Some(AccessControlAllowOrigin::Value("https://welder.example.com".to_string())));
+        assert_eq!(policy.allow_origin(Some("https://example.com")), None);
+        assert_eq!(policy.allow_origin(None), None);
+
+        // Origins are never echoed for *, so credentials are not allowed for them
+        let policy = CorsPolicy { credentials: true, ..Default::default() };
+        assert_eq!(policy.allow_origin(Some("https://example.com")), Some(AccessControlAllowOrigin::Any));
+
+        // Listed origins are echoed even when * is also allowed
+        let policy = CorsPolicy {
+            origins:     vec!["*".to_string(), "https://welder.example.com".to_string()],
+            credentials: true,
+            max_age:     None
+        };
+        assert_eq!(policy.allow_origin(Some("https://welder.example.com")),
+                   Some(AccessControlAllowOrigin::Value("https://welder.example.com".to_string())));
+        assert_eq!(policy.allow_origin(Some("https://example.com")), Some(AccessControlAllowOrigin::Any));
     }
 }
@@ -16,11 +16,21 @@
 //!  - Return the status of the server, including the build, database, schema version.
 //!  - [Example JSON](fn.status.html#examples)
 //! * `/api/v0/isos`
-//! * `/api/v0/compose`
-//! * `/api/v0/compose/cancel`
+//! * POST `/api/v0/compose`
+//!  - Start a compose of a recipe, returns the UUID of the new compose.
+//!  - [Example JSON](fn.compose.html#examples)
+//! * POST `/api/v0/compose/cancel/<id>`
+//!  - Cancel a waiting or running compose.
+//!  - [Example JSON](fn.compose_cancel.html#examples)
 //! * `/api/v0/compose/status`
+//!  - Return the status of all the composes.
+//!  - [Example JSON](fn.compose_status.html#examples)
 //! * `/api/v0/compose/status/<id>`
-//! * `/api/v0/compose/log/<kbytes>`
+//!  - Return the status of a single compose.
+//! * `/api/v0/compose/log/<id>/<kbytes>`
+//!  - Return the last kbytes of a compose's log. Pass `?follow=true` to keep receiving the log
+//!    until the compose has finished.
+//!  - [Example](fn.compose_log.html#examples)
 //! * `/api/v0/compose/types`
 //!  - Return the types of images that can be created
 //!  - [Example JSON](fn.compose_types.html#examples)
@@ -34,7 +44,8 @@
 //!  - [Example JSON](fn.projects_info.html#examples)
 //! * `/api/v0/projects/depsolve/<projects>`
 //!  - Returns the dependencies for the listed projects
-//!  - [Example JSON](fn.projects_depsolve.html#examples)
+//!  - [Example JSON](fn.projects_depsolve_arch.html#examples)
+//!  - Optional `?arch=<arches>` parameter, a comma separated list of package architectures
 //! * `/api/v0/modules/list`
 //!  - Return a list of available modules
 //!  - [Example JSON](fn.modules_list.html#examples)
@@ -42,6 +53,10 @@
 //! * `/api/v0/modules/info/<modules>`
 //!  - Return detailed information about a module.
 //!  - [Example JSON](fn.modules_info.html#examples)
+//! * `/api/v0/modules/whatrequires/<module>`
+//!  - Return the modules that require a module
+//!  - [Example JSON](fn.modules_whatrequires.html#examples)
+//!  - Optional `?recursive=true` parameter, to also return the modules that require those modules
 //! * `/api/v0/recipes/list`
 //!  - List the names of the available recipes
 //!  - [Example JSON](fn.recipes_list.html#examples)
@@ -51,7 +66,10 @@
 //!  - [Example JSON](fn.recipes_info.html#examples)
 //! * `/api/v0/recipes/freeze/<recipes>`
 //!  - Return the contents of the recipe with frozen dependencies instead of expressions.
-//!  - [Example JSON](fn.recipes_freeze.html#examples)
+//!  - [Example JSON](fn.recipes_freeze_arch.html#examples)
+//!  - Optional `?arch=<arches>` parameter, a comma separated list of package architectures
+//!  - Optional `?format=toml` parameter, to return a single recipe as TOML, which may be combined
+//!    with `arch`
 //! * `/api/v0/recipes/changes/<recipes>`
 //!  - Return the commit history of the recipes
 //!  - [Example JSON](fn.recipes_changes.html#examples)
@@ -61,12 +79,18 @@
 //!  - [Example JSON](fn.recipes_diff.html#examples)
 //! * `/api/v0/recipes/depsolve/<recipes>`
 //!  - Return the recipe and summary information about all of its modules and packages.
-//!  - [Example JSON](fn.recipes_depsolve.html#examples)
+//!  - [Example JSON](fn.recipes_depsolve_arch.html#examples)
+//!  - Optional `?arch=<arches>` parameter, a comma separated list of package architectures
 //! * POST `/api/v0/recipes/new`
 //!  - Create or update a recipe.
 //!  - The body of the post is a JSON representation of the recipe, using the same format
 //!    received by `/api/v0/recipes/info/<recipes>`
 //!  - [Example JSON](fn.recipes_new.html#examples)
+//!  - Optional `?strict=true` parameter, to reject the recipe if it has any problems
+//! * POST `/api/v0/recipes/validate`
+//!  - Check a recipe for problems without saving it
+//!  - The body of the post is a JSON or TOML recipe, the same as `/api/v0/recipes/new`
+//!  - [Example JSON](fn.recipes_validate_json.html#examples)
 //! * DELETE `/api/v0/recipes/delete/<recipe>`
 //!  - Delete the named recipe from the repository
 //!  - [Example JSON](fn.recipes_delete.html#examples)
@@ -81,6 +105,9 @@
 //! * POST `/api/v0/recipes/tag/<recipe>`
 //!  - Tag the most recent recipe commit as the next revision
 //!  - [Example](fn.recipes_tag.html)
+//! * POST `/api/v0/recipes/merge/<recipe>?from=<branch>&to=<branch>`
+//!  - Merge the recipe's changes from one branch into another
+//!  - [Example JSON](fn.recipes_merge.html#examples)
 //!
 //!
 //! ## TODO
@@ -111,6 +138,9 @@
 #![cfg_attr(feature="cargo-clippy", allow(needless_pass_by_value))]
 
 use rocket::State;
+use rocket::http::Status;
+use rocket::response::Stream;
+use rocket::response::status::Custom;
 use rocket_contrib::JSON;
 use rusqlite::Connection;
 
@@ -118,8 +148,11 @@ use rusqlite::Connection;
 use db::*;
 use depclose::*;
 use depsolve::*;
-use recipe::{self, RecipeRepo, Recipe, RecipeCommit, RecipeDiff};
-use api::{ApiError, CORS, Filter, Format, OFFSET, LIMIT};
+use recipe::{self, RecipeRepo, Recipe, RecipeCommit, RecipeDiff, RecipeMergeConflict};
+use api::{ApiError, ApiErrorMsg, Arch, CORS, Filter, FlattenMode, Format, FormatArch, MergeBranches, RecipeBranch, RecursiveMode, StrictMode, WriteUser, OFFSET, LIMIT};
+use compose::{ComposeQueue, ComposeStatus, ComposeLogReader};
+use comps::Comps;
+use validate::{password_problems, validate_recipe};
 use api::toml::TOML;
 use workspace::{write_to_workspace, read_from_workspace, workspace_dir, delete_workspace};
 
@@ -250,118 +283,270 @@ pub fn isos() -> CORS<&'static str> {
 }
 
 
+// /compose
+
+/// The body of a compose request
+#[derive(Debug, Deserialize)]
+pub struct ComposeRequest {
+    recipe_name:  String,
+    compose_type: String
+}
+
+/// Hold the JSON response for /compose
+#[derive(Debug, Serialize)]
+pub struct ComposeResponse {
+    status: bool,
+    id:     String
+}
+
+/// The CORS system 'protects' the client via an OPTIONS request to make sure it is allowed
+///
+/// This returns an empty response, with the CORS headers set by [CORS](struct.CORS.html).
+// Rocket has a collision with Diesel so uses route instead
+//#[options("/compose")]
+#[route(OPTIONS, "/compose")]
+pub fn options_compose() -> CORS<&'static str> {
+    CORS("")
+}
+
 /// Start a compose
 ///
-/// # Returns
+/// # Arguments
 ///
-/// * Unimplemented
+/// * `compose` - JSON body with the `recipe_name` and `compose_type` to build
+/// * `db` - Database pool
+/// * `repo` - Recipe repository
+/// * `queue` - The compose queue
 ///
 /// # Response
 ///
-/// * 'Unimplemented' string
+/// * JSON response with the UUID of the new compose. It is used by the status and cancel routes.
 ///
-/// This means that it will be implemented eventually, and is a valid path.
+/// The recipe is depsolved before it is queued. The package list and the frozen recipe are stored
+/// with the job in the results directory.
 ///
-/// # TODO
+/// # Errors
 ///
-/// * Change it to a meaningful error code and JSON response
-/// * Return an id that can be used for cancel and status
+/// * 404 if the recipe does not exist
+/// * 400 if there is no exporter for the compose type
+/// * 400 if the recipe cannot be depsolved, with the reason in the error message
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
 ///
-#[post("/compose")]
-pub fn compose() -> CORS<&'static str> {
-    CORS("Unimplemented")
+/// # Examples
+///
+/// ```json
+/// {
+///     "status": true,
+///     "id": "b27c5a7b-d1f6-4ef4-9b5b-6e3c1c8e9a4d"
+/// }
+/// ```
+#[post("/compose", format="application/json", data="<compose>")]
+pub fn compose(compose: JSON<ComposeRequest>, user: WriteUser, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>, queue: State<ComposeQueue>) -> Result<CORS<JSON<ComposeResponse>>, ApiError> {
+    info!("/compose"; "recipe_name" => compose.recipe_name.as_str(), "compose_type" => compose.compose_type.as_str(), "user" => user.login());
+
+    if !queue.has_type(&compose.compose_type) {
+        error!("/compose unsupported type"; "compose_type" => compose.compose_type.as_str());
+        return Err(ApiError::BadRequest(format!("Unsupported compose type {}", compose.compose_type)));
+    }
+
+    let (recipe, pkg_nevras) = try!(depsolve_recipe(&db, &policy, &comps, &repo, &compose.recipe_name, &branch, None));
+    let pkg_nevras = try!(pkg_nevras);
+    let frozen = freeze_recipe(&recipe, &pkg_nevras);
+    let status = try!(queue.submit(recipe, frozen, &compose.compose_type, pkg_nevras));
+
+    Ok(CORS(JSON(ComposeResponse {
+        status: true,
+        id:     status.id
+    })))
+}
+
+/// Hold the JSON response for /compose/status and /compose/cancel
+#[derive(Debug, Serialize)]
+pub struct ComposeStatusResponse {
+    composes: Vec<ComposeStatus>
+}
+
+/// The CORS system 'protects' the client via an OPTIONS request to make sure it is allowed
+///
+/// This returns an empty response, with the CORS headers set by [CORS](struct.CORS.html).
+// Rocket has a collision with Diesel so uses route instead
+//#[options("/compose/cancel/<id>")]
+#[route(OPTIONS, "/compose/cancel/<id>")]
+#[allow(unused_variables)]
+pub fn options_compose_cancel(id: &str) -> CORS<&'static str> {
+    CORS("")
 }
 
 /// Cancel a compose
 ///
-/// # Returns
+/// # Arguments
 ///
-/// * Unimplemented
+/// * `id` - The UUID of the compose to cancel
+/// * `queue` - The compose queue
 ///
 /// # Response
 ///
-/// * 'Unimplemented' string
+/// * JSON response with the status of the canceled compose
 ///
-/// This means that it will be implemented eventually, and is a valid path.
+/// A waiting compose is removed from the queue and marked as FAILED. A running compose is
+/// stopped by the worker, and then marked as FAILED.
 ///
-/// # TODO
+/// # Errors
 ///
-/// * Change it to a meaningful error code and JSON response
-/// * Pass it an id of a running compose
+/// * 404 if the id is unknown
+/// * 400 if the compose has already finished
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
 ///
-#[post("/compose/cancel")]
-pub fn compose_cancel() -> CORS<&'static str> {
-    CORS("Unimplemented")
+/// # Examples
+///
+/// ```json
+/// {
+///     "composes": [
+///         {
+///             "id": "b27c5a7b-d1f6-4ef4-9b5b-6e3c1c8e9a4d",
+///             "recipe": "http-server",
+///             "version": "0.2.0",
+///             "compose_type": "tar",
+///             "state": "FAILED",
+///             "queued": "2017-04-05T14:30:04.517447+00:00",
+///             "started": null,
+///             "finished": "2017-04-05T14:30:10.143225+00:00",
+///             "error": "Canceled"
+///         }
+///     ]
+/// }
+/// ```
+#[post("/compose/cancel/<id>")]
+pub fn compose_cancel(id: &str, user: WriteUser, queue: State<ComposeQueue>) -> Result<CORS<JSON<ComposeStatusResponse>>, ApiError> {
+    info!("/compose/cancel/"; "id" => id, "user" => user.login());
+    let status = try!(queue.cancel(id));
+
+    Ok(CORS(JSON(ComposeStatusResponse {
+        composes: vec![status]
+    })))
 }
 
 /// Get the status of all composes
 ///
-/// # Returns
+/// # Arguments
 ///
-/// * Unimplemented
+/// * `queue` - The compose queue
 ///
 /// # Response
 ///
-/// * 'Unimplemented' string
-///
-/// This means that it will be implemented eventually, and is a valid path.
-///
-/// # TODO
+/// * JSON response with the status of all the composes, oldest first
 ///
-/// * Change it to a meaningful error code and JSON response
+/// # Examples
 ///
+/// ```json
+/// {
+///     "composes": [
+///         {
+///             "id": "b27c5a7b-d1f6-4ef4-9b5b-6e3c1c8e9a4d",
+///             "recipe": "http-server",
+///             "version": "0.2.0",
+///             "compose_type": "tar",
+///             "state": "RUNNING",
+///             "queued": "2017-04-05T14:30:04.517447+00:00",
+///             "started": "2017-04-05T14:30:04.518203+00:00",
+///             "finished": null,
+///             "error": null
+///         }
+///     ]
+/// }
+/// ```
 #[get("/compose/status")]
-pub fn compose_status() -> CORS<&'static str> {
-    CORS("Unimplemented")
+pub fn compose_status(queue: State<ComposeQueue>) -> CORS<JSON<ComposeStatusResponse>> {
+    info!("/compose/status");
+    CORS(JSON(ComposeStatusResponse {
+        composes: queue.list()
+    }))
 }
 
 /// Get the status of a specific compose
 ///
-/// # Returns
+/// # Arguments
 ///
-/// * Unimplemented
+/// * `id` - The UUID of the compose
+/// * `queue` - The compose queue
 ///
 /// # Response
 ///
-/// * 'Unimplemented' string
-///
-/// This means that it will be implemented eventually, and is a valid path.
+/// * JSON response with the status of the compose, in the same format as
+///   [compose_status](fn.compose_status.html)
 ///
-/// # TODO
+/// # Errors
 ///
-/// * Change it to a meaningful error code and JSON response
+/// * 404 if the id is unknown
 ///
 #[get("/compose/status/<id>")]
-#[allow(unused_variables)]
-pub fn compose_status_id(id: &str) -> CORS<&'static str> {
-    #![cfg_attr(feature="strict", allow(unused_variables))]
-    CORS("Unimplemented")
+pub fn compose_status_id(id: &str, queue: State<ComposeQueue>) -> Result<CORS<JSON<ComposeStatusResponse>>, ApiError> {
+    info!("/compose/status/"; "id" => id);
+    let status = try_opt!(queue.status(id), Err(ApiError::NotFound(format!("Unknown compose id {}", id))));
+
+    Ok(CORS(JSON(ComposeStatusResponse {
+        composes: vec![status]
+    })))
 }
 
-/// Get the logs from a running compose
+// Log output is sent as soon as this many bytes are available
+const LOG_CHUNK_SIZE: u64 = 256;
+
+/// This is used for the optional follow parameter of `/compose/log`
+#[derive(Debug, FromForm)]
+pub struct LogFollow {
+    pub follow: bool
+}
+
+/// Handler for `/compose/log/<id>/<kbytes>` with the follow argument.
 ///
-/// # Returns
+/// This calls [compose_log](fn.compose_log.html) with the `follow` value.
+#[get("/compose/log/<id>/<kbytes>?<follow>")]
+pub fn compose_log_follow(id: &str, kbytes: u64, follow: LogFollow, queue: State<ComposeQueue>) -> Result<CORS<Stream<ComposeLogReader>>, ApiError> {
+    compose_log(id, kbytes, follow.follow, queue)
+}
+
+/// Handler for `/compose/log/<id>/<kbytes>` without any arguments.
 ///
-/// * Unimplemented
+/// This calls [compose_log](fn.compose_log.html) with follow set to false.
+#[get("/compose/log/<id>/<kbytes>", rank=2)]
+pub fn compose_log_default(id: &str, kbytes: u64, queue: State<ComposeQueue>) -> Result<CORS<Stream<ComposeLogReader>>, ApiError> {
+    compose_log(id, kbytes, false, queue)
+}
+
+/// Get the logs from a compose
+///
+/// # Arguments
+///
+/// * `id` - The UUID of the compose
+/// * `kbytes` - Number of kilobytes to return from the end of the log
+/// * `follow` - Keep sending the log until the compose has finished
+/// * `queue` - The compose queue
 ///
 /// # Response
 ///
-/// * 'Unimplemented' string
+/// * The end of the compose's log, one JSON object per line, sent using chunked encoding.
 ///
-/// This means that it will be implemented eventually, and is a valid path.
+/// When `?follow=true` is passed the response stays open, sending new log lines as they are
+/// written, until the compose is FINISHED or FAILED. This can be used to tail a running compose.
 ///
-/// # TODO
+/// The log is empty until the compose has started running.
 ///
-/// * Change it to a meaningful error code and JSON response
-/// * Pass it the id of a running compose
+/// # Errors
 ///
-#[get("/compose/log/<kbytes>")]
-#[allow(unused_variables)]
-pub fn compose_log(kbytes: usize) -> CORS<&'static str> {
-    #![cfg_attr(feature="strict", allow(unused_variables))]
-    CORS("Unimplemented")
-}
+/// * 404 if the id is unknown
+///
+/// # Examples
+///
+/// ```json
+/// {"msg":"Compose started","level":"INFO","ts":"2017-04-05T14:30:04.518203+00:00","id":"b27c5a7b-d1f6-4ef4-9b5b-6e3c1c8e9a4d","recipe":"http-server","type":"tar"}
+/// ```
+pub fn compose_log(id: &str, kbytes: u64, follow: bool, queue: State<ComposeQueue>) -> Result<CORS<Stream<ComposeLogReader>>, ApiError> {
+    info!("/compose/log/"; "id" => id, "kbytes" => kbytes, "follow" => follow);
+    let reader = try!(queue.log_reader(id, kbytes, follow));
 
+    Ok(CORS(Stream::chunked(reader, LOG_CHUNK_SIZE)))
+}
 
 // /compose/types
 
@@ -369,6 +554,7 @@ pub fn compose_log(kbytes: usize) -> CORS<&'static str> {
 #[derive(Serialize, Eq, PartialEq, Ord, PartialOrd)]
 pub struct ComposeTypes {
     name: String,
+    description: String,
     enabled: bool
 }
 
@@ -377,15 +563,16 @@ impl ComposeTypes {
     ///
     /// # Arguments
     ///
-    /// * `name` - Name of the output type. eg. 'iso'
+    /// * `name` - Name of the output type. eg. 'tar'
+    /// * `description` - Description of the output type
     /// * `enabled` - Whether or not that type is actually enabled.
     ///
     /// # Returns
     ///
     /// * A new [ComposeTypes](struct.ComposeTypes.html) struct
     ///
-    fn new<S: Into<String>>(name: S, enabled: bool) -> ComposeTypes {
-        ComposeTypes { name: name.into(), enabled: enabled }
+    fn new<S: Into<String>>(name: S, description: S, enabled: bool) -> ComposeTypes {
+        ComposeTypes { name: name.into(), description: description.into(), enabled: enabled }
     }
 }
 
@@ -395,11 +582,16 @@ pub struct ComposeTypesResponse {
     types: Vec<ComposeTypes>
 }
 
-/// Return the compose types and whether or not they are currently supported
+/// Return the compose types that are supported by the server
+///
+/// # Arguments
+///
+/// * `queue` - The compose queue, holding the registered exporters
 ///
 /// # Response
 ///
-/// * JSON response with 'types' set to a list of {'name':value, 'enabled': true|false} entries.
+/// * JSON response with 'types' set to a list of {'name':value, 'description': value,
+///   'enabled': true} entries, one for each registered exporter.
 ///
 ///
 /// # Examples
@@ -408,40 +600,20 @@ pub struct ComposeTypesResponse {
 /// {
 ///     "types": [
 ///         {
-///             "name": "iso",
+///             "name": "tar",
+///             "description": "Tar archive of the root filesystem",
 ///             "enabled": true
-///         },
-///         {
-///             "name": "disk-image",
-///             "enabled": false
-///         },
-///         {
-///             "name": "fs-image",
-///             "enabled": false
-///         },
-///         ...
 ///         }
 ///     ]
 /// }
 /// ```
 #[get("/compose/types")]
-pub fn compose_types() -> CORS<JSON<ComposeTypesResponse>> {
+pub fn compose_types(queue: State<ComposeQueue>) -> CORS<JSON<ComposeTypesResponse>> {
     info!("/compose/types");
-    let mut types = Vec::new();
-    types.push(ComposeTypes::new("ostree", true));
-    types.push(ComposeTypes::new("iso", true));
-    types.push(ComposeTypes::new("disk-image", false));
-    types.push(ComposeTypes::new("fs-image", false));
-    types.push(ComposeTypes::new("ami", true));
-    types.push(ComposeTypes::new("tar", false));
-    types.push(ComposeTypes::new("live-pxe", false));
-    types.push(ComposeTypes::new("live-ostree", false));
-    types.push(ComposeTypes::new("oci", false));
-    types.push(ComposeTypes::new("vagrant", false));
-    types.push(ComposeTypes::new("qcow2", true));
-    types.push(ComposeTypes::new("vmdk", false));
-    types.push(ComposeTypes::new("vhdx", true));
-    types.sort();
+    let types = queue.types()
+                     .into_iter()
+                     .map(|(name, description)| ComposeTypes::new(name, description, true))
+                     .collect();
 
     CORS(JSON(ComposeTypesResponse { types: types }))
 }
@@ -463,7 +635,7 @@ pub struct ProjectsResponse {
 /// This calls [projects_list](fn.projects_list.html) with the optional `offset` and/or `limit`
 /// values.
 #[get("/projects/list?<filter>")]
-pub fn projects_list_filter(filter: Filter, db: State<DBPool>) -> CORS<JSON<ProjectsResponse>> {
+pub fn projects_list_filter(filter: Filter, db: State<DBPool>) -> Result<CORS<JSON<ProjectsResponse>>, ApiError> {
     projects_list(db, filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT))
 }
 
@@ -472,7 +644,7 @@ pub fn projects_list_filter(filter: Filter, db: State<DBPool>) -> CORS<JSON<Proj
 ///
 /// This calls [projects_list](fn.projects_list.html) with the default `offset` and `limit` values.
 #[get("/projects/list", rank=2)]
-pub fn projects_list_default(db: State<DBPool>) -> CORS<JSON<ProjectsResponse>> {
+pub fn projects_list_default(db: State<DBPool>) -> Result<CORS<JSON<ProjectsResponse>>, ApiError> {
     projects_list(db, OFFSET, LIMIT)
 }
 
@@ -513,19 +685,16 @@ pub fn projects_list_default(db: State<DBPool>) -> CORS<JSON<ProjectsResponse>>
 /// }
 /// ```
 ///
-pub fn projects_list(db: State<DBPool>, offset: i64, limit: i64) -> CORS<JSON<ProjectsResponse>> {
+pub fn projects_list(db: State<DBPool>, offset: i64, limit: i64) -> Result<CORS<JSON<ProjectsResponse>>, ApiError> {
     info!("/projects/list"; "offset" => offset, "limit" => limit);
-    let (total, projects) = match get_projects_name(&db.conn(), "*", offset, limit) {
-        Ok((total, projects)) => (total, projects),
-        Err(_) => (0, vec![])
-    };
+    let (total, projects) = try!(get_projects_name(&db.conn(), "*", offset, limit));
 
-    CORS(JSON(ProjectsResponse {
+    Ok(CORS(JSON(ProjectsResponse {
             projects: projects,
             offset:   offset,
             limit:    limit,
             total:    total
-    }))
+    })))
 }
 
 
@@ -600,13 +769,13 @@ pub struct ProjectsInfoResponse {
 /// ```
 ///
 #[get("/projects/info/<projects>")]
-pub fn projects_info(projects: &str, db: State<DBPool>) -> CORS<JSON<ProjectsInfoResponse>> {
+pub fn projects_info(projects: &str, db: State<DBPool>) -> Result<CORS<JSON<ProjectsInfoResponse>>, ApiError> {
     info!("/projects/info/"; "projects" => projects);
     let projects: Vec<&str> = projects.split(',').collect();
-    let result = get_projects_details(&db.conn(), &projects);
-    CORS(JSON(ProjectsInfoResponse {
-            projects: result.unwrap_or_default(),
-    }))
+    let result = try!(get_projects_details(&db.conn(), &projects));
+    Ok(CORS(JSON(ProjectsInfoResponse {
+            projects: result,
+    })))
 }
 
 
@@ -622,6 +791,7 @@ pub struct ProjectsDepsolveResponse {
 /// # Arguments
 ///
 /// * `projects` - Comma separated list of project names
+/// * `arch` - Optional comma separated list of package architectures, eg. `?arch=aarch64`
 /// * `db` - Database pool
 ///
 /// # Response
@@ -653,59 +823,104 @@ pub struct ProjectsDepsolveResponse {
 ///     ]
 /// }
 /// ```
-#[get("/projects/depsolve/<projects>")]
-pub fn projects_depsolve(projects: &str, db: State<DBPool>) -> CORS<JSON<ProjectsDepsolveResponse>> {
-    info!("/projects/depsolve/"; "projects" => projects);
-    let projects: Vec<String> = projects.split(',').map(String::from).collect();
+///
+/// # Errors
+///
+/// * 400 if the projects cannot be depsolved, with the reason in the error message
+///
+#[get("/projects/depsolve/<projects>?<arch>")]
+pub fn projects_depsolve_arch(projects: &str, arch: Arch, db: State<DBPool>, policy: State<SolverPolicy>) -> Result<CORS<JSON<ProjectsDepsolveResponse>>, ApiError> {
+    projects_depsolve(projects, Some(arch.arches()), db, policy)
+}
+
+/// Default handler for `/projects/depsolve`, using the server's architectures
+#[get("/projects/depsolve/<projects>", rank=2)]
+pub fn projects_depsolve_default(projects: &str, db: State<DBPool>, policy: State<SolverPolicy>) -> Result<CORS<JSON<ProjectsDepsolveResponse>>, ApiError> {
+    projects_depsolve(projects, None, db, policy)
+}
+
+fn projects_depsolve(projects: &str, arches: Option<Vec<String>>, db: State<DBPool>, policy: State<SolverPolicy>) -> Result<CORS<JSON<ProjectsDepsolveResponse>>, ApiError> {
+    info!("/projects/depsolve/"; "projects" => projects, "arches" => format!("{:?}", arches));
+    let projects: Vec<PackageSpec> = projects.split(',').map(PackageSpec::from).collect();
 
-    let pkg_nevras = depsolve_helper(&db.conn(), &projects);
+    let policy = arch_policy(&policy, arches);
+    let pkg_nevras = try!(depsolve_helper(&db.conn(), &policy, &projects, &[]));
 
-    CORS(JSON(ProjectsDepsolveResponse {
+    Ok(CORS(JSON(ProjectsDepsolveResponse {
         projects: pkg_nevras
-    }))
+    })))
  }
 
-fn depsolve_helper(conn: &Connection, projects: &[String]) -> Vec<PackageNEVRA> {
+/// Return a copy of the policy that selects the architectures, or the policy itself if there are none
+fn arch_policy(policy: &SolverPolicy, arches: Option<Vec<String>>) -> SolverPolicy {
+    let mut policy = policy.clone();
+    match arches {
+        Some(ref arches) if !arches.is_empty() => policy.arches = arches.clone(),
+        _ => ()
+    }
+    policy
+}
+
+/// Depsolve the projects, without installing any of the packages named by `exclude`
+fn depsolve_helper(conn: &Connection, policy: &SolverPolicy, projects: &[PackageSpec], exclude: &[String]) -> Result<Vec<PackageNEVRA>, DepError> {
     // depclose the given projects into a big ol' depexpr
-    let depexpr = match close_dependencies(conn, &[String::from("x86_64")], projects) {
+    let depexpr = match close_dependencies(conn, &policy.arches, projects) {
         Ok(d) => d,
         Err(e) => {
-            error!("close_dependencies"; "projects" => format!("{:?}", projects), "error" => e);
-            return vec![];
+            error!("close_dependencies"; "projects" => format!("{:?}", projects), "error" => e.to_string());
+            return Err(e);
         }
     };
 
     // Wrap the returned depexpression in the crud it needs
     let mut exprs = vec![depexpr];
+    exprs.extend(try!(exclude_packages(conn, exclude)));
 
-    match solve_dependencies(conn, &mut exprs) {
+    match solve_dependencies(conn, policy, &mut exprs) {
         Ok(ids) => {
             let mut nevras = pkg_nevra_groups_vec(conn, &ids);
             nevras.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-            nevras
+            Ok(nevras)
         },
         Err(e) => {
-            error!("Error depsolving"; "pkgs" => format!("{:?}", projects), "error" => e);
-            return vec![];
+            error!("Error depsolving"; "pkgs" => format!("{:?}", projects), "error" => e.to_string());
+            Err(e)
         }
     }
 }
 
 /// Depsolve the recipe and return the list of package versions
 ///
-/// Return a tuple of the Recipe and the package NEVRAs if all goes well
-fn  depsolve_recipe(db: &State<DBPool>, repo: &State<RecipeRepo>, name: &str) -> Result<(Recipe, Vec<PackageNEVRA>), recipe::RecipeError> {
-    let recipe = try!(recipe::read(&repo.repo(), name, "master", None));
+/// Return a tuple of the Recipe and the package NEVRAs, or the reason the packages could not be
+/// depsolved, if the recipe can be read.
+///
+/// The packages are selected for `arches` if it is set, then for the recipe's `arch` list, and then
+/// for the server's default architectures. The recipe's package groups are expanded using `comps`,
+/// and any version of their packages may be selected. The included recipes are flattened into
+/// the recipe first. The packages in the recipe's `exclude` list are never selected.
+fn  depsolve_recipe(db: &State<DBPool>, policy: &State<SolverPolicy>, comps: &State<Comps>, repo: &State<RecipeRepo>, name: &str, branch: &RecipeBranch, arches: Option<Vec<String>>) -> Result<(Recipe, Result<Vec<PackageNEVRA>, DepError>), recipe::RecipeError> {
+    let recipe = {
+        let repo = repo.repo();
+        try!(recipe::read_flattened(&repo, name, recipe::branch_or_master(&repo, branch.as_str()), None))
+    };
+    let policy = arch_policy(&arch_policy(policy, Some(recipe.arch.clone())), arches);
 
+    // Only select the versions of the modules and packages allowed by the recipe
     let mut projects = Vec::new();
-    projects.extend(recipe.clone().modules.iter().map(|m| m.name.clone()));
-    projects.extend(recipe.clone().packages.iter().map(|p| p.name.clone()));
+    projects.extend(recipe.modules.iter().map(|m| PackageSpec::new(&m.name, m.version.as_ref().map(String::as_str))));
+    projects.extend(recipe.packages.iter().map(|p| PackageSpec::new(&p.name, p.version.as_ref().map(String::as_str))));
+
+    let conn = db.conn();
+    match comps.expand(&conn, &recipe.group_names()) {
+        Ok(packages) => projects.extend(packages.iter().map(|p| PackageSpec::from(p.as_str()))),
+        Err(e) => return Ok((recipe, Err(DepError::from(e))))
+    }
     projects.sort();
     projects.dedup();
 
     debug!("depsolve_recipe"; "projs" => format!("{:?}", projects));
     // deps for the whole recipe
-    let pkg_nevras = depsolve_helper(&db.conn(), &projects);
+    let pkg_nevras = depsolve_helper(&conn, &policy, &projects, &recipe.exclude);
     Ok((recipe, pkg_nevras))
 }
 
@@ -757,8 +972,13 @@ fn freeze_recipe(recipe: &Recipe, pkg_nevras: &[PackageNEVRA]) -> Recipe {
         name:        recipe.name.clone(),
         description: recipe.description.clone(),
         version:     recipe.version.clone(),
+        arch:        recipe.arch.clone(),
+        include:     recipe.include.clone(),
         modules:     modules,
-        packages:    packages
+        packages:    packages,
+        exclude:     recipe.exclude.clone(),
+        groups:      recipe.groups.clone(),
+        customizations: recipe.customizations.clone()
     }
 }
 
@@ -780,6 +1000,8 @@ pub struct ModuleInfoDeps {
 #[derive(Debug,Serialize)]
 pub struct ModulesInfoResponse {
     modules:  Vec<ModuleInfoDeps>,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    errors:   Vec<ApiErrorMsg>
 }
 
 /// Handler for `/modules/info/` without arguments.
@@ -796,6 +1018,10 @@ pub struct ModulesInfoResponse {
 ///
 /// * JSON response with a list of {'name': value, 'summary': value} entries inside {"modules":[]}
 ///
+/// If a module cannot be depsolved its dependencies are empty, and the reason is added to an
+/// `errors` list like `{"id": "DepsolveError", "msg": "httpd: ..."}`. Errors looking up a
+/// module are also in the `errors` list.
+///
 /// # Panics
 ///
 /// * Failure to get a database connection
@@ -835,15 +1061,19 @@ pub struct ModulesInfoResponse {
 /// ```
 ///
 #[get("/modules/info/<modules>")]
-pub fn modules_info(modules: &str, db: State<DBPool>) -> CORS<JSON<ModulesInfoResponse>> {
+pub fn modules_info(modules: &str, db: State<DBPool>, policy: State<SolverPolicy>) -> CORS<JSON<ModulesInfoResponse>> {
     info!("/modules/info/"; "modules" => modules);
     let modules: Vec<String> = modules.split(',').map(String::from).collect();
 
     let mut result = Vec::new();
+    let mut errors = Vec::new();
     for m in modules {
         match get_projects_name(&db.conn(), &m, 0, i64::max_value()) {
             Ok((1, p)) => {
-                let deps = depsolve_helper(&db.conn(), &[m]);
+                let deps = depsolve_helper(&db.conn(), &policy, &[PackageSpec::from(m.as_str())], &[]).unwrap_or_else(|e| {
+                    errors.push(named_error_msg(&m, e));
+                    Vec::new()
+                });
                 result.push(ModuleInfoDeps {
                     name:         p[0].name.clone(),
                     summary:      p[0].summary.clone(),
@@ -856,13 +1086,15 @@ pub fn modules_info(modules: &str, db: State<DBPool>) -> CORS<JSON<ModulesInfoRe
             //Ok((0,_)) => {}
             Ok((_,_)) => {}
             Err(e) => {
-                error!("Error looking up module info"; "module" => m, "error" => format!("{:?}", e));
+                error!("Error looking up module info"; "module" => m.as_str(), "error" => format!("{:?}", e));
+                errors.push(named_error_msg(&m, e));
             }
         }
     }
 
     CORS(JSON(ModulesInfoResponse {
             modules: result,
+            errors:  errors
     }))
 }
 
@@ -882,7 +1114,7 @@ pub struct ModulesListResponse {
 /// This calls [modules_list](fn.modules_list.html) with the optional `offset` and/or `limit`
 /// values.
 #[get("/modules/list/<modules>?<filter>")]
-pub fn modules_list_filter(modules: &str, filter: Filter, db: State<DBPool>) -> CORS<JSON<ModulesListResponse>> {
+pub fn modules_list_filter(modules: &str, filter: Filter, db: State<DBPool>) -> Result<CORS<JSON<ModulesListResponse>>, ApiError> {
     modules_list(modules, db, filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT))
 }
 
@@ -890,7 +1122,7 @@ pub fn modules_list_filter(modules: &str, filter: Filter, db: State<DBPool>) ->
 ///
 /// This calls [modules_list](fn.modules_list.html) with the default `offset` and `limit` values.
 #[get("/modules/list/<modules>", rank=2)]
-pub fn modules_list_default(modules: &str, db: State<DBPool>) -> CORS<JSON<ModulesListResponse>> {
+pub fn modules_list_default(modules: &str, db: State<DBPool>) -> Result<CORS<JSON<ModulesListResponse>>, ApiError> {
     modules_list(modules, db, OFFSET, LIMIT)
 }
 
@@ -899,7 +1131,7 @@ pub fn modules_list_default(modules: &str, db: State<DBPool>) -> CORS<JSON<Modul
 /// This calls [modules_list](fn.modules_list.html) with a wildcard name, `*`, and the optional
 /// `offset` and/or `limit` values.
 #[get("/modules/list?<filter>")]
-pub fn modules_list_noargs_filter(filter: Filter, db: State<DBPool>) -> CORS<JSON<ModulesListResponse>> {
+pub fn modules_list_noargs_filter(filter: Filter, db: State<DBPool>) -> Result<CORS<JSON<ModulesListResponse>>, ApiError> {
     modules_list("*", db, filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT))
 }
 
@@ -908,7 +1140,7 @@ pub fn modules_list_noargs_filter(filter: Filter, db: State<DBPool>) -> CORS<JSO
 /// This calls [modules_list](fn.modules_list.html) with a wildcard name, `*`, and the default
 /// `offset` and `limit` values.
 #[get("/modules/list", rank=2)]
-pub fn modules_list_noargs_default(db: State<DBPool>) -> CORS<JSON<ModulesListResponse>> {
+pub fn modules_list_noargs_default(db: State<DBPool>) -> Result<CORS<JSON<ModulesListResponse>>, ApiError> {
     modules_list("*", db, OFFSET, LIMIT)
 }
 
@@ -953,7 +1185,7 @@ pub fn modules_list_noargs_default(db: State<DBPool>) -> CORS<JSON<ModulesListRe
 /// }
 /// ```
 ///
-pub fn modules_list(modules: &str, db: State<DBPool>, offset: i64, limit: i64) -> CORS<JSON<ModulesListResponse>> {
+pub fn modules_list(modules: &str, db: State<DBPool>, offset: i64, limit: i64) -> Result<CORS<JSON<ModulesListResponse>>, ApiError> {
     info!("/modules/list/"; "modules" => modules, "offset" => offset, "limit" => limit);
 
     // FIXME What's the right way to do this?
@@ -965,8 +1197,7 @@ pub fn modules_list(modules: &str, db: State<DBPool>, offset: i64, limit: i64) -
                      };
 
     let groups: Vec<&str> = search_str.split(',').collect();
-    let mut result = get_groups_vec(&db.conn(), &groups)
-                     .unwrap_or_default();
+    let mut result = try!(get_groups_vec(&db.conn(), &groups));
     // Sort by case-insensitive name
     result.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
     // Groups includes the unique id, so dedupe using the name.
@@ -974,12 +1205,87 @@ pub fn modules_list(modules: &str, db: State<DBPool>, offset: i64, limit: i64) -
     let total = result.len() as i64;
 
     result = result.into_iter().skip(offset as usize).take(limit as usize).collect();
-    CORS(JSON(ModulesListResponse {
+    Ok(CORS(JSON(ModulesListResponse {
             modules: result,
             offset:  offset,
             limit:   limit,
             total:   total
-    }))
+    })))
+}
+
+
+// /modules/whatrequires/<module>
+
+/// Hold the JSON response for /modules/whatrequires/
+#[derive(Debug,Serialize)]
+pub struct ModulesWhatRequiresResponse {
+    name:      String,
+    recursive: bool,
+    modules:   Vec<PackageNEVRA>
+}
+
+/// Return the modules that require a module
+///
+/// # Arguments
+///
+/// * `module` - The name of the required module
+/// * `recursive` - `?recursive=true` also returns the modules that require those modules, and so on
+/// * `db` - Database pool
+///
+/// # Response
+///
+/// * JSON response with the NEVRA of each requiring build inside {"modules":[]}, sorted by name
+///
+/// A module requires another when one of its requirements is satisfied by a provide, or a file,
+/// of any build of the required module. Builds of the required module are not included, even
+/// when a recursive query leads back to them.
+///
+/// # Examples
+///
+/// ```json
+/// {
+///     "name": "bash",
+///     "recursive": false,
+///     "modules": [
+///         {
+///             "name": "dracut",
+///             "epoch": 0,
+///             "version": "033",
+///             "release": "502.el7",
+///             "arch": "x86_64"
+///         },
+///         {
+///             "name": "initscripts",
+///             "epoch": 0,
+///             "version": "9.49.39",
+///             "release": "1.el7",
+///             "arch": "x86_64"
+///         }
+///     ]
+/// }
+/// ```
+///
+/// # Errors
+///
+/// * 404 if there is no module with that name
+///
+#[get("/modules/whatrequires/<module>")]
+pub fn modules_whatrequires(module: &str, recursive: RecursiveMode, db: State<DBPool>) -> Result<CORS<JSON<ModulesWhatRequiresResponse>>, ApiError> {
+    info!("/modules/whatrequires/"; "module" => module, "recursive" => recursive.is_recursive());
+
+    let conn = db.conn();
+    let group_ids = try!(get_groups_by_name(&conn, module, "rpm"));
+    if group_ids.is_empty() {
+        return Err(ApiError::NotFound(format!("Unknown module {}", module)));
+    }
+
+    let groups = try!(whatrequires(&conn, &group_ids, recursive.is_recursive()));
+    let ids: Vec<i64> = groups.iter().map(|group| group.id).collect();
+    Ok(CORS(JSON(ModulesWhatRequiresResponse {
+            name:      module.to_string(),
+            recursive: recursive.is_recursive(),
+            modules:   pkg_nevra_groups_vec(&conn, &ids)
+    })))
 }
 
 
@@ -1003,16 +1309,16 @@ pub struct RecipesListResponse {
 /// This calls [recipes_list](fn.recipes_list.html) with the optional `offset` and/or `limit`
 /// values.
 #[get("/recipes/list?<filter>")]
-pub fn recipes_list_filter(filter: Filter, repo: State<RecipeRepo>) -> CORS<JSON<RecipesListResponse>> {
-    recipes_list(filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT), repo)
+pub fn recipes_list_filter(filter: Filter, branch: RecipeBranch, repo: State<RecipeRepo>) -> Result<CORS<JSON<RecipesListResponse>>, ApiError> {
+    recipes_list(filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT), branch, repo)
 }
 
 /// Handler for `/recipes/list/` without arguments.
 ///
 /// This calls [recipes_list](fn.recipes_list.html) with the default `offset` and `limit` values.
 #[get("/recipes/list", rank=2)]
-pub fn recipes_list_default(repo: State<RecipeRepo>) -> CORS<JSON<RecipesListResponse>> {
-    recipes_list(OFFSET, LIMIT, repo)
+pub fn recipes_list_default(branch: RecipeBranch, repo: State<RecipeRepo>) -> Result<CORS<JSON<RecipesListResponse>>, ApiError> {
+    recipes_list(OFFSET, LIMIT, branch, repo)
 }
 
 /// Return the list of available Recipes
@@ -1048,22 +1354,22 @@ pub fn recipes_list_default(repo: State<RecipeRepo>) -> CORS<JSON<RecipesListRes
 /// }
 /// ```
 ///
-pub fn recipes_list(offset: i64, limit: i64, repo: State<RecipeRepo>) -> CORS<JSON<RecipesListResponse>> {
-    info!("/recipes/list"; "offset" => offset, "limit" => limit);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_list(offset: i64, limit: i64, branch: RecipeBranch, repo: State<RecipeRepo>) -> Result<CORS<JSON<RecipesListResponse>>, ApiError> {
+    info!("/recipes/list"; "offset" => offset, "limit" => limit, "branch" => branch.as_str());
 
-    let mut result = recipe::list(&repo.repo(), "master", None).unwrap_or_default();
+    let repo = repo.repo();
+    let mut result = try!(recipe::list(&repo, recipe::branch_or_master(&repo, branch.as_str()), None));
     // Sort by case-insensitive name
     result.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()));
     result.dedup();
     let total = result.len() as i64;
     result.truncate(limit as usize);
-    CORS(JSON(RecipesListResponse {
+    Ok(CORS(JSON(RecipesListResponse {
             recipes: result,
             offset:  offset,
             limit:   limit,
             total:   total
-    }))
+    })))
 }
 
 
@@ -1098,6 +1404,8 @@ impl WorkspaceChanges {
 pub struct RecipesInfoResponse {
     changes: Vec<WorkspaceChanges>,
     recipes: Vec<Recipe>,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    errors:  Vec<ApiErrorMsg>
 }
 
 
@@ -1107,11 +1415,15 @@ pub struct RecipesInfoResponse {
 /// # Arguments
 ///
 /// * `recipe_names` - Comma separated list of recipe names to return
+/// * `flatten` - With `?flatten=true` the included recipes are merged into each recipe
 ///
 /// # Response
 ///
 /// * JSON response with recipe contents, using the recipe name(s) as keys
 ///
+/// The `changes` are always for the recipe as it was written. If a recipe's includes cannot be
+/// flattened the error is added to the `errors` list.
+///
 /// # Panics
 ///
 /// * Failure to serialize the response
@@ -1174,39 +1486,53 @@ pub struct RecipesInfoResponse {
 /// ```
 ///
 #[get("/recipes/info/<recipe_names>")]
-pub fn recipes_info(recipe_names: &str, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesInfoResponse>> {
-    info!("/recipes/info/ (JSON)"; "recipe_names" => recipe_names);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_info(recipe_names: &str, branch: RecipeBranch, flatten: FlattenMode, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesInfoResponse>> {
+    info!("/recipes/info/ (JSON)"; "recipe_names" => recipe_names, "flatten" => flatten.is_flat());
 
     let repo = repo_state.repo();
+    let read_branch = recipe::branch_or_master(&repo, branch.as_str());
     let mut result = Vec::new();
     let mut changes = Vec::new();
+    let mut errors = Vec::new();
     for name in recipe_names.split(',') {
-        // NOTE This is a kludge to squash Result errors until this route handles them properly
         // Reading a recipe may fail, if it does it may only exist in the workspace, so check
-        // there. If neither succeeds then nothing is pushed into the result and changes Vecs
-        let _: Result<(), String> = recipe::read(&repo, name, "master", None).map(|recipe| {
-            debug!("recipes_info"; "recipe" => format!("{:?}", recipe));
-            let ws_recipe = match read_from_workspace(&workspace_dir(&repo, "master"), name) {
-                Some(r) => r,
-                None => recipe.clone()
-            };
-            let changed = recipe != ws_recipe;
-            changes.push(WorkspaceChanges::new(name, changed));
-            debug!("workspace vs. git"; "changed" => format!("{:?}", changed));
-            result.push(ws_recipe);
-        }).or_else(|_| {
-            // Reading the recipe from git failed. Check the workspace.
-            match read_from_workspace(&workspace_dir(&repo, "master"), name) {
-                Some(ws_recipe) => {
-                    debug!("workspace recipe"; "name" => name, "recipe" => format!("{:?}", ws_recipe));
-                    changes.push(WorkspaceChanges::new(name, true));
-                    result.push(ws_recipe);
-                    Ok(())
-                },
-                None => Ok(())
+        // there. If neither succeeds then the error is pushed into the errors Vec
+        let ws_recipe = match recipe::read(&repo, name, read_branch, None) {
+            Ok(recipe) => {
+                debug!("recipes_info"; "recipe" => format!("{:?}", recipe));
+                let ws_recipe = match read_from_workspace(&workspace_dir(&repo, branch.as_str()), name) {
+                    Some(r) => r,
+                    None => recipe.clone()
+                };
+                let changed = recipe != ws_recipe;
+                changes.push(WorkspaceChanges::new(name, changed));
+                debug!("workspace vs. git"; "changed" => format!("{:?}", changed));
+                ws_recipe
+            },
+            Err(e) => {
+                // Reading the recipe from git failed. Check the workspace.
+                match read_from_workspace(&workspace_dir(&repo, branch.as_str()), name) {
+                    Some(ws_recipe) => {
+                        debug!("workspace recipe"; "name" => name, "recipe" => format!("{:?}", ws_recipe));
+                        changes.push(WorkspaceChanges::new(name, true));
+                        ws_recipe
+                    },
+                    None => {
+                        errors.push(named_error_msg(name, e));
+                        continue;
+                    }
+                }
             }
-        });
+        };
+
+        if !flatten.is_flat() {
+            result.push(ws_recipe);
+            continue;
+        }
+        match recipe::flatten(&repo, &ws_recipe, read_branch, None) {
+            Ok(flat_recipe) => result.push(flat_recipe),
+            Err(e) => errors.push(named_error_msg(name, e))
+        }
     }
     // Sort recipes by case-insensitive name
     result.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
@@ -1219,32 +1545,47 @@ pub fn recipes_info(recipe_names: &str, repo_state: State<RecipeRepo>) -> CORS<J
     CORS(JSON(RecipesInfoResponse {
         changes: changes,
         recipes: result,
+        errors:  errors
     }))
 }
 
+
+/// Return the error for one of the names in a request, for the errors list of a response
+fn named_error_msg<E: Into<ApiError>>(name: &str, err: E) -> ApiErrorMsg {
+    let err = err.into();
+    ApiErrorMsg {
+        id:  err.id().to_string(),
+        msg: format!("{}: {}", name, err)
+    }
+}
+
 /// Return the requested recipe as TOML
 /// Note that this only supports 1 recipe at a time
 ///
-/// The request should be: `/recipes/info/<recipe_name>?format=toml`
+/// The request should be: `/recipes/info/<recipe_name>?format=toml`, add `&flatten=true` to
+/// merge the included recipes into it.
 ///
 /// NOTE this is accomplished this way because Rocket doesn't have a way to specify a
 /// custom Content-Type for GET requests.
 ///
 /// TODO Figure out how to add custom content types
 #[get("/recipes/info/<recipe_name>?<format>", rank=3)]
-pub fn recipes_info_toml(recipe_name: &str, format: Format, repo_state: State<RecipeRepo>) -> Result<CORS<TOML<Recipe>>, ApiError> {
+pub fn recipes_info_toml(recipe_name: &str, format: Format, branch: RecipeBranch, flatten: FlattenMode, repo_state: State<RecipeRepo>) -> Result<CORS<TOML<Recipe>>, ApiError> {
     info!("/recipes/info/ (TOML)"; "recipe_name" => recipe_name, "format" => format!("{:?}", format));
-    // TODO Get the user's branch name. Use master for now.
 
     let repo = repo_state.repo();
-    let recipe = try!(recipe::read(&repo, recipe_name, "master", None));
-    let ws_recipe = match read_from_workspace(&workspace_dir(&repo, "master"), recipe_name) {
+    let read_branch = recipe::branch_or_master(&repo, branch.as_str());
+    let recipe = try!(recipe::read(&repo, recipe_name, read_branch, None));
+    let ws_recipe = match read_from_workspace(&workspace_dir(&repo, branch.as_str()), recipe_name) {
         Some(r) => r,
         None => recipe.clone()
     };
     let changed = recipe == ws_recipe;
     debug!("workspace vs. git"; "changed" => format!("{:?}", changed));
 
+    if flatten.is_flat() {
+        return Ok(CORS(TOML(try!(recipe::flatten(&repo, &ws_recipe, read_branch, None)))));
+    }
     Ok(CORS(TOML(ws_recipe)))
 }
 
@@ -1254,6 +1595,8 @@ pub fn recipes_info_toml(recipe_name: &str, format: Format, repo_state: State<Re
 #[derive(Debug, Serialize)]
 pub struct RecipesFreezeResponse {
     recipes: Vec<Recipe>,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    errors:  Vec<ApiErrorMsg>
 }
 
 
@@ -1263,6 +1606,7 @@ pub struct RecipesFreezeResponse {
 /// # Arguments
 ///
 /// * `recipe_names` - Comma separated list of recipe names to return
+/// * `arch` - Optional comma separated list of package architectures, eg. `?arch=aarch64`
 ///
 /// # Response
 ///
@@ -1326,43 +1670,79 @@ pub struct RecipesFreezeResponse {
 /// }
 /// ```
 ///
-#[get("/recipes/freeze/<recipe_names>")]
-pub fn recipes_freeze(recipe_names: &str, db: State<DBPool>, repo: State<RecipeRepo>) -> CORS<JSON<RecipesFreezeResponse>> {
-    info!("/recipes/freeze/ (JSON)"; "recipe_names" => recipe_names);
-    // TODO Get the user's branch name. Use master for now.
+#[get("/recipes/freeze/<recipe_names>?<arch>")]
+pub fn recipes_freeze_arch(recipe_names: &str, arch: Arch, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesFreezeResponse>> {
+    recipes_freeze(recipe_names, Some(arch.arches()), db, policy, comps, branch, repo)
+}
+
+/// Default handler for `/recipes/freeze`, using the recipe's or the server's architectures
+#[get("/recipes/freeze/<recipe_names>", rank=2)]
+pub fn recipes_freeze_default(recipe_names: &str, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesFreezeResponse>> {
+    recipes_freeze(recipe_names, None, db, policy, comps, branch, repo)
+}
+
+fn recipes_freeze(recipe_names: &str, arches: Option<Vec<String>>, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesFreezeResponse>> {
+    info!("/recipes/freeze/ (JSON)"; "recipe_names" => recipe_names, "arches" => format!("{:?}", arches));
 
     let mut result = Vec::new();
+    let mut errors = Vec::new();
     for name in recipe_names.split(',') {
-        let _ = depsolve_recipe(&db, &repo, name).and_then(|(recipe, pkg_nevras)| {
-            let new_recipe = freeze_recipe(&recipe, &pkg_nevras);
-            result.push(new_recipe);
-            Ok((recipe, pkg_nevras))
-        });
+        match depsolve_recipe(&db, &policy, &comps, &repo, name, &branch, arches.clone()) {
+            Ok((recipe, pkg_nevras)) => {
+                // Return the recipe without frozen versions, and the reason, if it cannot be depsolved
+                let pkg_nevras = pkg_nevras.unwrap_or_else(|e| {
+                    errors.push(named_error_msg(name, e));
+                    Vec::new()
+                });
+                result.push(freeze_recipe(&recipe, &pkg_nevras));
+            },
+            Err(e) => errors.push(named_error_msg(name, e))
+        }
     }
     CORS(JSON(RecipesFreezeResponse {
-        recipes: result
+        recipes: result,
+        errors:  errors
     }))
 }
 
 /// Return the requested recipe as TOML
 /// Note that this only supports 1 recipe at a time
 ///
-/// The request should be: `/recipes/freeze/<recipe_name>?format=toml`
+/// The request should be: `/recipes/freeze/<recipe_name>?format=toml`, add `&arch=aarch64` to
+/// freeze the versions for other package architectures.
 ///
 /// NOTE this is accomplished this way because Rocket doesn't have a way to specify a
 /// custom Content-Type for GET requests.
 ///
 /// TODO Figure out how to add custom content types
+///
+/// # Errors
+///
+/// * 400 if the recipe cannot be depsolved
+/// * 404 if the recipe cannot be read
+///
 #[get("/recipes/freeze/<recipe_name>?<format>", rank=3)]
-pub fn recipes_freeze_toml(recipe_name: &str, format: Format, db: State<DBPool>, repo: State<RecipeRepo>) -> CORS<TOML<Recipe>> {
+pub fn recipes_freeze_toml(recipe_name: &str, format: Format, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> Result<CORS<TOML<Recipe>>, ApiError> {
     info!("/recipes/freeze/ (TOML)"; "recipe_name" => recipe_name, "format" => format!("{:?}", format));
-    // TODO Get the user's branch name. Use master for now.
+    recipes_freeze_toml_arches(recipe_name, None, db, policy, comps, branch, repo)
+}
+
+/// Handler for `/recipes/freeze/<recipe_name>?format=toml&arch=<arches>`
+///
+/// Like [recipes_freeze_toml](fn.recipes_freeze_toml.html), using the comma separated list of
+/// architectures instead of the recipe's or the server's.
+#[get("/recipes/freeze/<recipe_name>?<format>", rank=4)]
+pub fn recipes_freeze_toml_arch(recipe_name: &str, format: FormatArch, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> Result<CORS<TOML<Recipe>>, ApiError> {
+    info!("/recipes/freeze/ (TOML)"; "recipe_name" => recipe_name, "format" => format!("{:?}", format));
+    recipes_freeze_toml_arches(recipe_name, Some(format.arches()), db, policy, comps, branch, repo)
+}
 
+fn recipes_freeze_toml_arches(recipe_name: &str, arches: Option<Vec<String>>, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> Result<CORS<TOML<Recipe>>, ApiError> {
     // TODO Error handling for format requests other than toml
-    let (recipe, pkg_nevras) = depsolve_recipe(&db, &repo, recipe_name).unwrap();
-    let new_recipe = freeze_recipe(&recipe, &pkg_nevras);
+    let (recipe, pkg_nevras) = try!(depsolve_recipe(&db, &policy, &comps, &repo, recipe_name, &branch, arches));
+    let new_recipe = freeze_recipe(&recipe, &try!(pkg_nevras));
 
-    CORS(TOML(new_recipe))
+    Ok(CORS(TOML(new_recipe)))
 }
 
 
@@ -1371,7 +1751,9 @@ pub fn recipes_freeze_toml(recipe_name: &str, format: Format, db: State<DBPool>,
 pub struct RecipesChangesResponse {
     recipes: Vec<RecipeCommitInfo>,
     offset:  i64,
-    limit:   i64
+    limit:   i64,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    errors:  Vec<ApiErrorMsg>
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
@@ -1386,16 +1768,16 @@ pub struct RecipeCommitInfo {
 /// This calls [recipes_changes](fn.recipes_changes.html) with the optional `offset` and/or `limit`
 /// values.
 #[get("/recipes/changes/<recipes>?<filter>")]
-pub fn recipes_changes_filter(recipes: &str, filter: Filter, repo: State<RecipeRepo>) -> CORS<JSON<RecipesChangesResponse>> {
-    recipes_changes(recipes, filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT), repo)
+pub fn recipes_changes_filter(recipes: &str, filter: Filter, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesChangesResponse>> {
+    recipes_changes(recipes, filter.offset.unwrap_or(OFFSET), filter.limit.unwrap_or(LIMIT), branch, repo)
 }
 
 /// Handler for `/recipes/changes/<recipes>`
 ///
 /// This calls [recipes_changes](fn.recipes_changes.html) with the default `offset` and `limit` values.
 #[get("/recipes/changes/<recipes>", rank=2)]
-pub fn recipes_changes_default(recipes: &str, repo: State<RecipeRepo>) -> CORS<JSON<RecipesChangesResponse>> {
-    recipes_changes(recipes, OFFSET, LIMIT, repo)
+pub fn recipes_changes_default(recipes: &str, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesChangesResponse>> {
+    recipes_changes(recipes, OFFSET, LIMIT, branch, repo)
 }
 
 /// Return the changes to a recipe or list of recipes
@@ -1462,13 +1844,15 @@ pub fn recipes_changes_default(recipes: &str, repo: State<RecipeRepo>) -> CORS<J
 /// }
 /// ```
 ///
-pub fn recipes_changes(recipe_names: &str, offset: i64, limit: i64, repo: State<RecipeRepo>) -> CORS<JSON<RecipesChangesResponse>> {
+pub fn recipes_changes(recipe_names: &str, offset: i64, limit: i64, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesChangesResponse>> {
     info!("/recipes/changes/ (JSON)"; "recipe_names" => recipe_names, "offset" => offset, "limit" => limit);
-    // TODO Get the user's branch name. Use master for now.
 
+    let repo = repo.repo();
+    let read_branch = recipe::branch_or_master(&repo, branch.as_str());
     let mut result = Vec::new();
+    let mut errors = Vec::new();
     for name in recipe_names.split(',') {
-        match recipe::commits(&repo.repo(), name, "master") {
+        match recipe::commits(&repo, name, read_branch) {
             Ok(mut commits) => {
                 let total = commits.len() as i64;
                 commits = commits.into_iter().skip(offset as usize).take(limit as usize).collect();
@@ -1480,6 +1864,7 @@ pub fn recipes_changes(recipe_names: &str, offset: i64, limit: i64, repo: State<
             },
             Err(e) => {
                 error!("Problem getting commits"; "recipe_name" => name, "error" => format!("{:?}", e));
+                errors.push(named_error_msg(name, e));
             }
         }
     }
@@ -1489,7 +1874,8 @@ pub fn recipes_changes(recipe_names: &str, offset: i64, limit: i64, repo: State<
     CORS(JSON(RecipesChangesResponse {
         recipes: result,
         offset:  offset,
-        limit:   limit
+        limit:   limit,
+        errors:  errors
     }))
 }
 
@@ -1606,50 +1992,40 @@ pub struct RecipeDiffInfo {
 /// }
 /// ```
 ///
+/// # Errors
+///
+/// * 404 if the recipe or one of the commits cannot be read
+///
 #[get("/recipes/diff/<recipe_name>/<from_commit>/<to_commit>")]
 pub fn recipes_diff(recipe_name: &str, from_commit: &str, to_commit: &str,
-                    repo_state: State<RecipeRepo>) -> CORS<JSON<RecipeDiff>> {
+                    branch: RecipeBranch, repo_state: State<RecipeRepo>) -> Result<CORS<JSON<RecipeDiff>>, ApiError> {
     info!("/recipes/diff/"; "recipe_name" => recipe_name,
                             "from_commit" => from_commit, "to_commit" => to_commit);
-    // TODO Get the user's branch name. Use master for now.
 
     let repo = repo_state.repo();
+    let read_branch = recipe::branch_or_master(&repo, branch.as_str());
     // Get the from_commit recipe
-    // TODO Need to add error handling so this can be a try!()
     let old_recipe = match from_commit {
-        "NEWEST" => recipe::read(&repo, recipe_name, "master", None).unwrap(),
-        commit => match recipe::read(&repo, recipe_name, "master", Some(commit)) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("recipes_diff"; "error" => format!("{:?}", e));
-                recipe::read(&repo, recipe_name, "master", None).unwrap()
-            }
-        }
+        "NEWEST" => try!(recipe::read(&repo, recipe_name, read_branch, None)),
+        commit => try!(recipe::read(&repo, recipe_name, read_branch, Some(commit)))
     };
 
     let new_recipe = match to_commit {
         "WORKSPACE" => {
-            match read_from_workspace(&workspace_dir(&repo, "master"), recipe_name) {
+            match read_from_workspace(&workspace_dir(&repo, branch.as_str()), recipe_name) {
                 Some(r) => r,
-                // TODO Need to add error handling so this can be a try!()
-                None => recipe::read(&repo, recipe_name, "master", None).unwrap()
+                None => try!(recipe::read(&repo, recipe_name, read_branch, None))
             }
         },
-        "NEWEST" => recipe::read(&repo, recipe_name, "master", None).unwrap(),
-        commit => match recipe::read(&repo, recipe_name, "master", Some(commit)) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("recipes_diff"; "error" => format!("{:?}", e));
-                recipe::read(&repo, recipe_name, "master", None).unwrap()
-            }
-        }
+        "NEWEST" => try!(recipe::read(&repo, recipe_name, read_branch, None)),
+        commit => try!(recipe::read(&repo, recipe_name, read_branch, Some(commit)))
     };
 
     let diff = recipe::diff(&old_recipe, &new_recipe);
 
-    CORS(JSON(RecipeDiff {
+    Ok(CORS(JSON(RecipeDiff {
         diff: diff
-    }))
+    })))
 }
 
 /// Hold the JSON response for /recipes/new/
@@ -1749,38 +2125,42 @@ pub fn options_recipes_new() -> CORS<&'static str> {
 ///     "status": true
 /// }
 /// ```
+///
+/// ## Strict mode
+///
+/// With `?strict=true` the recipe is checked the same way as [/recipes/validate](fn.recipes_validate_json.html)
+/// before it is saved.
+///
+/// # Errors
+///
+/// * 400 if the recipe name or version is not valid
+/// * 400 if a user's password is not a crypt(3) hash, with a PlaintextPassword error
+/// * 400 in strict mode if the recipe has problems, with an error for each one
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
+///
 #[post("/recipes/new", format="application/json", data="<recipe>")]
-pub fn recipes_new_json(recipe: JSON<Recipe>, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesNewResponse>> {
-    info!("/recipes/new/ (JSON)"; "recipe.name" => recipe.name);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_new_json(recipe: JSON<Recipe>, branch: RecipeBranch, strict: StrictMode, user: WriteUser, db: State<DBPool>, comps: State<Comps>, repo_state: State<RecipeRepo>) -> Result<CORS<JSON<RecipesNewResponse>>, ApiError> {
+    info!("/recipes/new/ (JSON)"; "recipe.name" => recipe.name, "strict" => strict.is_strict(), "user" => user.login());
 
-    let repo = repo_state.repo();
-    let mut status = match recipe::write(&repo, &recipe, "master", None) {
-        Ok(result) => result,
-        Err(e) => {
-            error!("recipes_new"; "recipe" => format!("{:?}", recipe), "error" => format!("{:?}", e));
-            false
-        }
-    };
+    if strict.is_strict() {
+        try!(check_recipe(&db.conn(), &comps, &recipe));
+    } else {
+        try!(check_passwords(&recipe));
+    }
 
-    if status {
-        // Read the latest commit, the version may have been changed so it could be different
-        let _ = recipe::read(&repo, &recipe.name, "master", None).map(|new_recipe| {
-            // Update the workspace copy, log any errors
-            match write_to_workspace(&workspace_dir(&repo, "master"), &new_recipe) {
-                Ok(_) => (),
-                Err(e) => {
-                    error!("recipes_new workspace"; "recipe" => format!("{:?}", recipe), "error" => format!("{:?}", e));
-                    status = false;
-                }
-            };
-        });
+    let repo = repo_state.repo();
+    if !try!(recipe::write(&repo, &recipe, branch.as_str(), None, &user.author())) {
+        return Err(ApiError::InternalServerError(format!("Unable to write recipe {}", recipe.name)));
     }
 
-    // TODO Return error information
-    CORS(JSON(RecipesNewResponse {
-            status: status
-    }))
+    // Read the latest commit, the version may have been changed so it could be different
+    let new_recipe = try!(recipe::read(&repo, &recipe.name, branch.as_str(), None));
+    // Update the workspace copy
+    try!(write_to_workspace(&workspace_dir(&repo, branch.as_str()), &new_recipe));
+
+    Ok(CORS(JSON(RecipesNewResponse {
+            status: true
+    })))
 }
 
 
@@ -1791,33 +2171,163 @@ pub fn recipes_new_json(recipe: JSON<Recipe>, repo_state: State<RecipeRepo>) ->
 ///
 /// eg. `curl -H "Content-Type: text/x-toml" -X POST --data-binary @nginx.toml http://API/URL`
 ///
+/// `?strict=true` works the same way as it does for [recipes_new_json](fn.recipes_new_json.html).
+///
 #[post("/recipes/new", data="<recipe>", rank=2)]
-pub fn recipes_new_toml(recipe: TOML<Recipe>, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesNewResponse>> {
-    info!("/recipes/new/ (TOML)"; "recipe.name" => recipe.name);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_new_toml(recipe: TOML<Recipe>, branch: RecipeBranch, strict: StrictMode, user: WriteUser, db: State<DBPool>, comps: State<Comps>, repo_state: State<RecipeRepo>) -> Result<CORS<JSON<RecipesNewResponse>>, ApiError> {
+    info!("/recipes/new/ (TOML)"; "recipe.name" => recipe.name, "strict" => strict.is_strict(), "user" => user.login());
+
+    if strict.is_strict() {
+        try!(check_recipe(&db.conn(), &comps, &recipe));
+    } else {
+        try!(check_passwords(&recipe));
+    }
 
     let repo = repo_state.repo();
-    let mut status = match recipe::write(&repo, &recipe, "master", None) {
-        Ok(result) => result,
-        Err(e) => {
-            error!("recipes_new_toml"; "recipe" => format!("{:?}", recipe), "error" => format!("{:?}", e));
-            false
-        }
-    };
+    if !try!(recipe::write(&repo, &recipe, branch.as_str(), None, &user.author())) {
+        return Err(ApiError::InternalServerError(format!("Unable to write recipe {}", recipe.name)));
+    }
 
-    // Update the workspace copy, log any errors
-    match write_to_workspace(&workspace_dir(&repo, "master"), &recipe) {
-        Ok(_) => (),
-        Err(e) => {
-            error!("recipes_new_toml workspace"; "recipe" => format!("{:?}", recipe), "error" => format!("{:?}", e));
-            status = false;
-        }
-    };
+    // Update the workspace copy
+    try!(write_to_workspace(&workspace_dir(&repo, branch.as_str()), &recipe));
 
-    // TODO Return error information
-    CORS(JSON(RecipesNewResponse {
-            status: status
-    }))
+    Ok(CORS(JSON(RecipesNewResponse {
+            status: true
+    })))
+}
+
+
+// Reject a recipe that has any problems
+fn check_recipe(conn: &Connection, comps: &Comps, recipe: &Recipe) -> Result<(), ApiError> {
+    let problems = try!(validate_recipe(conn, comps, recipe));
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::ValidationError(problems))
+    }
+}
+
+// Reject a recipe with a plaintext password, they must never be written to disk
+fn check_passwords(recipe: &Recipe) -> Result<(), ApiError> {
+    let problems = password_problems(recipe);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::ValidationError(problems))
+    }
+}
+
+
+/// Hold the JSON response for /recipes/validate
+#[derive(Debug, Serialize)]
+pub struct RecipesValidateResponse {
+    status: bool,
+    errors: Vec<ApiErrorMsg>
+}
+
+/// The CORS system 'protects' the client via an OPTIONS request to make sure it is allowed
+///
+/// This returns an empty response, with the CORS headers set by [CORS](struct.CORS.html).
+// Rocket has a collision with Diesel so uses route instead
+//#[options("/recipes/validate")]
+#[route(OPTIONS, "/recipes/validate")]
+pub fn options_recipes_validate() -> CORS<&'static str> {
+    CORS("")
+}
+
+/// Handler for `/recipes/validate`
+/// Check a Recipe for problems without saving it
+///
+/// # Arguments
+///
+/// * `recipe` - Recipe to check, in JSON format
+///
+/// # Response
+///
+/// * JSON response with "status" set to true if no problems were found, and an "errors" list
+///   with the id and a message for each problem.
+///
+/// The checks are:
+///
+/// * `BadVersion` - The recipe version is not blank, and is not a valid semver
+/// * `DuplicateName` - A module or package is listed more than once
+/// * `UnknownName` - A module or package is not in the metadata.db
+/// * `UnsatisfiedVersion` - None of the builds of a module or package match its version
+/// * `UnknownGroup` - There is no package group with this name
+/// * `GroupLoop` - A package group includes itself
+/// * `PlaintextPassword` - A user's password is not a crypt(3) hash
+///
+/// # Examples
+///
+/// ## POST body
+///
+/// ```json
+/// {
+///     "name": "http-server",
+///     "version": "0.2",
+///     "modules": [
+///         {
+///             "name": "httpd",
+///             "version": "3.*"
+///         }
+///     ],
+///     "packages": [
+///         {
+///             "name": "tmux",
+///             "version": "2.2"
+///         }
+///     ]
+/// }
+/// ```
+///
+/// ## Response
+///
+/// ```json
+/// {
+///     "status": false,
+///     "errors": [
+///         {
+///             "id": "BadVersion",
+///             "msg": "Invalid recipe version 0.2, it must be a semver like 0.1.2"
+///         },
+///         {
+///             "id": "UnsatisfiedVersion",
+///             "msg": "No build matches httpd 3.*"
+///         },
+///         {
+///             "id": "UnknownName",
+///             "msg": "Unknown module or package tmux"
+///         }
+///     ]
+/// }
+/// ```
+///
+#[post("/recipes/validate", format="application/json", data="<recipe>")]
+pub fn recipes_validate_json(recipe: JSON<Recipe>, db: State<DBPool>, comps: State<Comps>) -> Result<CORS<JSON<RecipesValidateResponse>>, ApiError> {
+    info!("/recipes/validate (JSON)"; "recipe.name" => recipe.name);
+
+    validate_response(&db.conn(), &comps, &recipe)
+}
+
+/// Accept a TOML formatted POST to /recipes/validate
+///
+/// This requires that the client set the type to "text/x-toml", the same as
+/// [recipes_new_toml](fn.recipes_new_toml.html).
+///
+#[post("/recipes/validate", data="<recipe>", rank=2)]
+pub fn recipes_validate_toml(recipe: TOML<Recipe>, db: State<DBPool>, comps: State<Comps>) -> Result<CORS<JSON<RecipesValidateResponse>>, ApiError> {
+    info!("/recipes/validate (TOML)"; "recipe.name" => recipe.name);
+
+    validate_response(&db.conn(), &comps, &recipe)
+}
+
+fn validate_response(conn: &Connection, comps: &Comps, recipe: &Recipe) -> Result<CORS<JSON<RecipesValidateResponse>>, ApiError> {
+    let problems = try!(validate_recipe(conn, comps, recipe));
+
+    Ok(CORS(JSON(RecipesValidateResponse {
+            status: problems.is_empty(),
+            errors: problems.iter().map(ApiErrorMsg::from).collect()
+    })))
 }
 
 
@@ -1859,34 +2369,25 @@ pub struct RecipesDeleteResponse {
 ///     "status": true
 /// }
 /// ```
+///
+/// # Errors
+///
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
+/// * 404 if the recipe or branch does not exist
+///
 #[delete("/recipes/delete/<recipe_name>")]
-pub fn recipes_delete(recipe_name: &str, repo: State<RecipeRepo>) -> CORS<JSON<RecipesDeleteResponse>> {
-    info!("/recipes/delete/"; "recipe_name" => recipe_name);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_delete(recipe_name: &str, branch: RecipeBranch, user: WriteUser, repo: State<RecipeRepo>) -> Result<CORS<JSON<RecipesDeleteResponse>>, ApiError> {
+    info!("/recipes/delete/"; "recipe_name" => recipe_name, "user" => user.login());
 
     let repo = repo.repo();
-    let mut status = match recipe::delete(&repo, recipe_name, "master") {
-        Ok(result) => result,
-        Err(e) => {
-            error!("recipes_delete"; "recipe_name" => recipe_name, "error" => format!("{:?}", e));
-            false
-        }
-    };
-
-    if status {
-        match delete_workspace(&workspace_dir(&repo, "master"), recipe_name) {
-            Ok(_) => (),
-            Err(e) => {
-                error!("recipes_delete workspace"; "recipe_name" => recipe_name, "error" => format!("{:?}", e));
-                status = false;
-            }
-        };
+    if !try!(recipe::delete(&repo, recipe_name, branch.as_str(), &user.author())) {
+        return Err(ApiError::NotFound(format!("Unknown recipe {}", recipe_name)));
     }
+    try!(delete_workspace(&workspace_dir(&repo, branch.as_str()), recipe_name));
 
-    // TODO Return error information
-    CORS(JSON(RecipesDeleteResponse {
-            status: status
-    }))
+    Ok(CORS(JSON(RecipesDeleteResponse {
+            status: true
+    })))
 }
 
 
@@ -1918,38 +2419,28 @@ pub struct RecipesUndoResponse {
 ///     "status": true
 /// }
 /// ```
+///
+/// # Errors
+///
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
+/// * 404 if the recipe, branch, or commit does not exist
+///
 #[post("/recipes/undo/<recipe_name>/<commit>")]
-pub fn recipes_undo(recipe_name: &str, commit: &str, repo: State<RecipeRepo>) -> CORS<JSON<RecipesUndoResponse>> {
-    info!("/recipes/undo/"; "recipe_name" => recipe_name, "commit" => commit);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_undo(recipe_name: &str, commit: &str, branch: RecipeBranch, user: WriteUser, repo: State<RecipeRepo>) -> Result<CORS<JSON<RecipesUndoResponse>>, ApiError> {
+    info!("/recipes/undo/"; "recipe_name" => recipe_name, "commit" => commit, "user" => user.login());
 
     let repo = repo.repo();
-    let mut status = match recipe::revert(&repo, recipe_name, "master", commit) {
-        Ok(result) => result,
-        Err(e) => {
-            error!("recipes_undo"; "recipe_name" => recipe_name, "commit" => commit, "error" => format!("{:?}", e));
-            false
-        }
-    };
-
-    if status {
-        // Read the latest commit and update the workspace copy
-        let _ = recipe::read(&repo, recipe_name, "master", None).map(|new_recipe| {
-            // Update the workspace copy, log any errors
-            match write_to_workspace(&workspace_dir(&repo, "master"), &new_recipe) {
-                Ok(_) => (),
-                Err(e) => {
-                    error!("recipes_undo workspace"; "recipe_name" => recipe_name, "error" => format!("{:?}", e));
-                    status = false;
-                }
-            };
-        });
+    if !try!(recipe::revert(&repo, recipe_name, branch.as_str(), commit, &user.author())) {
+        return Err(ApiError::NotFound(format!("Recipe {} is not in commit {}", recipe_name, commit)));
     }
 
-    // TODO Return error information
-    CORS(JSON(RecipesUndoResponse {
-            status: status
-    }))
+    // Read the latest commit and update the workspace copy
+    let new_recipe = try!(recipe::read(&repo, recipe_name, branch.as_str(), None));
+    try!(write_to_workspace(&workspace_dir(&repo, branch.as_str()), &new_recipe));
+
+    Ok(CORS(JSON(RecipesUndoResponse {
+            status: true
+    })))
 }
 
 
@@ -2038,25 +2529,25 @@ pub fn options_recipes_workspace() -> CORS<&'static str> {
 ///     "status": true
 /// }
 /// ```
+///
+/// # Errors
+///
+/// * 400 if a user's password is not a crypt(3) hash, with a PlaintextPassword error
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
+///
 #[post("/recipes/workspace", format="application/json", data="<recipe>")]
-pub fn recipes_workspace_json(recipe: JSON<Recipe>, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesNewResponse>> {
-    info!("/recipes/workspace/ (JSON)"; "recipe.name" => recipe.name);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_workspace_json(recipe: JSON<Recipe>, branch: RecipeBranch, user: WriteUser, repo_state: State<RecipeRepo>) -> Result<CORS<JSON<RecipesNewResponse>>, ApiError> {
+    info!("/recipes/workspace/ (JSON)"; "recipe.name" => recipe.name, "user" => user.login());
+
+    try!(check_passwords(&recipe));
 
     let repo = repo_state.repo();
-    // Update the workspace copy, log any errors
-    let status = match write_to_workspace(&workspace_dir(&repo, "master"), &recipe) {
-        Ok(_) => true,
-        Err(e) => {
-            error!("recipes_workspace_json"; "recipe" => format!("{:?}", recipe), "error" => format!("{:?}", e));
-            false
-        }
-    };
+    // Update the workspace copy
+    try!(write_to_workspace(&workspace_dir(&repo, branch.as_str()), &recipe));
 
-    // TODO Return error information
-    CORS(JSON(RecipesNewResponse {
-            status: status
-    }))
+    Ok(CORS(JSON(RecipesNewResponse {
+            status: true
+    })))
 }
 
 
@@ -2068,24 +2559,18 @@ pub fn recipes_workspace_json(recipe: JSON<Recipe>, repo_state: State<RecipeRepo
 /// eg. `curl -H "Content-Type: text/x-toml" -X POST --data-binary @nginx.toml http://API/URL`
 ///
 #[post("/recipes/workspace", data="<recipe>", rank=2)]
-pub fn recipes_workspace_toml(recipe: TOML<Recipe>, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesNewResponse>> {
-    info!("/recipes/workspace/ (TOML)"; "recipe.name" => recipe.name);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_workspace_toml(recipe: TOML<Recipe>, branch: RecipeBranch, user: WriteUser, repo_state: State<RecipeRepo>) -> Result<CORS<JSON<RecipesNewResponse>>, ApiError> {
+    info!("/recipes/workspace/ (TOML)"; "recipe.name" => recipe.name, "user" => user.login());
+
+    try!(check_passwords(&recipe));
 
     let repo = repo_state.repo();
-    // Update the workspace copy, log any errors
-    let status = match write_to_workspace(&workspace_dir(&repo, "master"), &recipe) {
-        Ok(_) => true,
-        Err(e) => {
-            error!("recipes_workspace_toml"; "recipe" => format!("{:?}", recipe), "error" => format!("{:?}", e));
-            false
-        }
-    };
+    // Update the workspace copy
+    try!(write_to_workspace(&workspace_dir(&repo, branch.as_str()), &recipe));
 
-    // TODO Return error information
-    CORS(JSON(RecipesNewResponse {
-            status: status
-    }))
+    Ok(CORS(JSON(RecipesNewResponse {
+            status: true
+    })))
 }
 
 
@@ -2094,13 +2579,16 @@ pub fn recipes_workspace_toml(recipe: TOML<Recipe>, repo_state: State<RecipeRepo
 pub struct RecipeDeps {
     recipe:       Recipe,
     modules:      Vec<PackageNEVRA>,
-    dependencies: Vec<PackageNEVRA>
+    dependencies: Vec<PackageNEVRA>,
+    problem:      Option<DepError>
 }
 
 /// Hold the JSON response for /recipes/depsolve/
 #[derive(Debug, Serialize)]
 pub struct RecipesDepsolveResponse {
-    recipes: Vec<RecipeDeps>
+    recipes: Vec<RecipeDeps>,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    errors:  Vec<ApiErrorMsg>
 }
 
 /// Return the contents of a recipe and its dependencies
@@ -2108,16 +2596,27 @@ pub struct RecipesDepsolveResponse {
 /// # Arguments
 ///
 /// * `recipe_names` - Comma separated list of recipe names to return
+/// * `arch` - Optional comma separated list of package architectures, eg. `?arch=aarch64`. The
+///   recipe's `arch` list is used when it isn't set, and then the server's default.
 ///
 /// # Response
 ///
 /// * JSON response like:
-///   `{"recipes": [{"recipe": {RECIPE}, "modules": [NEVRA, ...], "dependencies": [NEVRA, ...]}]}`
+///   `{"recipes": [{"recipe": {RECIPE}, "modules": [NEVRA, ...], "dependencies": [NEVRA, ...], "problem": PROBLEM}]}`
 ///   Where RECIPE is the same JSON you would get from a /recipes/info/ query
 ///   NEVRA Is the name and version of a project build. modules are the versions chosen for the
 ///   modules and packages listed in the recipe. dependencies are all the dependencies needed to
 ///   satisfy the recipe.
 ///   Detailed info about the selected project can be requested with /modules/info/<name>
+///   PROBLEM is null when the recipe depsolved. Otherwise it explains why it failed, and is one of:
+///   `{"no_package": NAME}`, `{"missing": {"chain": [NEVRA, ...], "requirement": REQ}}` where chain
+///   is the packages that led to the requirement that nothing provides,
+///   `{"conflict": {"package": NEVRA, "other": NEVRA, "key": "rpm-conflict", "requirement": REQ}}`
+///   where key is rpm-conflict or rpm-obsolete, `{"excluded": NEVRA}` where NEVRA is a package
+///   that is needed but is in the recipe's exclude list, or `{"error": MESSAGE}`.
+///
+///   Recipes that cannot be read are not in `recipes`, the reason is added to an `errors` list
+///   like `{"id": "RecipeError", "msg": "http-server: ..."}`.
 ///
 /// # Panics
 ///
@@ -2169,18 +2668,34 @@ pub struct RecipesDepsolveResponse {
 ///                },
 ///                ...
 ///             ],
+///             "problem": null
 ///         }
 ///     ]
 /// }
 ///
-#[get("/recipes/depsolve/<recipe_names>")]
-pub fn recipes_depsolve(recipe_names: &str, db: State<DBPool>, repo: State<RecipeRepo>) -> CORS<JSON<RecipesDepsolveResponse>> {
-    info!("/recipes/depsolve/"; "recipe_names" => recipe_names);
-    // TODO Get the user's branch name. Use master for now.
+#[get("/recipes/depsolve/<recipe_names>?<arch>")]
+pub fn recipes_depsolve_arch(recipe_names: &str, arch: Arch, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesDepsolveResponse>> {
+    recipes_depsolve(recipe_names, Some(arch.arches()), db, policy, comps, branch, repo)
+}
+
+/// Default handler for `/recipes/depsolve`, using the recipe's or the server's architectures
+#[get("/recipes/depsolve/<recipe_names>", rank=2)]
+pub fn recipes_depsolve_default(recipe_names: &str, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesDepsolveResponse>> {
+    recipes_depsolve(recipe_names, None, db, policy, comps, branch, repo)
+}
+
+fn recipes_depsolve(recipe_names: &str, arches: Option<Vec<String>>, db: State<DBPool>, policy: State<SolverPolicy>, comps: State<Comps>, branch: RecipeBranch, repo: State<RecipeRepo>) -> CORS<JSON<RecipesDepsolveResponse>> {
+    info!("/recipes/depsolve/"; "recipe_names" => recipe_names, "arches" => format!("{:?}", arches));
 
     let mut result = Vec::new();
+    let mut errors = Vec::new();
     for name in recipe_names.split(',') {
-        let _ = depsolve_recipe(&db, &repo, name).and_then(|(recipe, pkg_nevras)| {
+        let solved = depsolve_recipe(&db, &policy, &comps, &repo, name, &branch, arches.clone()).and_then(|(recipe, depsolved)| {
+            let (pkg_nevras, problem) = match depsolved {
+                Ok(pkg_nevras) => (pkg_nevras, None),
+                Err(e) => (vec![], Some(e))
+            };
+
             // Get the version chosen for each individual recipe module/package
             let mut recipe_nevras = Vec::new();
 
@@ -2211,14 +2726,19 @@ pub fn recipes_depsolve(recipe_names: &str, db: State<DBPool>, repo: State<Recip
             result.push(RecipeDeps {
                 recipe:       recipe,
                 modules:      recipe_nevras,
-                dependencies: pkg_nevras
+                dependencies: pkg_nevras,
+                problem:      problem
             });
             Ok(())
         });
+        if let Err(e) = solved {
+            errors.push(named_error_msg(name, e));
+        }
     }
     result.sort();
     CORS(JSON(RecipesDepsolveResponse {
-            recipes: result
+            recipes: result,
+            errors:  errors
     }))
 }
 
@@ -2258,22 +2778,142 @@ pub fn options_recipes_tag(recipe_name: &str) -> CORS<&'static str> {
 ///     "status": true
 /// }
 /// ```
+///
+/// # Errors
+///
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
+/// * 404 if the branch does not exist
+/// * 409 if there are no new commits to tag
+///
 #[post("/recipes/tag/<recipe_name>")]
-pub fn recipes_tag(recipe_name: &str, repo_state: State<RecipeRepo>) -> CORS<JSON<RecipesNewResponse>> {
-    info!("/recipes/tag/"; "recipe_name" => recipe_name);
-    // TODO Get the user's branch name. Use master for now.
+pub fn recipes_tag(recipe_name: &str, branch: RecipeBranch, user: WriteUser, repo_state: State<RecipeRepo>) -> Result<CORS<JSON<RecipesNewResponse>>, ApiError> {
+    info!("/recipes/tag/"; "recipe_name" => recipe_name, "user" => user.login());
 
     let repo = repo_state.repo();
-    let status = match recipe::tag(&repo, recipe_name, "master") {
-        Ok(result) => result,
-        Err(e) => {
-            error!("recipes_tag"; "error" => format!("{:?}", e));
-            false
+    if !try!(recipe::tag(&repo, recipe_name, branch.as_str(), &user.author())) {
+        return Err(ApiError::Conflict(format!("Recipe {} has no new commits to tag", recipe_name)));
+    }
+
+    Ok(CORS(JSON(RecipesNewResponse {
+            status: true
+    })))
+}
+
+
+/// Hold the JSON response for /recipes/merge/
+#[derive(Debug, Serialize)]
+pub struct RecipesMergeResponse {
+    status: bool,
+    recipe: Recipe,
+    conflicts: Vec<RecipeMergeConflict>,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    errors: Vec<ApiErrorMsg>
+}
+
+/// The CORS system 'protects' the client via an OPTIONS request to make sure it is allowed
+///
+/// This returns an empty response, with the CORS headers set by [CORS](struct.CORS.html).
+// Rocket has a collision with Diesel so uses route instead
+//#[options("/recipes/merge/<recipe_name>")]
+#[route(OPTIONS, "/recipes/merge/<recipe_name>")]
+#[allow(unused_variables)]
+pub fn options_recipes_merge(recipe_name: &str) -> CORS<&'static str> {
+    CORS("")
+}
+
+
+/// Handler for `/recipes/merge/<recipe_name>?from=<branch>&to=<branch>`
+/// Merge a recipe's changes from one branch into another
+///
+/// # Arguments
+///
+/// * `recipe_name` - Name of the recipe to merge
+/// * `branches` - The `from` and `to` branch names
+///
+/// # Response
+///
+/// * JSON response with "status", the recipe in the `to` branch, and the conflicts
+///
+/// This is a three-way merge using [recipe::merge](../../recipe/fn.merge.html). Changes to the
+/// description, arch, modules and packages made on only one of the branches are merged. When both
+/// branches changed the same thing differently the recipe is not written, and it returns a 409
+/// with "status" set to false. Each conflict has the change made on the `to` branch as `ours`,
+/// and the change made on the `from` branch as `theirs`. The workspace copy of the recipe in `to`
+/// is updated after merging.
+///
+/// # Errors
+///
+/// * 400 if a branch name is invalid
+/// * 401 or 403 if authentication is enabled and the user may not change the recipes
+/// * 404 if the recipe or branch cannot be read
+/// * 409 if there are conflicts
+///
+/// # Examples
+///
+/// ```json
+/// {
+///     "status": false,
+///     "recipe": {
+///         "name": "http-server",
+///         "description": "An example http server with PHP and MySQL support.",
+///         "version": "0.2.1",
+///         "modules": [],
+///         "packages": [
+///             {
+///                 "name": "tmux",
+///                 "version": "2.3"
+///             }
+///         ]
+///     },
+///     "conflicts": [
+///         {
+///             "ours": {
+///                 "old": {"Package": {"name": "tmux", "version": "2.2"}},
+///                 "new": {"Package": {"name": "tmux", "version": "2.3"}}
+///             },
+///             "theirs": {
+///                 "old": {"Package": {"name": "tmux", "version": "2.2"}},
+///                 "new": {"Package": {"name": "tmux", "version": "2.4"}}
+///             }
+///         }
+///     ],
+///     "errors": [
+///         {
+///             "id": "Conflict",
+///             "msg": "Recipe http-server has conflicting changes in bcl and master"
+///         }
+///     ]
+/// }
+/// ```
+#[post("/recipes/merge/<recipe_name>?<branches>")]
+pub fn recipes_merge(recipe_name: &str, branches: MergeBranches, user: WriteUser, repo_state: State<RecipeRepo>) -> Result<CORS<Custom<JSON<RecipesMergeResponse>>>, ApiError> {
+    info!("/recipes/merge/"; "recipe_name" => recipe_name, "from" => branches.from.as_str(), "to" => branches.to.as_str(), "user" => user.login());
+
+    for name in &[&branches.from, &branches.to] {
+        if !RecipeBranch::is_valid(name) {
+            return Err(ApiError::BadRequest(format!("Invalid branch name {}", name)));
         }
-    };
+    }
 
-    // TODO Return error information
-    CORS(JSON(RecipesNewResponse {
-            status: status
-    }))
+    let repo = repo_state.repo();
+    let merged = try!(recipe::merge(&repo, recipe_name, &branches.from, &branches.to, &user.author()));
+    if !merged.conflicts.is_empty() {
+        let err = ApiError::Conflict(format!("Recipe {} has conflicting changes in {} and {}", recipe_name, branches.from, branches.to));
+        return Ok(CORS(Custom(Status::Conflict, JSON(RecipesMergeResponse {
+            status: false,
+            recipe: merged.recipe,
+            conflicts: merged.conflicts,
+            errors: vec![err.to_msg()]
+        }))));
+    }
+
+    // Update the workspace copy
+    try!(write_to_workspace(&workspace_dir(&repo, &branches.to), &merged.recipe));
+
+    Ok(CORS(Custom(Status::Ok, JSON(RecipesMergeResponse {
+        status: true,
+        recipe: merged.recipe,
+        conflicts: merged.conflicts,
+        errors: vec![]
+    }))))
 }
@@ -0,0 +1,310 @@
+//! Authentication
+//!
+//! ## Overview
+//!
+//! Requests that change the recipe repository, eg. `/recipes/new` and `/recipes/delete`, are
+//! authenticated when the server is started with `--tokens`. The user's name and email are used
+//! as the author of the git commit. Requests that only read are not authenticated.
+//!
+//! The credentials are passed in the `Authorization` header, either as a bearer token:
+//!
+//! `Authorization: Bearer 0123456789abcdef`
+//!
+//! or as basic auth, using the user's login and token as the username and password.
+//!
+//! Authentication is pluggable. The [Authenticator](trait.Authenticator.html) trait checks the
+//! credentials and returns the [User](struct.User.html), and [TokenFile](struct.TokenFile.html)
+//! is an Authenticator that reads static tokens from a TOML file.
+//!
+//! ## Example token file
+//!
+//! ```toml
+//! [[user]]
+//! login = "bcl"
+//! name = "Brian C. Lane"
+//! email = "bcl@redhat.com"
+//! token = "0123456789abcdef"
+//!
+//! [[user]]
+//! login = "ci"
+//! name = "CI System"
+//! email = "ci@example.com"
+//! token = "fedcba9876543210"
+//! read_only = true
+//! ```
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use hyper::header::Basic;
+use toml;
+
+use recipe::Author;
+
+
+/// Authentication Errors
+#[derive(Debug)]
+pub enum AuthError {
+    IO(io::Error),
+    TomlDe(toml::de::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthError::IO(ref e) => write!(f, "IO error: {}", e),
+            AuthError::TomlDe(ref e) => write!(f, "Error parsing the tokens: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for AuthError {
+    fn from(err: io::Error) -> AuthError {
+        AuthError::IO(err)
+    }
+}
+
+impl From<toml::de::Error> for AuthError {
+    fn from(err: toml::de::Error) -> AuthError {
+        AuthError::TomlDe(err)
+    }
+}
+
+
+/// The credentials passed with a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// `Authorization: Bearer <token>`
+    Token(String),
+    /// `Authorization: Basic <base64 of login:password>`
+    Basic { login: String, password: String }
+}
+
+impl Credentials {
+    /// Parse the value of an `Authorization` header
+    ///
+    /// Returns None if the scheme is not Bearer or Basic, or if the value is badly formed.
+    pub fn from_header(value: &str) -> Option<Credentials> {
+        let mut parts = value.trim().splitn(2, ' ');
+        match (parts.next(), parts.next().map(str::trim)) {
+            (Some("Bearer"), Some(token)) if !token.is_empty() => Some(Credentials::Token(token.to_string())),
+            (Some("Basic"), Some(encoded)) => match Basic::from_str(encoded) {
+                Ok(basic) => Some(Credentials::Basic {
+                    login:    basic.username,
+                    password: basic.password.unwrap_or_default()
+                }),
+                Err(_) => None
+            },
+            _ => None
+        }
+    }
+}
+
+
+/// An authenticated user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub login: String,
+    pub name: String,
+    pub email: String,
+    /// The user may only make requests that do not change the recipes
+    pub read_only: bool
+}
+
+impl User {
+    /// Return the git author for the user's commits
+    pub fn author(&self) -> Author {
+        Author::new(self.name.as_str(), self.email.as_str())
+    }
+}
+
+
+/// Check the credentials of a request
+///
+/// Implement this to add a new way of authenticating users, and pass it to
+/// [Auth::new](struct.Auth.html#method.new).
+pub trait Authenticator: Send + Sync {
+    /// Return the User for the credentials, or None if they are not valid
+    fn authenticate(&self, credentials: &Credentials) -> Option<User>;
+}
+
+
+// An entry in the token file
+#[derive(Debug, Clone, Deserialize)]
+struct TokenEntry {
+    login: String,
+    name: Option<String>,
+    email: String,
+    token: String,
+    #[serde(default)]
+    read_only: bool
+}
+
+// The layout of the token file
+#[derive(Debug, Default, Deserialize)]
+struct TokenFileContents {
+    #[serde(default)]
+    user: Vec<TokenEntry>
+}
+
+/// Static tokens, read from a TOML file
+///
+/// A user may use their token as a bearer token, or as the basic auth password with their login.
+/// The user's name defaults to their login.
+#[derive(Debug, Default)]
+pub struct TokenFile {
+    users: Vec<TokenEntry>
+}
+
+impl TokenFile {
+    /// Read the tokens from a TOML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TokenFile, AuthError> {
+        let mut input = String::new();
+        let mut file = try!(File::open(path));
+        try!(file.read_to_string(&mut input));
+        TokenFile::from_toml(&input)
+    }
+
+    /// Parse the tokens from a TOML string
+    pub fn from_toml(input: &str) -> Result<TokenFile, AuthError> {
+        let contents = try!(toml::from_str::<TokenFileContents>(input));
+        Ok(TokenFile { users: contents.user })
+    }
+}
+
+impl Authenticator for TokenFile {
+    fn authenticate(&self, credentials: &Credentials) -> Option<User> {
+        let entry = match *credentials {
+            Credentials::Token(ref token) =>
+                self.users.iter().find(|u| token_eq(&u.token, token)),
+            Credentials::Basic { ref login, ref password } =>
+                self.users.iter().find(|u| u.login == *login && token_eq(&u.token, password))
+        };
+
+        entry.map(|u| User {
+            login:     u.login.clone(),
+            name:      u.name.clone().unwrap_or_else(|| u.login.clone()),
+            email:     u.email.clone(),
+            read_only: u.read_only
+        })
+    }
+}
+
+// Compare two tokens, taking the same time no matter where they differ
+fn token_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() || a.is_empty() {
+        return false;
+    }
+    a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+/// The authentication used by the server, used with Rocket's managed state system
+///
+/// Without an [Authenticator](trait.Authenticator.html) authentication is disabled, every request
+/// may change the recipes, and the commits are made by the server's default
+/// [Author](../recipe/struct.Author.html).
+pub struct Auth {
+    authenticator: Option<Box<Authenticator>>
+}
+
+impl Auth {
+    /// Authenticate the requests that change the recipes
+    pub fn new(authenticator: Box<Authenticator>) -> Auth {
+        Auth { authenticator: Some(authenticator) }
+    }
+
+    /// Do not authenticate any requests
+    pub fn disabled() -> Auth {
+        Auth { authenticator: None }
+    }
+
+    /// Return true if requests are authenticated
+    pub fn is_enabled(&self) -> bool {
+        self.authenticator.is_some()
+    }
+
+    /// Check the credentials, returning None if they are not valid or authentication is disabled
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<User> {
+        self.authenticator.as_ref().and_then(|a| a.authenticate(credentials))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use auth::*;
+
+    const TOKENS_TOML: &'static str = r#"
+[[user]]
+login = "admin"
+name = "Admin User"
+email = "admin@example.com"
+token = "0123456789abcdef"
+
+[[user]]
+login = "reader"
+email = "reader@example.com"
+token = "fedcba9876543210"
+read_only = true
+"#;
+
+    #[test]
+    fn test_credentials() {
+        assert_eq!(Credentials::from_header("Bearer 0123456789abcdef"),
+                   Some(Credentials::Token("0123456789abcdef".to_string())));
+        // admin:0123456789abcdef
+        assert_eq!(Credentials::from_header("Basic YWRtaW46MDEyMzQ1Njc4OWFiY2RlZg=="),
+                   Some(Credentials::Basic { login: "admin".to_string(), password: "0123456789abcdef".to_string() }));
+        assert_eq!(Credentials::from_header("Bearer "), None);
+        assert_eq!(Credentials::from_header("Digest username=admin"), None);
+        assert_eq!(Credentials::from_header(""), None);
+    }
+
+    #[test]
+    fn test_token_file() {
+        let tokens = TokenFile::from_toml(TOKENS_TOML).unwrap();
+
+        let user = tokens.authenticate(&Credentials::Token("0123456789abcdef".to_string())).unwrap();
+        assert_eq!(user.name, "Admin User");
+        assert_eq!(user.author(), Author::new("Admin User", "admin@example.com"));
+        assert!(!user.read_only);
+
+        // The name defaults to the login
+        let user = tokens.authenticate(&Credentials::Basic { login: "reader".to_string(), password: "fedcba9876543210".to_string() }).unwrap();
+        assert_eq!(user.name, "reader");
+        assert!(user.read_only);
+
+        assert_eq!(tokens.authenticate(&Credentials::Token("0123456789abcdee".to_string())), None);
+        assert_eq!(tokens.authenticate(&Credentials::Token(String::new())), None);
+        assert_eq!(tokens.authenticate(&Credentials::Basic { login: "admin".to_string(), password: "fedcba9876543210".to_string() }), None);
+    }
+
+    #[test]
+    fn test_auth_disabled() {
+        let auth = Auth::disabled();
+        assert!(!auth.is_enabled());
+        assert_eq!(auth.authenticate(&Credentials::Token("0123456789abcdef".to_string())), None);
+    }
+}
@@ -21,8 +21,18 @@
 //! * `--log` - Path to logfile, which uses the slog JSON format. Defaults to `/var/log/bdcs-api.log`
 //! * `--mockfiles` - Path to JSON files used for `/api/mock/` paths. Defaults to `/var/tmp/bdcs-mockfiles/`
 //! * `--bdcs` - Path to the content store. Static files are served from the /api/bdcs/ path.
+//! * `--comps` - Path to a TOML file with package group definitions, used by the recipe `groups`.
+//!   The groups in the database are used without it. See `examples/comps.toml`.
+//! * `--tokens` - Path to a TOML file with the users' tokens. Requests that change the recipes
+//!   must be authenticated with one of them. Without it no authentication is used.
+//! * `--cors-origin` - An origin allowed to make cross-origin requests, eg. `https://welder.example.com`.
+//!   It may be used more than once. Defaults to `*`, any origin.
+//! * `--cors-credentials` - Allow cross-origin requests with credentials from the `--cors-origin`
+//!   origins. It cannot be used with the default `*` origin.
+//! * `--cors-max-age` - How long, in seconds, browsers may cache the CORS preflight response.
 //! * `DB` - Path to the metadata sqlite database created by the Haskell bdcs utility.
-//! * `RECIPES` - Path to the directory holding the TOML formatted recipes.
+//! * `RECIPES` - Path to the directory holding the TOML formatted recipes. Compose results are
+//!   stored in the `results/` sub-directory.
 //!
 
 // Copyright (C) 2016-2017 Red Hat, Inc.
@@ -62,8 +72,13 @@ use std::path::PathBuf;
 
 use bdcs::api::bdcs_server::BDCSPath;
 use bdcs::{RocketToml, RocketConfig};
-use bdcs::api::{v0, mock, docs, bdcs_server};
-use bdcs::db::DBPool;
+use bdcs::api::{self, v0, mock, docs, bdcs_server};
+use bdcs::auth::{Auth, TokenFile};
+use bdcs::compose::ComposeQueue;
+use bdcs::comps::Comps;
+use bdcs::export::{Exporter, TarExporter};
+use bdcs::db::{DBPool, DB_VERSION, get_schema_version};
+use bdcs::depsolve::SolverPolicy;
 use bdcs::recipe::{self, RecipeRepo};
 use bdcs::workspace::check_workspace_dir;
 use clap::{Arg, App};
@@ -105,6 +120,31 @@ fn main() {
                                         .value_name("BDCS")
                                         .help("Path to the content store directory")
                                         .takes_value(true))
+                            .arg(Arg::with_name("comps")
+                                        .long("comps")
+                                        .value_name("COMPS")
+                                        .help("Path to the package group definitions")
+                                        .takes_value(true))
+                            .arg(Arg::with_name("tokens")
+                                        .long("tokens")
+                                        .value_name("TOKENS")
+                                        .help("Path to the tokens used to authenticate changes to the recipes")
+                                        .takes_value(true))
+                            .arg(Arg::with_name("cors-origin")
+                                        .long("cors-origin")
+                                        .value_name("ORIGIN")
+                                        .help("Origin allowed to make cross-origin requests (*)")
+                                        .takes_value(true)
+                                        .multiple(true)
+                                        .number_of_values(1))
+                            .arg(Arg::with_name("cors-credentials")
+                                        .long("cors-credentials")
+                                        .help("Allow cross-origin requests with credentials"))
+                            .arg(Arg::with_name("cors-max-age")
+                                        .long("cors-max-age")
+                                        .value_name("SECONDS")
+                                        .help("How long browsers may cache the CORS preflight response")
+                                        .takes_value(true))
                             .arg(Arg::with_name("DB")
                                         .help("Path to the BDCS sqlite database")
                                         .required(true)
@@ -124,10 +164,19 @@ fn main() {
             db_path: matches.value_of("DB").unwrap().to_string(),
             recipe_path: matches.value_of("RECIPES").unwrap().to_string(),
             log_path: matches.value_of("log").unwrap_or("/var/log/bdcs-api.log").to_string(),
-            mockfiles_path: matches.value_of("mockfiles").unwrap_or("/var/tmp/bdcs-mockfiles/").to_string()
-
+            mockfiles_path: matches.value_of("mockfiles").unwrap_or("/var/tmp/bdcs-mockfiles/").to_string(),
+            cors_origins: match matches.values_of("cors-origin") {
+                Some(origins) => origins.map(String::from).collect(),
+                None => vec!["*".to_string()]
+            },
+            cors_credentials: matches.is_present("cors-credentials"),
+            cors_max_age: matches.value_of("cors-max-age").unwrap_or("").parse().unwrap_or(0)
         }
     };
+    if rocket_config.global.cors_credentials && rocket_config.global.cors_origins.iter().any(|o| o == "*") {
+        clap::Error::with_description("--cors-credentials needs a list of --cors-origin origins, it cannot be used with *",
+                                      clap::ErrorKind::ArgumentConflict).exit();
+    }
 
     // Write out a Rocket.toml config with [global] settings
     let rocket_toml = toml::to_string(&rocket_config).unwrap();
@@ -157,36 +206,79 @@ fn main() {
         check_workspace_dir(&PathBuf::from(repo.path()).join("workspace")).unwrap();
     }
 
-    rocket::ignite()
+    // Compose results are stored next to the recipe repository
+    let results_path = PathBuf::from(&rocket_config.global.recipe_path).join("results");
+    // Register the supported compose types
+    let exporters: Vec<Box<Exporter>> = vec![
+        Box::new(TarExporter::new(&rocket_config.global.db_path, &rocket_config.global.bdcs_path))
+    ];
+    let compose_queue = ComposeQueue::new(&results_path.to_string_lossy(), exporters).unwrap();
+
+    // Package group definitions, in addition to the groups in the database
+    let comps = match matches.value_of("comps") {
+        Some(path) => Comps::from_file(path).expect("Error reading the package groups"),
+        None => Comps::new()
+    };
+
+    // Authenticate the requests that change the recipes
+    let auth = match matches.value_of("tokens") {
+        Some(path) => Auth::new(Box::new(TokenFile::from_file(path).expect("Error reading the tokens"))),
+        None => {
+            warn!("No --tokens, requests that change the recipes are not authenticated");
+            Auth::disabled()
+        }
+    };
+
+    // Check the database's schema version
+    let db_pool = DBPool::new(&rocket_config.global.db_path);
+    match get_schema_version(&db_pool.conn()) {
+        Ok((_, _, true)) => (),
+        Ok((_, schema_version, false)) =>
+            error!("Unsupported database schema version, upgrade it with bdcs-dbmigrate";
+                   "schema_version" => schema_version, "db_version" => DB_VERSION),
+        Err(e) => error!("Error reading the database schema version"; "error" => e.to_string())
+    }
+
+    // Launched by api::launch so that the CORS headers can check the request's origin
+    api::launch(rocket::ignite()
         .mount("/api/v0/", routes![v0::test, v0::version,
-                                   v0::isos, v0::compose, v0::compose_types, v0::compose_cancel,
-                                   v0::compose_status, v0::compose_status_id, v0::compose_log,
+                                   v0::isos, v0::options_compose, v0::compose, v0::compose_types,
+                                   v0::options_compose_cancel, v0::compose_cancel,
+                                   v0::compose_status, v0::compose_status_id,
+                                   v0::compose_log_default, v0::compose_log_follow,
                                    v0::projects_list_default, v0::projects_list_filter,
                                    v0::projects_info,
-                                   v0::projects_depsolve,
-                                   v0::modules_info,
+                                   v0::projects_depsolve_default, v0::projects_depsolve_arch,
+                                   v0::modules_info, v0::modules_whatrequires,
                                    v0::modules_list_default, v0::modules_list_filter,
                                    v0::modules_list_noargs_default, v0::modules_list_noargs_filter,
                                    v0::recipes_list_default, v0::recipes_list_filter,
                                    v0::recipes_info, v0::recipes_info_toml,
-                                   v0::recipes_freeze, v0::recipes_freeze_toml,
+                                   v0::recipes_freeze_default, v0::recipes_freeze_arch,
+                                   v0::recipes_freeze_toml, v0::recipes_freeze_toml_arch,
                                    v0::recipes_changes_default, v0::recipes_changes_filter,
                                    v0::recipes_diff,
                                    v0::options_recipes_new, v0::recipes_new_json, v0::recipes_new_toml,
+                                   v0::options_recipes_validate, v0::recipes_validate_json, v0::recipes_validate_toml,
                                    v0::options_recipes_workspace,
                                    v0::recipes_workspace_json, v0::recipes_workspace_toml,
                                    v0::options_recipes_delete, v0::recipes_delete,
                                    v0::recipes_undo,
-                                   v0::recipes_depsolve,
+                                   v0::recipes_depsolve_default, v0::recipes_depsolve_arch,
                                    v0::options_recipes_tag, v0::recipes_tag,
+                                   v0::options_recipes_merge, v0::recipes_merge,
                                    v0::status])
         .mount("/api/mock/", routes![mock::static_route, mock::static_route_filter,
                                      mock::static_route_param, mock::static_route_param_filter,
                                      mock::static_route_action, mock::static_route_action_filter])
         .mount("/api/docs/", routes![docs::index, docs::files])
         .mount("/api/bdcs/", routes![bdcs_server::files])
-        .manage(DBPool::new(&rocket_config.global.db_path))
+        .catch(errors![api::bad_request, api::unauthorized, api::forbidden, api::not_found])
+        .manage(db_pool)
         .manage(RecipeRepo::new(&rocket_config.global.recipe_path))
         .manage(BDCSPath(rocket_config.global.bdcs_path))
-        .launch();
+        .manage(SolverPolicy::default())
+        .manage(comps)
+        .manage(auth)
+        .manage(compose_queue));
 }
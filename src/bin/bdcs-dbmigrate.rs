@@ -0,0 +1,87 @@
+//! BDCS Database Migration
+//!
+//! Upgrade the schema of a metadata database to the version supported by bdcs-api-server. Each
+//! migration is run in its own transaction.
+//!
+//! # Arguments
+//!
+//! * `--version` - The schema version to upgrade to, defaults to the supported version
+//! * `--check` - Print the schema version without changing the database. Exits with 1 if it
+//!   needs to be upgraded.
+//! * `DB` - Path to the metadata sqlite database
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate bdcs;
+#[macro_use] extern crate clap;
+extern crate rusqlite;
+
+use bdcs::db::DB_VERSION;
+use bdcs::migrate::{get_user_version, migrate};
+
+use clap::{Arg, App};
+use rusqlite::Connection;
+use std::process::exit;
+
+macro_rules! exit_error {
+    ($rc:expr, $msg:expr) => ( { println!("error: {}", $msg); exit($rc); } )
+}
+
+fn main() {
+    let matches = App::new("bdcs-dbmigrate")
+                            .about("Upgrade the schema of a BDCS metadata database")
+                            .version(crate_version!())
+                            .arg(Arg::with_name("version")
+                                        .long("version")
+                                        .value_name("VERSION")
+                                        .help("Schema version to upgrade to")
+                                        .takes_value(true))
+                            .arg(Arg::with_name("check")
+                                        .long("check")
+                                        .help("Print the schema version, without upgrading it"))
+                            .arg(Arg::with_name("DB")
+                                        .help("Path to the BDCS sqlite database")
+                                        .required(true)
+                                        .index(1))
+                        .get_matches();
+
+    let target = match matches.value_of("version") {
+        Some(version) => version.parse().unwrap_or_else(|e| exit_error!(2, e)),
+        None => DB_VERSION
+    };
+
+    let conn = Connection::open(matches.value_of("DB").unwrap())
+        .unwrap_or_else(|e| exit_error!(3, e));
+    let version = get_user_version(&conn)
+        .unwrap_or_else(|e| exit_error!(3, e));
+
+    if matches.is_present("check") {
+        println!("Schema version {}, supported version {}", version, DB_VERSION);
+        exit(if version < target { 1 } else { 0 });
+    }
+
+    let applied = migrate(&conn, target)
+        .unwrap_or_else(|e| exit_error!(1, e));
+    if applied.is_empty() {
+        println!("Schema version {} is up to date", version);
+    }
+    for migration in applied {
+        println!("Upgraded to schema version {}: {}", migration.version, migration.description);
+    }
+}
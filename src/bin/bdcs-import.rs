@@ -0,0 +1,109 @@
+//! BDCS RPM Import
+//!
+//! Create or add to a metadata database from local `.rpm` files. Only the rpm headers are read,
+//! the files are not added to a content store. See the [import](../bdcs/import/index.html) module
+//! for the details.
+//!
+//! Directories with a `repodata/repomd.xml` are imported from the repository's metadata instead,
+//! see the [repodata](../bdcs/repodata/index.html) module.
+//!
+//! # Arguments
+//!
+//! * `DB` - Path to the metadata sqlite database. It is created if it does not exist.
+//! * `RPMS` - The rpm files, directories of rpm files, or repository directories to import.
+//!   Source rpms in the directories are skipped.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate bdcs;
+#[macro_use] extern crate clap;
+extern crate rusqlite;
+
+use bdcs::db::{DB_VERSION, create_schema};
+use bdcs::import::{import_file, rpm_files};
+use bdcs::migrate::get_user_version;
+use bdcs::repodata::{import_repodata, is_repo};
+
+use clap::{Arg, App};
+use rusqlite::Connection;
+use std::path::PathBuf;
+use std::process::exit;
+
+macro_rules! exit_error {
+    ($rc:expr, $msg:expr) => ( { println!("error: {}", $msg); exit($rc); } )
+}
+
+fn main() {
+    let matches = App::new("bdcs-import")
+                            .about("Import rpm files into a BDCS metadata database")
+                            .version(crate_version!())
+                            .arg(Arg::with_name("DB")
+                                        .help("Path to the BDCS sqlite database")
+                                        .required(true)
+                                        .index(1))
+                            .arg(Arg::with_name("RPMS")
+                                        .help("rpm files, directories of rpm files, or repositories to import")
+                                        .required(true)
+                                        .multiple(true)
+                                        .index(2))
+                        .get_matches();
+
+    let conn = Connection::open(matches.value_of("DB").unwrap())
+        .unwrap_or_else(|e| exit_error!(3, e));
+    match get_user_version(&conn).unwrap_or_else(|e| exit_error!(3, e)) {
+        0 => create_schema(&conn).unwrap_or_else(|e| exit_error!(3, e)),
+        DB_VERSION => (),
+        version => exit_error!(3, format!("schema version {} is not {}, upgrade it with bdcs-dbmigrate", version, DB_VERSION))
+    }
+
+    let mut failed = false;
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for arg in matches.values_of("RPMS").unwrap() {
+        let path = PathBuf::from(arg);
+        if is_repo(&path) {
+            match import_repodata(&conn, &path) {
+                Ok(summary) => println!("Imported {} packages from {}, skipped {} that had already been imported",
+                                        summary.imported, path.display(), summary.skipped),
+                Err(e) => {
+                    println!("error: {}: {}", path.display(), e);
+                    failed = true;
+                }
+            }
+        } else if path.is_dir() {
+            paths.extend(rpm_files(&path).unwrap_or_else(|e| exit_error!(2, format!("{}: {}", arg, e))));
+        } else {
+            paths.push(path);
+        }
+    }
+
+    for path in paths {
+        match import_file(&conn, &path) {
+            Ok(Some(_)) => println!("Imported {}", path.display()),
+            Ok(None) => println!("Skipped {}, it has already been imported", path.display()),
+            Err(e) => {
+                println!("error: {}: {}", path.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+}
@@ -1,4 +1,5 @@
 extern crate bdcs;
+#[macro_use] extern crate clap;
 extern crate r2d2;
 extern crate r2d2_sqlite;
 
@@ -6,8 +7,8 @@ use bdcs::db::*;
 use bdcs::depclose::*;
 use bdcs::depsolve::*;
 
+use clap::{Arg, App};
 use r2d2_sqlite::SqliteConnectionManager;
-use std::env;
 use std::process::exit;
 
 macro_rules! exit_error {
@@ -15,29 +16,63 @@ macro_rules! exit_error {
 }
 
 fn main() {
-    let mut argv: Vec<String> = env::args().collect();
-    if argv.len() < 3 { exit_error!(2, "depclose metadata.db RPM [RPM...]"); }
+    let matches = App::new("depclose")
+                            .about("Print the packages needed to install a list of packages")
+                            .version(crate_version!())
+                            .arg(Arg::with_name("arch")
+                                        .long("arch")
+                                        .value_name("ARCH")
+                                        .help("Package architecture to select, may be repeated (x86_64)")
+                                        .takes_value(true)
+                                        .multiple(true)
+                                        .number_of_values(1))
+                            .arg(Arg::with_name("no-name-match")
+                                        .long("no-name-match")
+                                        .help("Don't prefer providers named after the requirement"))
+                            .arg(Arg::with_name("no-newest")
+                                        .long("no-newest")
+                                        .help("Don't prefer the newest version of a package"))
+                            .arg(Arg::with_name("no-fewest")
+                                        .long("no-fewest")
+                                        .help("Don't prefer the alternatives with the fewest packages"))
+                            .arg(Arg::with_name("DB")
+                                        .help("Path to the BDCS sqlite database")
+                                        .required(true)
+                                        .index(1))
+                            .arg(Arg::with_name("RPM")
+                                        .help("Packages to depclose")
+                                        .required(true)
+                                        .multiple(true)
+                                        .index(2))
+                        .get_matches();
 
-    // Remove the program, grab the database.
-    argv.remove(0);
-    let db = argv.remove(0);
+    let mut policy = SolverPolicy::default();
+    if let Some(arches) = matches.values_of("arch") {
+        policy.arches = arches.map(String::from).collect();
+    }
+    policy.name_match = !matches.is_present("no-name-match");
+    policy.newest = !matches.is_present("no-newest");
+    policy.fewest_packages = !matches.is_present("no-fewest");
+
+    let db = matches.value_of("DB").unwrap();
+    let argv: Vec<PackageSpec> = matches.values_of("RPM").unwrap().map(PackageSpec::from).collect();
 
     // connect to the database
     let cfg = r2d2::Config::builder().build();
-    let mgr = SqliteConnectionManager::new(db.as_str());
+    let mgr = SqliteConnectionManager::new(db);
     let pool = r2d2::Pool::new(cfg, mgr)
         .unwrap_or_else(|e| exit_error!(3, e));
     let conn = pool.get()
         .unwrap_or_else(|e| exit_error!(3, e));
 
     // depclose the given args into a big ol' depexpr
-    let depexpr = close_dependencies(&conn, &[String::from("x86_64")], &argv)
+    let depexpr = close_dependencies(&conn, &policy.arches, &argv)
         .unwrap_or_else(|e| exit_error!(1, e));
 
     // Wrap the returned depexpression in a Vector
     let mut exprs = vec![depexpr];
 
-    let results:Vec<bdcs::db::Groups> = solve_dependencies(&conn, &mut exprs)
+    let results:Vec<bdcs::db::Groups> = solve_dependencies(&conn, &policy, &mut exprs)
         .unwrap_or_else(|e| exit_error!(1, e)).iter()
         .map(|id| get_groups_id(&conn, id))
         .filter_map(|grp_res| grp_res.unwrap_or(None))
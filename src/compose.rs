@@ -0,0 +1,829 @@
+//! Compose job queue
+//!
+//! ## Overview
+//!
+//! Composes are started by the `/compose` API route. The recipe is depsolved by the handler and
+//! the resulting [ComposeJob](struct.ComposeJob.html) is added to the
+//! [ComposeQueue](struct.ComposeQueue.html), which is stored as Rocket managed state.
+//!
+//! Each job is assigned a UUID which is used by the status and cancel routes. The jobs are run,
+//! one at a time, by a worker thread that is started when the queue is created.
+//!
+//! The job metadata, including the recipe and its frozen package versions, is kept in
+//! `<results>/<id>/compose.toml` so that the compose history survives a restart of the server.
+//! Each job also has its own JSON log in `<results>/<id>/compose.log`, separate from the server's
+//! log. It can be read with a [ComposeLogReader](struct.ComposeLogReader.html).
+//!
+//! A job moves through these states:
+//!
+//! * `WAITING` - The job is in the queue, waiting for the worker.
+//! * `RUNNING` - The worker is running the job.
+//! * `FINISHED` - The job has finished successfully.
+//! * `FAILED` - The job failed or was canceled.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions, create_dir_all, read_dir, rename};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use chrono::UTC;
+use slog::{self, DrainExt};
+use slog_json;
+use slog_stream;
+use toml;
+use uuid::Uuid;
+
+use db::PackageNEVRA;
+use export::Exporter;
+use recipe::Recipe;
+
+
+/// The states a compose job can be in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ComposeState {
+    #[serde(rename = "WAITING")]
+    Waiting,
+    #[serde(rename = "RUNNING")]
+    Running,
+    #[serde(rename = "FINISHED")]
+    Finished,
+    #[serde(rename = "FAILED")]
+    Failed
+}
+
+impl fmt::Display for ComposeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ComposeState::Waiting => f.write_str("WAITING"),
+            ComposeState::Running => f.write_str("RUNNING"),
+            ComposeState::Finished => f.write_str("FINISHED"),
+            ComposeState::Failed => f.write_str("FAILED"),
+        }
+    }
+}
+
+
+/// Errors returned by the compose queue
+#[derive(Debug)]
+pub enum ComposeError {
+    NotFound(String),
+    Finished(String),
+    WorkerStopped,
+    Io(io::Error),
+    TomlSer(toml::ser::Error),
+    TomlDe(toml::de::Error),
+}
+
+impl fmt::Display for ComposeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ComposeError::NotFound(ref id) => write!(f, "Unknown compose id {}", id),
+            ComposeError::Finished(ref id) => write!(f, "Compose {} has already finished", id),
+            ComposeError::WorkerStopped => f.write_str("The compose worker has stopped"),
+            ComposeError::Io(ref e) => write!(f, "IO error: {}", e),
+            ComposeError::TomlSer(ref e) => write!(f, "TOML serialization error: {}", e),
+            ComposeError::TomlDe(ref e) => write!(f, "TOML parse error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ComposeError {
+    fn from(err: io::Error) -> ComposeError {
+        ComposeError::Io(err)
+    }
+}
+
+impl From<toml::ser::Error> for ComposeError {
+    fn from(err: toml::ser::Error) -> ComposeError {
+        ComposeError::TomlSer(err)
+    }
+}
+
+impl From<toml::de::Error> for ComposeError {
+    fn from(err: toml::de::Error) -> ComposeError {
+        ComposeError::TomlDe(err)
+    }
+}
+
+
+/// A single compose job
+///
+/// This is written to `<results>/<id>/compose.toml` each time its state changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComposeJob {
+    pub id:           String,
+    pub compose_type: String,
+    pub state:        ComposeState,
+    pub queued:       String,
+    pub started:      Option<String>,
+    pub finished:     Option<String>,
+    pub error:        Option<String>,
+    pub log:          String,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub cancel:       bool,
+    pub recipe:       Recipe,
+    pub frozen:       Recipe,
+    pub packages:     Vec<PackageNEVRA>
+}
+
+impl ComposeJob {
+    /// Create a new compose job in the WAITING state with a random UUID
+    ///
+    /// # Arguments
+    ///
+    /// * `results` - The top level results directory
+    /// * `recipe` - The recipe being composed
+    /// * `frozen` - The recipe with the depsolved versions of its modules and packages
+    /// * `compose_type` - The type of output to create. eg. 'tar'
+    /// * `packages` - The depsolved packages for the recipe
+    ///
+    /// # Returns
+    ///
+    /// * A new [ComposeJob](struct.ComposeJob.html)
+    ///
+    fn new(results: &Path, recipe: Recipe, frozen: Recipe, compose_type: &str, packages: Vec<PackageNEVRA>) -> ComposeJob {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        let log = results.join(&id).join("compose.log").to_string_lossy().into_owned();
+
+        ComposeJob {
+            id:           id,
+            compose_type: compose_type.to_string(),
+            state:        ComposeState::Waiting,
+            queued:       UTC::now().to_rfc3339(),
+            started:      None,
+            finished:     None,
+            error:        None,
+            log:          log,
+            cancel:       false,
+            recipe:       recipe,
+            frozen:       frozen,
+            packages:     packages
+        }
+    }
+
+    /// Return a summary of the job, without the package list
+    pub fn status(&self) -> ComposeStatus {
+        ComposeStatus {
+            id:           self.id.clone(),
+            recipe:       self.recipe.name.clone(),
+            version:      self.recipe.version.clone(),
+            compose_type: self.compose_type.clone(),
+            state:        self.state,
+            queued:       self.queued.clone(),
+            started:      self.started.clone(),
+            finished:     self.finished.clone(),
+            error:        self.error.clone()
+        }
+    }
+}
+
+
+/// Summary of a compose job, as returned by the status routes
+#[derive(Debug, Clone, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct ComposeStatus {
+    pub id:           String,
+    pub recipe:       String,
+    pub version:      String,
+    pub compose_type: String,
+    pub state:        ComposeState,
+    pub queued:       String,
+    pub started:      Option<String>,
+    pub finished:     Option<String>,
+    pub error:        Option<String>
+}
+
+
+/// The registered exporters, shared with the worker thread
+type Exporters = Arc<Vec<Box<Exporter>>>;
+
+
+/// The compose queue, stored as Rocket managed state
+///
+/// The job ids are sent to the worker thread over a channel, the jobs themselves are kept in a
+/// HashMap that is shared with the worker.
+pub struct ComposeQueue {
+    results:   PathBuf,
+    exporters: Exporters,
+    jobs:      Arc<Mutex<HashMap<String, ComposeJob>>>,
+    sender:    Mutex<Sender<String>>
+}
+
+impl ComposeQueue {
+    /// Create a new queue and start the worker thread
+    ///
+    /// # Arguments
+    ///
+    /// * `results` - Path to the directory holding the compose results
+    /// * `exporters` - The [Exporters](../export/trait.Exporter.html) for the supported compose
+    ///   types
+    ///
+    /// # Returns
+    ///
+    /// * A new ComposeQueue or a ComposeError
+    ///
+    /// Jobs from a previous run are read from the results directory. Jobs that were RUNNING are
+    /// marked as FAILED, jobs that were WAITING are queued again.
+    ///
+    pub fn new(results: &str, exporters: Vec<Box<Exporter>>) -> Result<ComposeQueue, ComposeError> {
+        let results = PathBuf::from(results);
+        try!(create_dir_all(&results));
+
+        let jobs = load_jobs(&results);
+        let mut waiting: Vec<(String, String)> = jobs.values()
+                                                     .filter(|job| job.state == ComposeState::Waiting)
+                                                     .map(|job| (job.queued.clone(), job.id.clone()))
+                                                     .collect();
+        waiting.sort();
+
+        let jobs = Arc::new(Mutex::new(jobs));
+        let (sender, receiver) = channel();
+        for (_, id) in waiting {
+            let _ = sender.send(id);
+        }
+
+        let exporters = Arc::new(exporters);
+        let worker_jobs = jobs.clone();
+        let worker_results = results.clone();
+        let worker_exporters = exporters.clone();
+        thread::spawn(move || compose_worker(&worker_results, &worker_exporters, worker_jobs, receiver));
+
+        Ok(ComposeQueue {
+            results:   results,
+            exporters: exporters,
+            jobs:      jobs,
+            sender:    Mutex::new(sender)
+        })
+    }
+
+    /// Add a new job to the queue
+    ///
+    /// # Arguments
+    ///
+    /// * `recipe` - The recipe being composed
+    /// * `frozen` - The recipe with the depsolved versions of its modules and packages
+    /// * `compose_type` - The type of output to create. eg. 'tar'
+    /// * `packages` - The depsolved packages for the recipe
+    ///
+    /// # Returns
+    ///
+    /// * The status of the queued job
+    ///
+    pub fn submit(&self, recipe: Recipe, frozen: Recipe, compose_type: &str, packages: Vec<PackageNEVRA>) -> Result<ComposeStatus, ComposeError> {
+        let job = ComposeJob::new(&self.results, recipe, frozen, compose_type, packages);
+        try!(write_job(&self.results, &job));
+
+        let status = job.status();
+        let id = job.id.clone();
+        self.jobs.lock().unwrap().insert(id.clone(), job);
+
+        match self.sender.lock().unwrap().send(id.clone()) {
+            Ok(_) => Ok(status),
+            Err(_) => Err(ComposeError::WorkerStopped)
+        }
+    }
+
+    /// Return the names and descriptions of the registered compose types, sorted by name
+    pub fn types(&self) -> Vec<(String, String)> {
+        let mut types: Vec<(String, String)> = self.exporters.iter()
+                                                   .map(|e| (e.name().to_string(), e.description().to_string()))
+                                                   .collect();
+        types.sort();
+        types
+    }
+
+    /// Return true if an exporter is registered for the compose type
+    pub fn has_type(&self, compose_type: &str) -> bool {
+        self.exporters.iter().any(|e| e.name() == compose_type)
+    }
+
+    /// Return the status of a single job
+    pub fn status(&self, id: &str) -> Option<ComposeStatus> {
+        self.jobs.lock().unwrap().get(id).map(|job| job.status())
+    }
+
+    /// Return a copy of a job, including its recipes and package list
+    pub fn job(&self, id: &str) -> Option<ComposeJob> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// Return the status of all the jobs, sorted by the time they were queued
+    pub fn list(&self) -> Vec<ComposeStatus> {
+        let mut status: Vec<ComposeStatus> = self.jobs.lock().unwrap()
+                                                 .values()
+                                                 .map(|job| job.status())
+                                                 .collect();
+        status.sort_by(|a, b| a.queued.cmp(&b.queued));
+        status
+    }
+
+    /// Return a reader for the end of a job's log
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The UUID of the job
+    /// * `kbytes` - Number of kilobytes to return from the end of the log
+    /// * `follow` - Keep reading until the job has finished
+    ///
+    /// # Returns
+    ///
+    /// * A [ComposeLogReader](struct.ComposeLogReader.html) or a ComposeError
+    ///
+    pub fn log_reader(&self, id: &str, kbytes: u64, follow: bool) -> Result<ComposeLogReader, ComposeError> {
+        let path = {
+            let jobs = self.jobs.lock().unwrap();
+            let job = try_opt!(jobs.get(id), Err(ComposeError::NotFound(id.to_string())));
+            PathBuf::from(&job.log)
+        };
+
+        // Start kbytes from the end of the log, or at the start if it hasn't been written yet
+        let offset = match path.metadata() {
+            Ok(metadata) => metadata.len().saturating_sub(kbytes * 1024),
+            Err(_) => 0
+        };
+
+        Ok(ComposeLogReader {
+            id:     id.to_string(),
+            jobs:   self.jobs.clone(),
+            path:   path,
+            file:   None,
+            offset: offset,
+            done:   !follow
+        })
+    }
+
+    /// Cancel a job
+    ///
+    /// A WAITING job is marked as FAILED immediately and will be skipped by the worker. A RUNNING
+    /// job is flagged, and its exporter stops the next time it checks the flag. The job is marked
+    /// as FAILED when the exporter returns.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The UUID of the job
+    ///
+    /// # Returns
+    ///
+    /// * The status of the job after canceling it
+    ///
+    pub fn cancel(&self, id: &str) -> Result<ComposeStatus, ComposeError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = try_opt!(jobs.get_mut(id), Err(ComposeError::NotFound(id.to_string())));
+
+        match job.state {
+            ComposeState::Waiting => {
+                job.state = ComposeState::Failed;
+                job.finished = Some(UTC::now().to_rfc3339());
+                job.error = Some("Canceled".to_string());
+                try!(write_job(&self.results, job));
+            },
+            ComposeState::Running => job.cancel = true,
+            _ => return Err(ComposeError::Finished(id.to_string()))
+        }
+
+        Ok(job.status())
+    }
+}
+
+
+/// How long to wait for more log output when following a log
+const FOLLOW_INTERVAL_MS: u64 = 500;
+
+/// Reader for a job's log
+///
+/// When following the log the reader waits for more output instead of returning end of file,
+/// until the job is no longer WAITING or RUNNING.
+pub struct ComposeLogReader {
+    id:     String,
+    jobs:   Arc<Mutex<HashMap<String, ComposeJob>>>,
+    path:   PathBuf,
+    file:   Option<File>,
+    offset: u64,
+    done:   bool
+}
+
+impl Read for ComposeLogReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // The log is created when the job starts running
+            if self.file.is_none() && self.path.exists() {
+                let mut file = try!(File::open(&self.path));
+                try!(file.seek(SeekFrom::Start(self.offset)));
+                self.file = Some(file);
+            }
+
+            if let Some(ref mut file) = self.file {
+                let size = try!(file.read(buf));
+                if size > 0 {
+                    return Ok(size);
+                }
+            }
+
+            if self.done {
+                return Ok(0);
+            }
+
+            // Read whatever was written before the job finished, and then stop
+            if !is_active(&self.jobs, &self.id) {
+                self.done = true;
+                continue;
+            }
+            thread::sleep(Duration::from_millis(FOLLOW_INTERVAL_MS));
+        }
+    }
+}
+
+
+/// Write a job's metadata to `<results>/<id>/compose.toml`
+///
+/// The metadata is written to a temporary file first, and then renamed, so that a crash will not
+/// leave a partial file behind.
+fn write_job(results: &Path, job: &ComposeJob) -> Result<(), ComposeError> {
+    let job_dir = results.join(&job.id);
+    try!(create_dir_all(&job_dir));
+
+    let tmp_filename = job_dir.join("compose.toml.tmp");
+    {
+        let mut file = try!(OpenOptions::new()
+                                        .create(true)
+                                        .write(true)
+                                        .truncate(true)
+                                        .open(&tmp_filename));
+        // NOTE toml::to_string() can fail depending on which struct elements are empty
+        // we use try_from to work around this by converting to a Value first.
+        let job_toml = try!(toml::Value::try_from(job));
+        try!(file.write_all(job_toml.to_string().as_bytes()));
+    }
+    try!(rename(&tmp_filename, job_dir.join("compose.toml")));
+
+    Ok(())
+}
+
+/// Read a job's metadata from a results sub-directory
+fn read_job(job_dir: &Path) -> Result<ComposeJob, ComposeError> {
+    let mut input = String::new();
+    try!(try!(File::open(job_dir.join("compose.toml"))).read_to_string(&mut input));
+    Ok(try!(toml::from_str::<ComposeJob>(&input)))
+}
+
+/// Read all the jobs from the results directory
+///
+/// Jobs that were RUNNING when the server stopped are marked as FAILED and written back out.
+/// Directories that cannot be read are logged and skipped.
+fn load_jobs(results: &Path) -> HashMap<String, ComposeJob> {
+    let mut jobs = HashMap::new();
+    let entries = match read_dir(results) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Error reading compose results"; "results" => results.to_string_lossy().into_owned(), "error" => e.to_string());
+            return jobs;
+        }
+    };
+
+    for job_dir in entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| p.is_dir()) {
+        let mut job = match read_job(&job_dir) {
+            Ok(job) => job,
+            Err(e) => {
+                error!("Error reading compose"; "path" => job_dir.to_string_lossy().into_owned(), "error" => e.to_string());
+                continue;
+            }
+        };
+
+        if job.state == ComposeState::Running {
+            job.state = ComposeState::Failed;
+            job.finished = Some(UTC::now().to_rfc3339());
+            job.error = Some("The server was restarted while the compose was running".to_string());
+            if let Err(e) = write_job(results, &job) {
+                error!("Error writing compose"; "id" => job.id.as_str(), "error" => e.to_string());
+            }
+        }
+        jobs.insert(job.id.clone(), job);
+    }
+    jobs
+}
+
+
+/// Run the queued jobs, one at a time
+///
+/// This runs until the queue's Sender is dropped.
+fn compose_worker(results: &Path, exporters: &Exporters, jobs: Arc<Mutex<HashMap<String, ComposeJob>>>, receiver: Receiver<String>) {
+    for id in receiver.iter() {
+        // Move the job to RUNNING, skipping jobs that were canceled while waiting
+        let job = {
+            let mut jobs = jobs.lock().unwrap();
+            let job = match jobs.get_mut(&id) {
+                Some(job) => job,
+                None => continue
+            };
+            if job.state != ComposeState::Waiting {
+                continue;
+            }
+            job.state = ComposeState::Running;
+            job.started = Some(UTC::now().to_rfc3339());
+            if let Err(e) = write_job(results, job) {
+                error!("Error writing compose"; "id" => id.as_str(), "error" => e.to_string());
+            }
+            job.clone()
+        };
+        info!("Compose started"; "id" => id.as_str(), "recipe" => job.recipe.name.as_str(), "type" => job.compose_type.as_str());
+
+        let log = job_logger(&job);
+        slog_info!(log, "Compose started"; "recipe" => job.recipe.name.as_str(), "type" => job.compose_type.as_str());
+        let result = run_compose(&jobs, &job, results, exporters, &log);
+
+        let mut jobs = jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&id) {
+            match result {
+                Ok(_) => job.state = ComposeState::Finished,
+                Err(e) => {
+                    error!("Compose failed"; "id" => id.as_str(), "error" => e.as_str());
+                    slog_error!(log, "Compose failed"; "error" => e.as_str());
+                    job.state = ComposeState::Failed;
+                    job.error = Some(e);
+                }
+            }
+            job.finished = Some(UTC::now().to_rfc3339());
+            if let Err(e) = write_job(results, job) {
+                error!("Error writing compose"; "id" => id.as_str(), "error" => e.to_string());
+            }
+            info!("Compose finished"; "id" => id.as_str(), "state" => job.state.to_string());
+            slog_info!(log, "Compose finished"; "state" => job.state.to_string());
+        }
+    }
+}
+
+/// Create a logger that writes JSON to the job's log file
+///
+/// If the log cannot be opened the error is logged to the server's log and the job's messages are
+/// discarded.
+fn job_logger(job: &ComposeJob) -> slog::Logger {
+    let log_file = OpenOptions::new()
+                       .create(true)
+                       .append(true)
+                       .open(&job.log);
+    match log_file {
+        Ok(log_file) => {
+            let file_drain = slog_stream::stream(log_file, slog_json::default());
+            slog::Logger::root(file_drain.fuse(), o!("id" => job.id.clone()))
+        },
+        Err(e) => {
+            error!("Error opening compose log"; "id" => job.id.as_str(), "error" => e.to_string());
+            slog::Logger::root(slog::Discard, o!())
+        }
+    }
+}
+
+/// Check whether or not the job is waiting or running
+fn is_active(jobs: &Arc<Mutex<HashMap<String, ComposeJob>>>, id: &str) -> bool {
+    jobs.lock().unwrap().get(id)
+        .map(|job| job.state == ComposeState::Waiting || job.state == ComposeState::Running)
+        .unwrap_or(false)
+}
+
+/// Check whether or not the job has been canceled
+fn is_canceled(jobs: &Arc<Mutex<HashMap<String, ComposeJob>>>, id: &str) -> bool {
+    jobs.lock().unwrap().get(id).map(|job| job.cancel).unwrap_or(true)
+}
+
+/// Run a single compose job
+///
+/// The output is written to `<results>/<id>/` by the job's exporter.
+fn run_compose(jobs: &Arc<Mutex<HashMap<String, ComposeJob>>>, job: &ComposeJob, results: &Path, exporters: &Exporters, log: &slog::Logger) -> Result<(), String> {
+    if job.packages.is_empty() {
+        return Err(format!("Recipe {} has no depsolved packages", job.recipe.name));
+    }
+    let exporter = try_opt!(exporters.iter().find(|e| e.name() == job.compose_type),
+                            Err(format!("Compose type {} is not supported", job.compose_type)));
+
+    for pkg in &job.packages {
+        slog_info!(log, "Compose package"; "package" => pkg.to_string());
+    }
+
+    if is_canceled(jobs, &job.id) {
+        return Err("Canceled".to_string());
+    }
+
+    slog_info!(log, "Running exporter"; "exporter" => exporter.name());
+    let canceled = || is_canceled(jobs, &job.id);
+    let result = exporter.run(&job.frozen, &job.packages, &results.join(&job.id), &canceled)
+                         .map_err(|e| e.to_string());
+
+    // The exporter may have finished without checking for the cancel
+    if canceled() {
+        return Err("Canceled".to_string());
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use compose::*;
+    use db::PackageNEVRA;
+    use export::{ExportError, Exporter, TarExporter};
+    use recipe::Recipe;
+    use test_helper::*;
+
+    /// An exporter that runs until it is canceled
+    struct WaitExporter;
+
+    impl Exporter for WaitExporter {
+        fn name(&self) -> &str {
+            "wait"
+        }
+
+        fn description(&self) -> &str {
+            "Wait to be canceled"
+        }
+
+        fn run(&self, _recipe: &Recipe, _nevras: &[PackageNEVRA], _out_dir: &Path, canceled: &Fn() -> bool) -> Result<(), ExportError> {
+            for _ in 0..100 {
+                if canceled() {
+                    return Err(ExportError::Canceled);
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            Ok(())
+        }
+    }
+
+    fn test_recipe() -> Recipe {
+        Recipe {
+            name:        "compose-test".to_string(),
+            description: None,
+            version:     "0.0.1".to_string(),
+            arch:        vec![],
+            include:     vec![],
+            modules:     vec![],
+            packages:    vec![],
+            exclude:     vec![],
+            groups:      vec![],
+            customizations: None
+        }
+    }
+
+    fn test_package() -> PackageNEVRA {
+        PackageNEVRA {
+            name:    "bash".to_string(),
+            epoch:   0,
+            version: "4.2.46".to_string(),
+            release: "12.el7".to_string(),
+            arch:    "x86_64".to_string()
+        }
+    }
+
+    /// Return a new results directory, with a metadata database holding test_package()
+    fn results_dir() -> PathBuf {
+        let results = env::temp_dir().join(format!("bdcs-compose-test-{}", Uuid::new_v4()));
+        create_dir_all(&results).unwrap();
+
+        create_test_db_file(&results.join("metadata.db"), &[
+            TestData::Groups(TestGroups {
+                files:    vec![test_file("/usr/share/doc/bash", None)],
+                key_vals: vec![test_kv("name", "bash"), test_kv("version", "4.2.46"),
+                               test_kv("release", "12.el7"), test_kv("arch", "x86_64")],
+                .. test_group("bash", "rpm")
+            })]).unwrap();
+        results
+    }
+
+    fn test_queue(results: &Path) -> ComposeQueue {
+        let tar = TarExporter::new(results.join("metadata.db").to_str().unwrap(),
+                                   results.join("cs.repo").to_str().unwrap());
+        ComposeQueue::new(results.to_str().unwrap(), vec![Box::new(tar)]).unwrap()
+    }
+
+    /// Wait for a job to leave the WAITING and RUNNING states
+    fn wait_for(queue: &ComposeQueue, id: &str) -> ComposeStatus {
+        for _ in 0..100 {
+            let status = queue.status(id).unwrap();
+            if status.state == ComposeState::Finished || status.state == ComposeState::Failed {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("Compose {} did not finish", id);
+    }
+
+    #[test]
+    fn test_compose_empty_fails() {
+        let results = results_dir();
+        let queue = test_queue(&results);
+        let status = queue.submit(test_recipe(), test_recipe(), "tar", vec![]).unwrap();
+        assert_eq!(status.recipe, "compose-test");
+
+        let status = wait_for(&queue, &status.id);
+        assert_eq!(status.state, ComposeState::Failed);
+        assert!(status.error.is_some());
+
+        remove_dir_all(results).unwrap();
+    }
+
+    #[test]
+    fn test_compose_finished() {
+        let results = results_dir();
+        let queue = test_queue(&results);
+        let status = queue.submit(test_recipe(), test_recipe(), "tar", vec![test_package()]).unwrap();
+
+        let status = wait_for(&queue, &status.id);
+        assert_eq!(status.state, ComposeState::Finished);
+        assert_eq!(queue.list().len(), 1);
+        assert!(results.join(&status.id).join("root.tar").exists());
+        assert_eq!(queue.types(), vec![("tar".to_string(), "Tar archive of the root filesystem".to_string())]);
+        assert!(queue.has_type("tar"));
+        assert!(!queue.has_type("iso"));
+
+        // Finished jobs cannot be canceled
+        assert!(queue.cancel(&status.id).is_err());
+        assert!(queue.cancel("not-a-real-id").is_err());
+
+        // The job's log has the package list in it
+        let mut log = String::new();
+        queue.log_reader(&status.id, 1, true).unwrap().read_to_string(&mut log).unwrap();
+        assert!(log.contains("Compose started"));
+        assert!(log.contains("bash-4.2.46-12.el7.x86_64"));
+        assert!(log.contains("Compose finished"));
+        assert!(queue.log_reader("not-a-real-id", 1, false).is_err());
+
+        remove_dir_all(results).unwrap();
+    }
+
+    #[test]
+    fn test_compose_cancel_running() {
+        let results = results_dir();
+        let queue = ComposeQueue::new(results.to_str().unwrap(), vec![Box::new(WaitExporter)]).unwrap();
+        let status = queue.submit(test_recipe(), test_recipe(), "wait", vec![test_package()]).unwrap();
+
+        for _ in 0..100 {
+            if queue.status(&status.id).unwrap().state == ComposeState::Running {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(queue.cancel(&status.id).unwrap().state, ComposeState::Running);
+
+        // The exporter stops when it sees the cancel
+        let status = wait_for(&queue, &status.id);
+        assert_eq!(status.state, ComposeState::Failed);
+        assert_eq!(status.error, Some("Canceled".to_string()));
+
+        remove_dir_all(results).unwrap();
+    }
+
+    #[test]
+    fn test_compose_restart() {
+        let results = results_dir();
+        let finished_id = {
+            let queue = test_queue(&results);
+            let status = queue.submit(test_recipe(), test_recipe(), "tar", vec![test_package()]).unwrap();
+            wait_for(&queue, &status.id).id
+        };
+
+        // Write a job that was running when the server stopped
+        let mut job = ComposeJob::new(&results, test_recipe(), test_recipe(), "tar", vec![test_package()]);
+        job.state = ComposeState::Running;
+        write_job(&results, &job).unwrap();
+
+        let queue = test_queue(&results);
+        assert_eq!(queue.list().len(), 2);
+
+        let finished = queue.job(&finished_id).unwrap();
+        assert_eq!(finished.state, ComposeState::Finished);
+        assert_eq!(finished.packages, vec![test_package()]);
+        assert_eq!(finished.frozen, test_recipe());
+        assert_eq!(queue.status(&job.id).unwrap().state, ComposeState::Failed);
+
+        remove_dir_all(results).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+//! Package Group Functions
+//!
+//! ## Overview
+//!
+//! Recipes can list comps-style package groups, eg. `@development-tools`, in their `groups`
+//! section. A group is a list of packages, and can also include other groups. Environment groups
+//! are groups that include other groups, eg. `@developer-workstation`.
+//!
+//! The group definitions are read from a TOML file passed to the server with `--comps`, and from
+//! the `groups` table of the metadata database, where a group has a `group_type` of `comps` and
+//! its members are its child groups. Definitions in the file are used before the database.
+//!
+//! ## Example
+//!
+//! ```toml
+//! [[group]]
+//! id = "development-tools"
+//! description = "A basic development environment"
+//! packages = ["gcc", "gdb", "git", "make"]
+//!
+//! [[environment]]
+//! id = "developer-workstation"
+//! groups = ["development-tools"]
+//! packages = ["vim-enhanced"]
+//! ```
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use rusqlite::{self, Connection};
+use toml;
+
+use db::{get_child_groups, get_groups_name};
+use depclose::DepError;
+
+/// The `group_type` of package groups in the metadata database
+pub const COMPS_GROUP_TYPE: &'static str = "comps";
+
+
+/// Package Group Errors
+#[derive(Debug)]
+pub enum CompsError {
+    IO(io::Error),
+    TomlDe(toml::de::Error),
+    SQLite(rusqlite::Error),
+    /// There is no group with this id
+    UnknownGroup(String),
+    /// The group includes itself, directly or through other groups
+    Loop(String),
+}
+
+impl fmt::Display for CompsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompsError::IO(ref e) => write!(f, "IO error: {}", e),
+            CompsError::TomlDe(ref e) => write!(f, "Error parsing the groups: {}", e),
+            CompsError::SQLite(ref e) => write!(f, "SQLite error: {}", e),
+            CompsError::UnknownGroup(ref id) => write!(f, "Unknown group @{}", id),
+            CompsError::Loop(ref id) => write!(f, "Group @{} includes itself", id),
+        }
+    }
+}
+
+impl From<io::Error> for CompsError {
+    fn from(err: io::Error) -> CompsError {
+        CompsError::IO(err)
+    }
+}
+
+impl From<toml::de::Error> for CompsError {
+    fn from(err: toml::de::Error) -> CompsError {
+        CompsError::TomlDe(err)
+    }
+}
+
+impl From<rusqlite::Error> for CompsError {
+    fn from(err: rusqlite::Error) -> CompsError {
+        CompsError::SQLite(err)
+    }
+}
+
+/// Unknown groups can't be depsolved, like unknown packages
+impl From<CompsError> for DepError {
+    fn from(err: CompsError) -> DepError {
+        match err {
+            CompsError::UnknownGroup(ref id) => DepError::NoPackage(format!("@{}", id)),
+            _ => DepError::Other(err.to_string())
+        }
+    }
+}
+
+
+/// A package group definition
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CompsGroup {
+    pub id: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>
+}
+
+// The layout of the group definitions file
+#[derive(Debug, Default, Deserialize)]
+struct CompsFile {
+    #[serde(default)]
+    group: Vec<CompsGroup>,
+    #[serde(default)]
+    environment: Vec<CompsGroup>
+}
+
+
+/// The package group definitions, used with Rocket's managed state system
+#[derive(Debug, Default)]
+pub struct Comps {
+    groups: HashMap<String, CompsGroup>
+}
+
+impl Comps {
+    /// Create an empty set of definitions, so that only the database groups are used
+    pub fn new() -> Comps {
+        Comps::default()
+    }
+
+    /// Read the group definitions from a TOML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Comps, CompsError> {
+        let mut input = String::new();
+        let mut file = try!(File::open(path));
+        try!(file.read_to_string(&mut input));
+        Comps::from_toml(&input)
+    }
+
+    /// Parse the group definitions from a TOML string
+    pub fn from_toml(input: &str) -> Result<Comps, CompsError> {
+        let comps_file = try!(toml::from_str::<CompsFile>(input));
+        let mut groups = HashMap::new();
+        for group in comps_file.group.into_iter().chain(comps_file.environment.into_iter()) {
+            groups.insert(group.id.clone(), group);
+        }
+        Ok(Comps { groups: groups })
+    }
+
+    /// Find a group definition
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The database connection
+    /// * `name` - The group's id, with or without the leading `@`
+    ///
+    /// # Returns
+    ///
+    /// * The [CompsGroup](struct.CompsGroup.html), or None if there is no group with this id
+    ///
+    pub fn group(&self, conn: &Connection, name: &str) -> Result<Option<CompsGroup>, CompsError> {
+        let id = group_id(name);
+        if let Some(group) = self.groups.get(id) {
+            return Ok(Some(group.clone()));
+        }
+
+        let db_group = try!(get_groups_name(conn, id, 0, -1)).into_iter()
+                           .find(|g| g.name == id && g.group_type == COMPS_GROUP_TYPE);
+        let db_group = match db_group {
+            Some(group) => group,
+            None => return Ok(None)
+        };
+
+        let mut group = CompsGroup { id: id.to_string(), ..Default::default() };
+        for child in try!(get_child_groups(conn, db_group.id)) {
+            if child.group_type == COMPS_GROUP_TYPE {
+                group.groups.push(child.name);
+            } else {
+                group.packages.push(child.name);
+            }
+        }
+        Ok(Some(group))
+    }
+
+    /// Expand a list of groups into the names of their packages
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The database connection
+    /// * `names` - The group ids, with or without the leading `@`
+    ///
+    /// # Returns
+    ///
+    /// * A sorted Vector of the package names, including the packages of the included groups
+    ///
+    /// It is an error if a group is unknown, or if it includes itself.
+    ///
+    pub fn expand(&self, conn: &Connection, names: &[String]) -> Result<Vec<String>, CompsError> {
+        let mut packages = HashSet::new();
+        let mut done = HashSet::new();
+        for name in names {
+            try!(self.expand_group(conn, group_id(name), &mut Vec::new(), &mut done, &mut packages));
+        }
+
+        let mut packages: Vec<String> = packages.into_iter().collect();
+        packages.sort();
+        Ok(packages)
+    }
+
+    // Add a group's packages, and the packages of the groups it includes. `parents` is the list
+    // of groups that included this one, so that loops can be found.
+    fn expand_group(&self, conn: &Connection, id: &str, parents: &mut Vec<String>,
+                    done: &mut HashSet<String>, packages: &mut HashSet<String>) -> Result<(), CompsError> {
+        if parents.iter().any(|p| p == id) {
+            return Err(CompsError::Loop(id.to_string()));
+        }
+        if done.contains(id) {
+            return Ok(());
+        }
+
+        let group = match try!(self.group(conn, id)) {
+            Some(group) => group,
+            None => return Err(CompsError::UnknownGroup(id.to_string()))
+        };
+        packages.extend(group.packages.iter().cloned());
+
+        parents.push(id.to_string());
+        for child in &group.groups {
+            try!(self.expand_group(conn, group_id(child), parents, done, packages));
+        }
+        parents.pop();
+
+        done.insert(id.to_string());
+        Ok(())
+    }
+}
+
+// Strip the leading @ from a group name
+fn group_id(name: &str) -> &str {
+    name.trim_left_matches('@')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use comps::*;
+    use test_helper::*;
+
+    const COMPS_TOML: &'static str = r#"
+[[group]]
+id = "development-tools"
+packages = ["gcc", "make"]
+
+[[group]]
+id = "editors"
+packages = ["emacs", "vim-enhanced"]
+
+[[environment]]
+id = "developer-workstation"
+groups = ["development-tools", "@editors"]
+packages = ["make", "tmux"]
+
+[[group]]
+id = "loop-a"
+groups = ["loop-b"]
+
+[[group]]
+id = "loop-b"
+groups = ["loop-a"]
+"#;
+
+    #[test]
+    fn test_expand_file() {
+        let conn = create_test_db(&[]).unwrap();
+        let comps = Comps::from_toml(COMPS_TOML).unwrap();
+
+        assert_eq!(comps.expand(&conn, &["@development-tools".to_string()]).unwrap(),
+                   vec!["gcc", "make"]);
+        assert_eq!(comps.expand(&conn, &["developer-workstation".to_string()]).unwrap(),
+                   vec!["emacs", "gcc", "make", "tmux", "vim-enhanced"]);
+        assert_eq!(comps.expand(&conn, &[]).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let conn = create_test_db(&[]).unwrap();
+        let comps = Comps::from_toml(COMPS_TOML).unwrap();
+
+        match comps.expand(&conn, &["@not-a-group".to_string()]) {
+            Err(CompsError::UnknownGroup(id)) => assert_eq!(id, "not-a-group"),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+        match comps.expand(&conn, &["@loop-a".to_string()]) {
+            Err(CompsError::Loop(id)) => assert_eq!(id, "loop-a"),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_expand_db() {
+        let tools = TestGroups {
+            children: vec![Box::new(test_group("gcc", "rpm")), Box::new(test_group("make", "rpm"))],
+            .. test_group("development-tools", COMPS_GROUP_TYPE)
+        };
+        let workstation = TestGroups {
+            children: vec![Box::new(test_group("development-tools", COMPS_GROUP_TYPE)),
+                           Box::new(test_group("tmux", "rpm"))],
+            .. test_group("workstation", COMPS_GROUP_TYPE)
+        };
+        let editors = TestGroups {
+            children: vec![Box::new(test_group("nano", "rpm"))],
+            .. test_group("editors", COMPS_GROUP_TYPE)
+        };
+        let conn = create_test_db(&[TestData::Groups(tools), TestData::Groups(workstation),
+                                    TestData::Groups(editors)]).unwrap();
+
+        let comps = Comps::new();
+        assert_eq!(comps.expand(&conn, &["@workstation".to_string()]).unwrap(),
+                   vec!["gcc", "make", "tmux"]);
+        assert_eq!(comps.expand(&conn, &["@editors".to_string()]).unwrap(), vec!["nano"]);
+
+        // The file's groups are used before the database
+        let comps = Comps::from_toml(COMPS_TOML).unwrap();
+        assert_eq!(comps.expand(&conn, &["@editors".to_string()]).unwrap(), vec!["emacs", "vim-enhanced"]);
+
+        // rpm groups are not package groups
+        assert!(comps.group(&conn, "@gcc").unwrap().is_none());
+    }
+}
@@ -7,10 +7,11 @@
 //!
 //! When serializing the structures the id fields are excluded from the results.
 //!
-//! ## TODO
+//! ## Schema version
 //!
-//! The database schema support should be versioned, with the ability
-//! to upgrade older databases to newer schema.
+//! The schema version is stored in the database's `user_version` pragma, and must match
+//! [DB_VERSION](constant.DB_VERSION.html). Older databases can be upgraded with `bdcs-dbmigrate`,
+//! see the [migrate](../migrate/index.html) module.
 //!
 
 // Copyright (C) 2016-2017 Red Hat, Inc.
@@ -32,28 +33,206 @@
 
 use std::collections::HashMap;
 use std::fmt;
-use std::path::PathBuf;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use r2d2;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{self, Connection};
 
-// Database Schema Version that is supported
-const DB_VERSION: u32 = 1;
+use migrate;
+use rpm::Requirement;
+
+/// Database Schema Version that is supported
+pub const DB_VERSION: u32 = 1;
+
+/// How often, in seconds, [DBPool](struct.DBPool.html) checks whether the database file has been replaced
+pub const RELOAD_INTERVAL: u64 = 5;
+
+/// Errors reloading the database
+#[derive(Debug)]
+pub enum ReloadError {
+    SQLite(rusqlite::Error),
+    Pool(r2d2::InitializationError),
+    Timeout(r2d2::GetTimeout),
+    /// The new database's schema version is not supported
+    Unsupported(u32),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReloadError::SQLite(ref e) => write!(f, "SQLite error: {}", e),
+            ReloadError::Pool(ref e) => write!(f, "Error opening the database: {}", e),
+            ReloadError::Timeout(ref e) => write!(f, "Error connecting to the database: {}", e),
+            ReloadError::Unsupported(version) => write!(f, "Unsupported schema version {}, {} is required", version, DB_VERSION),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ReloadError {
+    fn from(err: rusqlite::Error) -> ReloadError {
+        ReloadError::SQLite(err)
+    }
+}
+
+impl From<r2d2::InitializationError> for ReloadError {
+    fn from(err: r2d2::InitializationError) -> ReloadError {
+        ReloadError::Pool(err)
+    }
+}
+
+impl From<r2d2::GetTimeout> for ReloadError {
+    fn from(err: r2d2::GetTimeout) -> ReloadError {
+        ReloadError::Timeout(err)
+    }
+}
+
+// Identifies a version of the database file. The importer replaces the file, so the inode
+// changes, and the mtime catches a file that is rewritten in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DBFileId {
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64
+}
+
+impl DBFileId {
+    fn from_path(path: &Path) -> Option<DBFileId> {
+        fs::metadata(path).ok().map(|m| DBFileId {
+            ino:        m.ino(),
+            mtime:      m.mtime(),
+            mtime_nsec: m.mtime_nsec()
+        })
+    }
+}
+
+struct DBPoolState {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+    file: Option<DBFileId>,
+    // A replacement that was refused, so that it isn't checked on every request
+    rejected: Option<DBFileId>
+}
 
 /// Database pool connection, used with Rocket's managed state system
-pub struct DBPool(r2d2::Pool<SqliteConnectionManager>);
+///
+/// When a connection is requested the database file is checked, at most once every
+/// [RELOAD_INTERVAL](constant.RELOAD_INTERVAL.html) seconds. When it has been replaced, eg. by a
+/// new import, a new pool is opened and swapped in. The old connections stay open until they are
+/// returned. A new database that cannot be opened, or has an unsupported schema version, is not
+/// used.
+pub struct DBPool {
+    path: PathBuf,
+    reload_interval: Duration,
+    last_check: Mutex<Instant>,
+    state: RwLock<DBPoolState>
+}
+
 impl DBPool {
     pub fn new(db_path: &str) -> DBPool {
         // Setup the database pool
-        let db_mgr = SqliteConnectionManager::new(db_path);
-        let db_pool = r2d2::Pool::new(r2d2::Config::default(), db_mgr)
+        let path = PathBuf::from(db_path);
+        let file = DBFileId::from_path(&path);
+        let db_pool = DBPool::create_pool(&path)
                             .expect("Unable to initialize the connection pool.");
-        DBPool(db_pool)
+        DBPool {
+            path: path,
+            reload_interval: Duration::from_secs(RELOAD_INTERVAL),
+            last_check: Mutex::new(Instant::now()),
+            state: RwLock::new(DBPoolState { pool: db_pool, file: file, rejected: None })
+        }
     }
 
+    fn create_pool(path: &Path) -> Result<r2d2::Pool<SqliteConnectionManager>, r2d2::InitializationError> {
+        let db_mgr = SqliteConnectionManager::new(&path.to_string_lossy());
+        r2d2::Pool::new(r2d2::Config::default(), db_mgr)
+    }
+
+    /// Set how often the database file is checked when a connection is requested
+    ///
+    /// The default is [RELOAD_INTERVAL](constant.RELOAD_INTERVAL.html) seconds, and a zero
+    /// interval checks it every time.
+    pub fn set_reload_interval(&mut self, interval: Duration) {
+        self.reload_interval = interval;
+    }
+
+    /// Return a connection, reloading the database first if the file has changed
     pub fn conn(&self) -> r2d2::PooledConnection<SqliteConnectionManager> {
-        self.0.get().unwrap()
+        if self.reload_due() {
+            match self.reload() {
+                Ok(true) => info!("Reloaded the database"; "path" => self.path.to_string_lossy().to_string()),
+                Ok(false) => (),
+                Err(e) => error!("Database was not reloaded"; "path" => self.path.to_string_lossy().to_string(),
+                                                              "error" => e.to_string())
+            }
+        }
+        self.state.read().unwrap().pool.get().unwrap()
+    }
+
+    // Return true if the file has not been checked for reload_interval
+    fn reload_due(&self) -> bool {
+        let mut last_check = self.last_check.lock().unwrap();
+        if last_check.elapsed() < self.reload_interval {
+            return false;
+        }
+        *last_check = Instant::now();
+        true
+    }
+
+    /// Reload the database if the file has been replaced or modified
+    ///
+    /// # Returns
+    ///
+    /// * true if a new pool was swapped in
+    ///
+    /// A missing file is ignored, the importer may be in the middle of replacing it. It is an
+    /// error if the new database cannot be opened or its schema is not supported, and the old pool
+    /// is kept. The error is only returned once for each version of the file.
+    ///
+    pub fn reload(&self) -> Result<bool, ReloadError> {
+        let file = match DBFileId::from_path(&self.path) {
+            Some(file) => file,
+            None => return Ok(false)
+        };
+        {
+            let state = self.state.read().unwrap();
+            if state.file == Some(file) || state.rejected == Some(file) {
+                return Ok(false);
+            }
+        }
+
+        let new_pool = DBPool::open_supported(&self.path);
+
+        let mut state = self.state.write().unwrap();
+        // Another request may have already swapped it in
+        if state.file == Some(file) {
+            return Ok(false);
+        }
+        match new_pool {
+            Ok(pool) => {
+                state.pool = pool;
+                state.file = Some(file);
+                state.rejected = None;
+                Ok(true)
+            },
+            Err(e) => {
+                state.rejected = Some(file);
+                Err(e)
+            }
+        }
+    }
+
+    // Open a new pool, if the database's schema version is supported
+    fn open_supported(path: &Path) -> Result<r2d2::Pool<SqliteConnectionManager>, ReloadError> {
+        let pool = try!(DBPool::create_pool(path));
+        let (_, schema_version, supported) = try!(get_schema_version(&*try!(pool.get())));
+        if !supported {
+            return Err(ReloadError::Unsupported(schema_version));
+        }
+        Ok(pool)
     }
 }
 
@@ -118,6 +297,7 @@ pub struct Files {
     pub file_user: String,
     pub file_group: String,
     pub mtime: i64,
+    pub cs_object: Option<String>,  // Content store checksum, directories have none
 }
 
 /// File attribute types
@@ -347,6 +527,39 @@ pub fn get_group_files_name(conn: &Connection, group_id: i64) -> rusqlite::Resul
     rows.collect()
 }
 
+/// List the files in a package given by group id, including their ownership and content store
+/// object
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `group_id` - The id of the group to query
+///
+/// # Returns
+///
+/// * A Vector of [Files](struct.Files.html) sorted by path
+///
+pub fn get_group_files(conn: &Connection, group_id: i64) -> rusqlite::Result<Vec<Files>> {
+    let mut stmt = try!(conn.prepare("
+        select files.id, files.path, files.file_user, files.file_group, files.mtime, files.cs_object
+        from files, group_files
+        on files.id == group_files.file_id
+        where group_files.group_id == :groupId
+        order by files.path
+    "));
+    let rows = try!(stmt.query_map_named(
+        &[(":groupId", &group_id)],
+        |row| Files {
+            id:         row.get(0),
+            path:       row.get(1),
+            file_user:  row.get(2),
+            file_group: row.get(3),
+            mtime:      row.get(4),
+            cs_object:  row.get(5)
+        }));
+    rows.collect()
+}
+
 /// Fetch groups with a given name
 ///
 /// # Arguments
@@ -908,6 +1121,39 @@ pub fn get_groups_id(conn: &Connection, id: &i64) -> rusqlite::Result<Option<Gro
     }
 }
 
+/// Find the child groups of a group
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `group_id` - The id of the parent [Groups](struct.Groups.html) entry
+///
+/// # Returns
+///
+/// * A Vector of [Groups](struct.Groups.html) that are children of `group_id`, sorted by name
+///
+pub fn get_child_groups(conn: &Connection, group_id: i64) -> rusqlite::Result<Vec<Groups>> {
+    let mut stmt = try!(conn.prepare("
+            select groups.*
+            from groups, group_groups
+            on groups.id == group_groups.child_group_id
+            where group_groups.parent_group_id == :groupId
+            order by groups.name"));
+    let mut rows = try!(stmt.query_named(&[(":groupId", &group_id)]));
+
+    let mut contents = Vec::new();
+    while let Some(row) = rows.next() {
+        let row = try!(row);
+        contents.push(Groups {
+                        id: row.get(0),
+                        name: row.get(1),
+                        group_type: row.get(2),
+                        build_id: row.get_checked(3).unwrap_or(0),
+                    });
+    }
+    Ok(contents)
+}
+
 /// Find all groups matching a vector of group names, using case-insensitive matching
 ///
 /// # Arguments
@@ -1068,8 +1314,101 @@ pub fn get_provider_groups(conn: &Connection, thing: &str) -> rusqlite::Result<V
 }
 
 
+
+// Read the Groups and Requirements columns from a `select groups.*, requirements.*` row
+fn group_requirement_from_row(row: &rusqlite::Row) -> (Groups, Requirements) {
+    (Groups {
+       id: row.get(0),
+       name: row.get(1),
+       group_type: row.get(2),
+       build_id: row.get_checked(3).unwrap_or(0),
+     },
+     Requirements {
+       id: row.get(4),
+       req_language: row.get(5),
+       req_context: row.get(6),
+       req_strength: row.get(7),
+       req_expr: row.get(8),
+     })
+}
+
+/// Get information for everything that requires a given name.  This is the reverse of
+/// [get_provider_groups](fn.get_provider_groups.html).
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `thing` - The thing, any version expression after the name is ignored
+///
+/// # Returns
+///
+/// * A Vector of ([Groups](struct.Groups.html), [Requirements](struct.Requirements.html)) for
+///   the groups with a requirement on `thing`, with or without a version expression. The
+///   requirement is included so that its version can be checked later.
+pub fn get_requirement_groups(conn: &Connection, thing: &str) -> rusqlite::Result<Vec<(Groups, Requirements)>> {
+    let base_thing = thing.split_whitespace().next().unwrap_or("");
+
+    let mut stmt = try!(conn.prepare("
+            select distinct groups.*, requirements.*
+            from groups, group_requirements, requirements
+            on group_requirements.group_id == groups.id and requirements.id == group_requirements.req_id
+            where requirements.req_expr == :thing
+               or substr(requirements.req_expr, 1, length(:thing) + 1) == :thing || ' '"));
+    let rows = try!(stmt.query_map_named(&[(":thing", &base_thing)], group_requirement_from_row));
+    rows.collect()
+}
+
+/// Get the groups that require something provided by a group
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `group_id` - The id of the [Groups](struct.Groups.html) entry that is required
+///
+/// # Returns
+///
+/// * A Vector of [Groups](struct.Groups.html), sorted by name, with a requirement that is
+///   satisfied by one of the group's `rpm-provide` expressions or by one of its files. The group
+///   itself is not included.
+///
+/// The versions are compared with [Requirement::satisfies](../rpm/struct.Requirement.html#method.satisfies),
+/// the same way that the depsolver finds the providers of a requirement.
+pub fn get_requiring_groups(conn: &Connection, group_id: i64) -> rusqlite::Result<Vec<Groups>> {
+    let provides: Vec<Requirement> = try!(get_groups_kv_group_id(conn, group_id))
+                                         .iter()
+                                         .filter(|kv| kv.key_value == "TextKey \"rpm-provide\"")
+                                         .filter_map(|kv| kv.ext_value.as_ref().map(|expr| Requirement::from(expr.as_str())))
+                                         .collect();
+
+    let mut groups = Vec::new();
+    for provide in &provides {
+        for (group, requirement) in try!(get_requirement_groups(conn, &provide.name)) {
+            if provide.satisfies(&Requirement::from(requirement.req_expr.as_str())) {
+                groups.push(group);
+            }
+        }
+    }
+
+    // Requirements on the group's files, these have no version
+    let mut stmt = try!(conn.prepare("
+            select distinct groups.*, requirements.*
+            from groups, group_requirements, requirements, group_files, files
+            on group_requirements.group_id == groups.id and requirements.id == group_requirements.req_id
+               and files.id == group_files.file_id and requirements.req_expr == files.path
+            where group_files.group_id == :group_id"));
+    let rows = try!(stmt.query_map_named(&[(":group_id", &group_id)], group_requirement_from_row));
+    for row in rows {
+        groups.push(try!(row).0);
+    }
+
+    groups.retain(|group| group.id != group_id);
+    groups.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+    groups.dedup_by(|a, b| a.id == b.id);
+    Ok(groups)
+}
+
 // Package NEVRA from a Group's KeyVal entries.
-#[derive(Debug, Clone, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PackageNEVRA {
     pub name:    String,
     pub epoch:   i64,
@@ -1126,6 +1465,22 @@ pub fn pkg_nevra_group_id(conn: &Connection, group_id: i64) -> Option<PackageNEV
     })
 }
 
+/// Get the group id of a package NEVRA
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `nevra` - The package to find
+///
+/// # Returns
+///
+/// * The id of the package's group, or None if it is not in the database
+///
+pub fn get_group_id_nevra(conn: &Connection, nevra: &PackageNEVRA) -> rusqlite::Result<Option<i64>> {
+    let group_ids = try!(get_groups_by_name(conn, &nevra.name, "rpm"));
+    Ok(group_ids.into_iter().find(|id| pkg_nevra_group_id(conn, *id).as_ref() == Some(nevra)))
+}
+
 /// Get package NEVRA's associated with a vec of group ids
 ///
 /// # Arguments
@@ -1276,8 +1631,189 @@ pub fn get_projects_details(conn: &Connection, projects: &[&str]) -> rusqlite::R
 /// supported.
 ///
 pub fn get_schema_version(conn: &Connection) -> rusqlite::Result<(u32, u32, bool)> {
-    let mut stmt = try!(conn.prepare("pragma user_version"));
-    let schema_version = try!(stmt.query_row(&[], |row| row.get(0)));
+    let schema_version = try!(migrate::get_user_version(conn));
 
     Ok((DB_VERSION, schema_version, DB_VERSION == schema_version))
 }
+
+// The tables of schema version 1, copied from bdcs/schema.sql. The later versions are created by
+// applying the migrations.
+const BASE_SCHEMA: &'static str = "
+create table projects (
+    id integer primary key,
+    name text not null unique,
+    summary text not null,
+    description text not null,
+    homepage text,
+    upstream_vcs text not null
+);
+
+create table sources (
+    id integer primary key,
+    project_id integer references projects(id) not null,
+    license text not null,
+    version text not null,
+    source_ref text not null
+);
+create index sources_project_id_idx on sources(project_id);
+
+create table builds (
+    id integer primary key,
+    source_id integer references sources(id) not null,
+    epoch integer default 0,
+    release text not null,
+    arch text not null,
+    build_time text not null,
+    changelog blob not null,
+    build_config_ref text not null,
+    build_env_ref text not null
+);
+create index builds_source_id_idx on builds(source_id);
+
+create table build_signatures (
+    id integer primary key,
+    build_id integer references build(id) not null,
+    signature_type text not null,
+    signature_data blob not null
+);
+create index build_signatures_build_id_idx on build_signatures(build_id);
+
+create table files (
+    id integer primary key,
+    path text not null,
+    file_user text not null,
+    file_group text not null,
+    mtime integer not null,
+    cs_object text
+);
+create index files_path_idx on files(path);
+
+create table build_files (
+    id integer primary key,
+    build_id integer references build(id) not null,
+    file_id integer references files(id) not null
+);
+create index build_files_build_id_idx on build_files(build_id);
+create index build_files_file_id_idx on build_files(file_id);
+
+create table key_val (
+    id integer primary key,
+    key_value text not null,
+    val_value text,
+    ext_value text
+);
+
+create index key_val_key_value_idx on key_val(key_value);
+create index key_val_val_value_idx on key_val(key_value, val_value);
+
+create table project_values (
+    id integer primary key,
+    project_id integer references projects(id) not null,
+    key_val_id integer references key_val(id) not null
+);
+create index project_values_project_id_idx on project_values(project_id);
+create index project_values_key_val_id_idx on project_values(key_val_id);
+
+create table source_key_values (
+    id integer primary key,
+    source_id integer references sources(id) not null,
+    key_val_id integer references key_val(id) not null
+);
+create index source_key_values_source_id_idx on source_key_values(source_id);
+create index source_key_values_key_val_id_idx on source_key_values(key_val_id);
+
+create table build_key_values (
+    id integer primary key,
+    build_id integer references builds(id) not null,
+    key_val_id integer references key_val(id) not null
+);
+create index build_key_values_build_id_idx on build_key_values(build_id);
+create index build_key_values_key_val_id_idx on build_key_values(key_val_id);
+
+create table file_key_values (
+    id integer primary key,
+    file_id integer references files(id) not null,
+    key_val_id integer references key_val(id) not null
+);
+create index file_key_values_file_id_idx on file_key_values(file_id);
+create index file_key_values_key_val_id_idx on file_key_values(key_val_id);
+
+create table groups (
+    id integer primary key,
+    name text not null,
+    group_type text not null,
+    build_id integer references builds(id) null
+);
+create index groups_name_idx on groups(name);
+
+create table group_files (
+    id integer primary key,
+    group_id integer references groups(id) not null,
+    file_id integer references files(id) not null
+);
+create index group_files_group_id_idx on group_files(group_id);
+create index group_files_file_id_idx on group_files(file_id);
+
+create table group_groups (
+    id integer primary key,
+    parent_group_id references groups(id) not null,
+    child_group_id references groups(id) not null
+);
+create index group_groups_parent_group_id_idx on group_groups(parent_group_id);
+create index group_groups_child_group_id_idx on group_groups(child_group_id);
+
+create table group_key_values (
+    id integer primary key,
+    group_id integer references groups(id) not null,
+    key_val_id integer references key_val(id) not null
+);
+create index group_key_values_group_id_idx on group_key_values(group_id);
+create index group_key_values_key_val_id_idx on group_key_values(key_val_id);
+
+create table requirements (
+    id integer primary key,
+    req_language text not null,
+    req_context text not null,
+    req_strength text not null,
+    req_expr text not null
+);
+
+create table group_requirements (
+    id integer primary key,
+    group_id integer references groups(id) not null,
+    req_id integer references requirements(id) not null
+);
+create index group_requirements_group_id_idx on group_requirements(group_id);
+create index group_requirements_req_id_idx on group_requirements(req_id);
+";
+
+/// Create the tables in a new database
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+///
+/// The schema is created at [DB_VERSION](constant.DB_VERSION.html), and the `user_version` is set.
+///
+pub fn create_schema(conn: &Connection) -> rusqlite::Result<()> {
+    create_schema_version(conn, DB_VERSION)
+}
+
+/// Create the tables of an older schema version in a new database
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `version` - The schema version to create, from 1 to [DB_VERSION](constant.DB_VERSION.html)
+///
+/// This is mostly useful for testing the [migrations](../migrate/index.html).
+///
+pub fn create_schema_version(conn: &Connection, version: u32) -> rusqlite::Result<()> {
+    let mut sql = String::from("BEGIN;\n");
+    sql.push_str(BASE_SCHEMA);
+    for migration in migrate::MIGRATIONS.iter().filter(|m| m.version <= version) {
+        sql.push_str(migration.upgrade);
+    }
+    sql.push_str(&format!("pragma user_version = {};\nCOMMIT;\n", version));
+    conn.execute_batch(&sql)
+}
@@ -21,6 +21,7 @@ use rusqlite::Connection;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::str::FromStr;
+use glob;
 use itertools::Itertools;
 
 pub type GroupId = i64;
@@ -49,6 +50,111 @@ impl fmt::Display for DepExpression {
     }
 }
 
+/// Why a set of packages could not be depsolved
+#[derive(Debug, Clone, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+pub enum DepError {
+    /// There is no package with the requested name and version
+    #[serde(rename="no_package")]
+    NoPackage(String),
+    /// Nothing provides a requirement. `chain` is the packages that led to the requirement, starting
+    /// with the requested package.
+    #[serde(rename="missing")]
+    Missing { chain: Vec<String>, requirement: String },
+    /// `package` has an rpm-conflict or rpm-obsolete `key` on `requirement`, which matches `other`
+    #[serde(rename="conflict")]
+    Conflict { package: String, other: String, key: String, requirement: String },
+    /// The package is needed, but it is excluded by the recipe
+    #[serde(rename="excluded")]
+    Excluded(String),
+    #[serde(rename="error")]
+    Other(String)
+}
+
+impl fmt::Display for DepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DepError::NoPackage(ref name) => write!(f, "No package matching {}", name),
+            DepError::Missing { ref chain, ref requirement } =>
+                write!(f, "Unable to satisfy requirement {} needed by {}", requirement, chain.join(" -> ")),
+            DepError::Conflict { ref package, ref other, ref key, ref requirement } =>
+                write!(f, "{} has {} {}, which matches {}", package, key, requirement, other),
+            DepError::Excluded(ref package) => write!(f, "{} is needed, but it is excluded", package),
+            DepError::Other(ref e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl From<String> for DepError {
+    fn from(err: String) -> DepError {
+        DepError::Other(err)
+    }
+}
+
+/// A package to depclose, and the versions of it that may be selected
+///
+/// The version is either a glob, eg. `2.4.*`, that is matched against the package's version and
+/// its version-release, or an rpm-style expression like `>= 2.4`. No version, or `*`, selects
+/// every version.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct PackageSpec {
+    pub name: String,
+    pub version: Option<String>
+}
+
+impl PackageSpec {
+    pub fn new(name: &str, version: Option<&str>) -> PackageSpec {
+        PackageSpec {
+            name: name.to_string(),
+            version: version.map(String::from)
+        }
+    }
+
+    /// Check if a group is a version of the package that may be selected
+    pub fn matches(&self, conn: &Connection, group_id: GroupId) -> Result<bool, String> {
+        let version = match self.version {
+            Some(ref v) if !v.trim().is_empty() && v.trim() != "*" => v.trim(),
+            _ => return Ok(true)
+        };
+
+        let (_, group_req) = try!(group_id_to_requirement(conn, group_id));
+        let evr = match group_req.expr {
+            Some((_, ref evr)) => evr.clone(),
+            None => return Ok(false)
+        };
+
+        if version.starts_with('<') || version.starts_with('>') || version.starts_with('=') {
+            let req = Requirement::from(format!("{} {}", self.name, version).as_str());
+            Ok(group_req.satisfies(&req))
+        } else {
+            let pattern = try!(glob::Pattern::new(version).map_err(|e| format!("Bad version {}: {}", version, e)));
+            Ok(pattern.matches(&evr.version) || pattern.matches(&format!("{}-{}", evr.version, evr.release)))
+        }
+    }
+}
+
+impl fmt::Display for PackageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.version {
+            Some(ref version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name)
+        }
+    }
+}
+
+impl<'a> From<&'a str> for PackageSpec {
+    fn from(name: &str) -> PackageSpec {
+        PackageSpec::new(name, None)
+    }
+}
+
+// The NEVRA of a group as a string, for error messages
+fn group_nevra_string(conn: &Connection, group_id: GroupId) -> String {
+    match pkg_nevra_group_id(conn, group_id) {
+        Some(nevra) => nevra.to_string(),
+        None => format!("group {}", group_id)
+    }
+}
+
 fn group_matches_arch(conn: &Connection, group_id: i64, arches: &[String]) -> bool {
     match get_groups_kv_group_id(conn, group_id) {
         Ok(kvs) => { for kv in kvs {
@@ -202,7 +308,7 @@ fn req_provider_ids(conn: &Connection, arches: &[String], req: &Requirement) ->
 //
 // Calling depclose on A will recurse on B with a parents of {A}, recurse on C with a parents of
 // {A,B}, and then finish since A is already being depclosed in this expression.
-fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, parents: &HashSet<GroupId>, cache: &mut HashMap<GroupId, DepExpression>) -> Result<DepExpression, String> {
+fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, parents: &HashSet<GroupId>, cache: &mut HashMap<GroupId, DepExpression>) -> Result<DepExpression, DepError> {
     fn kv_to_req(kv: &KeyVal) -> Result<Requirement, String> {
         match kv.ext_value {
             Some(ref ext_value) => Ok(Requirement::from(ext_value.as_str())),
@@ -236,7 +342,7 @@ fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, par
                 }
             }
         },
-        Err(e) => return Err(e.to_string())
+        Err(e) => return Err(DepError::Other(e.to_string()))
     };
 
     // look for packages that provide the conflict expressions
@@ -263,7 +369,10 @@ fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, par
 
                 // If there are no providers, that's an error
                 if provider_ids.is_empty() {
-                    return Err(format!("Unable to satisfy requirement {}", r));
+                    return Err(DepError::Missing {
+                        chain:       vec![group_nevra_string(conn, group_id)],
+                        requirement: r.to_string()
+                    });
                 }
 
                 // If any of the provider ids have already been closed over, we're done with this
@@ -275,7 +384,15 @@ fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, par
                 // if the providers are new, recurse over their requirements
                 let mut req_providers: Vec<DepExpression> = Vec::new();
                 for p in &provider_ids {
-                    req_providers.push(try!(depclose_package(conn, arches, *p, &parent_groups_copy, cache)));
+                    match depclose_package(conn, arches, *p, &parent_groups_copy, cache) {
+                        Ok(expr) => req_providers.push(expr),
+                        // Add this package to the chain leading to the missing requirement
+                        Err(DepError::Missing { mut chain, requirement }) => {
+                            chain.insert(0, group_nevra_string(conn, group_id));
+                            return Err(DepError::Missing { chain: chain, requirement: requirement });
+                        },
+                        Err(e) => return Err(e)
+                    }
                 }
 
                 // If only one group comes back as the requirement (i.e., there is only one
@@ -310,7 +427,7 @@ fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, par
                 }
             }
         },
-        Err(e) => return Err(e.to_string())
+        Err(e) => return Err(DepError::Other(e.to_string()))
     };
 
     // Add the package itself to the requirements
@@ -333,38 +450,38 @@ fn depclose_package(conn: &Connection, arches: &[String], group_id: GroupId, par
 ///
 /// * `conn` - The database connection
 /// * `arches` - The package architectures to select. e.g., x86_64, i686
-/// * `package` - The package names to select
+/// * `packages` - The packages to select, and the versions of them that may be selected
 ///
 /// # Returns
 ///
 /// * On success an unsolved DepExpression, describing the selected packages and the packages they
 ///   require and conflict with.
 ///
-/// * On error, a string describing the error. This could be because a package does not exist or
-///   its dependencies cannot be found.
+/// * On error, a DepError describing the problem. This could be because a package does not exist
+///   or its dependencies cannot be found.
 ///
-pub fn close_dependencies(conn: &Connection, arches: &[String], packages: &[String]) -> Result<DepExpression, String> {
+pub fn close_dependencies(conn: &Connection, arches: &[String], packages: &[PackageSpec]) -> Result<DepExpression, DepError> {
     let mut req_list: Vec<DepExpression> = Vec::new();
     let mut cache: HashMap<GroupId, DepExpression> = HashMap::new();
 
     for p in packages {
         // Get all the groups with the given name, and then filter out all those with an invalid
-        // architecture.  This will really only matter when we are called with a library package,
-        // which could have been built for several arches.  Binary packages are typically single
-        // arch.
+        // architecture or version.  The architecture will really only matter when we are called
+        // with a library package, which could have been built for several arches.  Binary packages
+        // are typically single arch.
         let mut group_list = Vec::new();
-        match get_groups_name(conn, p, 0, -1) {
-            Ok(groups) => { if groups.is_empty() {
-                                return Err(format!("No package named {}", p));
-                            }
-
-                            for grp in groups {
-                                if group_matches_arch(conn, grp.id, arches) {
+        match get_groups_name(conn, &p.name, 0, -1) {
+            Ok(groups) => { for grp in groups {
+                                if group_matches_arch(conn, grp.id, arches) && try!(p.matches(conn, grp.id)) {
                                     group_list.push(try!(depclose_package(conn, arches, grp.id, &HashSet::new(), &mut cache)));
                                 }
                             }
                            },
-            Err(e)     => return Err(e.to_string())
+            Err(e)     => return Err(DepError::Other(e.to_string()))
+        }
+
+        if group_list.is_empty() {
+            return Err(DepError::NoPackage(p.to_string()));
         }
 
         // if it's just one thing, don't wrap it
@@ -383,6 +500,145 @@ pub fn close_dependencies(conn: &Connection, arches: &[String], packages: &[Stri
     }
 }
 
+/// Find the packages that must not be installed
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `names` - The names of the packages to exclude, which may be globs like `kernel-debug*`
+///
+/// # Returns
+///
+/// * A `DepExpression::Not` for every build of the matching packages, to be solved along with
+///   the expression from `close_dependencies`. A name that matches nothing is ignored.
+///
+pub fn exclude_packages(conn: &Connection, names: &[String]) -> Result<Vec<DepExpression>, DepError> {
+    let mut ids = Vec::new();
+    for name in names {
+        let groups = try!(get_groups_name(conn, name, 0, -1).map_err(|e| DepError::Other(e.to_string())));
+        ids.extend(groups.iter().map(|grp| grp.id));
+    }
+    ids.sort();
+    ids.dedup();
+
+    Ok(ids.into_iter().map(DepExpression::Not).collect())
+}
+
+/// Explain why an excluded package made the packages unsolvable
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `group_id` - The excluded package that is needed
+///
+/// # Returns
+///
+/// * A DepError::Excluded naming the package
+///
+pub fn explain_exclusion(conn: &Connection, group_id: GroupId) -> DepError {
+    DepError::Excluded(group_nevra_string(conn, group_id))
+}
+
+// The rpm-conflict and rpm-obsolete requirements of a group
+fn conflict_requirements(conn: &Connection, group_id: GroupId) -> Vec<(&'static str, Requirement)> {
+    let mut reqs = Vec::new();
+    if let Ok(kvs) = get_groups_kv_group_id(conn, group_id) {
+        for kv in kvs {
+            let key = match kv.key_value.as_str() {
+                "TextKey \"rpm-conflict\"" => "rpm-conflict",
+                "TextKey \"rpm-obsolete\"" => "rpm-obsolete",
+                _ => continue
+            };
+            if let Some(ref ext_value) = kv.ext_value {
+                reqs.push((key, Requirement::from(ext_value.as_str())));
+            }
+        }
+    }
+    reqs
+}
+
+/// Explain why a package conflicts with a set of packages
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `arches` - The package architectures to select
+/// * `group_id` - The package that could not be added to, or removed from, the solution
+/// * `installed` - The packages selected when the conflict was found
+///
+/// # Returns
+///
+/// * A DepError::Conflict naming the package with the rpm-conflict or rpm-obsolete and the
+///   package it matches, or DepError::Other if no such pair is found.
+///
+pub fn explain_conflict(conn: &Connection, arches: &[String], group_id: GroupId, installed: &[GroupId]) -> DepError {
+    // Check the package's own conflicts first, then those of the installed packages against it
+    let mut pairs: Vec<(GroupId, GroupId)> = installed.iter().map(|id| (group_id, *id)).collect();
+    pairs.extend(installed.iter().map(|id| (*id, group_id)));
+
+    for (package, other) in pairs {
+        if package == other {
+            continue;
+        }
+
+        for (key, req) in conflict_requirements(conn, package) {
+            let matches = if key == "rpm-conflict" {
+                req_provider_ids(conn, arches, &req)
+            } else {
+                req_obsolete_ids(conn, arches, &req)
+            };
+
+            if matches.map(|ids| ids.contains(&other)).unwrap_or(false) {
+                return DepError::Conflict {
+                    package:     group_nevra_string(conn, package),
+                    other:       group_nevra_string(conn, other),
+                    key:         key.to_string(),
+                    requirement: req.to_string()
+                };
+            }
+        }
+    }
+
+    DepError::Other(format!("Conflict resolving {}", group_nevra_string(conn, group_id)))
+}
+
+/// Find the packages that require a package
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `group_ids` - The required groups, eg. every build of a package
+/// * `recursive` - Also find the packages that require those packages, and so on
+///
+/// # Returns
+///
+/// * The requiring groups, sorted by name. The required groups are not included.
+///
+/// Like `depclose_package`, the groups that have already been visited are tracked, so that a
+/// cycle like A requires B, B requires A ends the search instead of repeating it.
+///
+pub fn whatrequires(conn: &Connection, group_ids: &[GroupId], recursive: bool) -> Result<Vec<Groups>, DepError> {
+    let mut visited: HashSet<GroupId> = group_ids.iter().cloned().collect();
+    let mut pending: Vec<GroupId> = group_ids.to_vec();
+    let mut requiring = Vec::new();
+
+    while let Some(group_id) = pending.pop() {
+        let groups = try!(get_requiring_groups(conn, group_id).map_err(|e| DepError::Other(e.to_string())));
+        for group in groups {
+            if !visited.insert(group.id) {
+                continue;
+            }
+            if recursive {
+                pending.push(group.id);
+            }
+            requiring.push(group);
+        }
+    }
+
+    requiring.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+    Ok(requiring)
+}
+
 // Test functions
 // TODO share this between here and tests/db.rs
 #[cfg(test)]
@@ -851,3 +1107,104 @@ mod test_depclose_package {
         assert!(exprcmp(&test_result, &test_data))
     }
 }
+
+#[cfg(test)]
+mod test_close_dependencies {
+    use depclose::*;
+    use test_helper::*;
+
+    #[test]
+    fn test_missing() {
+        let conn = create_test_packages(&[
+            // A requires B, which requires something that doesn't exist
+            testpkg("test-package-A", None, "1.0", "1", "x86_64",
+                    &["test-package-A = 1.0-1"],
+                    &["test-package-B"],
+                    &[],
+                    &[]),
+
+            testpkg("test-package-B", None, "1.0", "1", "x86_64",
+                    &["test-package-B = 1.0-1"],
+                    &["does-not-exist >= 2.0"],
+                    &[],
+                    &[])
+        ]).unwrap();
+        let arches = vec!["x86_64".to_string()];
+
+        assert_eq!(close_dependencies(&conn, &arches, &[PackageSpec::from("test-package-C")]).unwrap_err(),
+                   DepError::NoPackage("test-package-C".to_string()));
+
+        let err = close_dependencies(&conn, &arches, &[PackageSpec::from("test-package-A")]).unwrap_err();
+        assert_eq!(err, DepError::Missing {
+            chain:       vec!["test-package-A-1.0-1.x86_64".to_string(), "test-package-B-1.0-1.x86_64".to_string()],
+            requirement: "does-not-exist >= 2.0".to_string()
+        });
+        assert_eq!(err.to_string(),
+                   "Unable to satisfy requirement does-not-exist >= 2.0 needed by test-package-A-1.0-1.x86_64 -> test-package-B-1.0-1.x86_64");
+    }
+
+    #[test]
+    fn test_versions() {
+        let conn = create_test_packages(&[
+            testpkg("test-package-A", None, "2.4.6", "40.el7", "x86_64", &["test-package-A = 2.4.6-40.el7"], &[], &[], &[]),
+            testpkg("test-package-A", None, "2.2.15", "1.el7", "x86_64", &["test-package-A = 2.2.15-1.el7"], &[], &[], &[]),
+            testpkg("test-package-A", Some(1), "1.0", "1", "x86_64", &["test-package-A = 1:1.0-1"], &[], &[], &[])
+        ]).unwrap();
+        let new_id = get_nevra_group_id(&conn, "test-package-A", None, "2.4.6", "40.el7", "x86_64");
+        let old_id = get_nevra_group_id(&conn, "test-package-A", None, "2.2.15", "1.el7", "x86_64");
+        let epoch_id = get_nevra_group_id(&conn, "test-package-A", Some(1), "1.0", "1", "x86_64");
+
+        let matching = |version: Option<&str>| -> Vec<GroupId> {
+            let spec = PackageSpec::new("test-package-A", version);
+            vec![new_id, old_id, epoch_id].into_iter().filter(|id| spec.matches(&conn, *id).unwrap()).collect()
+        };
+
+        assert_eq!(matching(None), vec![new_id, old_id, epoch_id]);
+        assert_eq!(matching(Some("*")), vec![new_id, old_id, epoch_id]);
+        assert_eq!(matching(Some("2.4.*")), vec![new_id]);
+        assert_eq!(matching(Some("2.2.15")), vec![old_id]);
+        assert_eq!(matching(Some("2.4.6-40.*")), vec![new_id]);
+        assert_eq!(matching(Some(">= 2.4")), vec![new_id, epoch_id]);
+        assert_eq!(matching(Some("< 2.4")), vec![old_id]);
+        assert_eq!(matching(Some("= 1:1.0")), vec![epoch_id]);
+
+        let arches = vec!["x86_64".to_string()];
+        assert_eq!(close_dependencies(&conn, &arches, &[PackageSpec::new("test-package-A", Some("3.*"))]).unwrap_err(),
+                   DepError::NoPackage("test-package-A 3.*".to_string()));
+        assert!(close_dependencies(&conn, &arches, &[PackageSpec::new("test-package-A", Some("2.4.*"))]).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_whatrequires {
+    use db::{Groups, get_requiring_groups};
+    use depclose::*;
+    use test_helper::*;
+
+    fn names(groups: &[Groups]) -> Vec<&str> {
+        groups.iter().map(|g| g.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_whatrequires() {
+        let conn = create_test_packages(&[
+            // A requires B, B requires C, and C requires A
+            testpkg("test-package-A", None, "1.0", "1", "x86_64", &["test-package-A = 1.0-1"], &["test-package-B"], &[], &[]),
+            testpkg("test-package-B", None, "1.0", "1", "x86_64", &["test-package-B = 1.0-1"], &["libc >= 2.0"], &[], &[]),
+            testpkg("test-package-C", None, "2.5", "1", "x86_64", &["test-package-C = 2.5-1", "libc = 2.5"], &["test-package-A"], &[], &[]),
+            // D needs a newer C
+            testpkg("test-package-D", None, "1.0", "1", "x86_64", &["test-package-D = 1.0-1"], &["libc >= 3.0", "test-package-Cx"], &[], &[])
+        ]).unwrap();
+        let group_id_b = get_nevra_group_id(&conn, "test-package-B", None, "1.0", "1", "x86_64");
+        let group_id_c = get_nevra_group_id(&conn, "test-package-C", None, "2.5", "1", "x86_64");
+        let group_id_d = get_nevra_group_id(&conn, "test-package-D", None, "1.0", "1", "x86_64");
+
+        assert_eq!(names(&get_requiring_groups(&conn, group_id_c).unwrap()), vec!["test-package-B"]);
+        assert!(get_requiring_groups(&conn, group_id_d).unwrap().is_empty());
+
+        assert_eq!(names(&whatrequires(&conn, &[group_id_c], false).unwrap()), vec!["test-package-B"]);
+        // The cycle leads back to C, which is left out
+        assert_eq!(names(&whatrequires(&conn, &[group_id_c], true).unwrap()), vec!["test-package-A", "test-package-B"]);
+        assert_eq!(names(&whatrequires(&conn, &[group_id_b], true).unwrap()), vec!["test-package-A", "test-package-C"]);
+    }
+}
@@ -1,147 +1,689 @@
+//! Solve dependency expressions
+//!
+//! ## Overview
+//!
+//! `solve_dependencies` takes the expressions built by
+//! [close_dependencies](../depclose/fn.close_dependencies.html) and finds an assignment of the
+//! group ids that makes every expression true. The groups assigned `true` are the packages to
+//! install.
+//!
+//! The solver is a DPLL search. Unit propagation assigns every `Atom` and `Not` that is required
+//! by the expressions, and simplifies the remaining `And` and `Or` expressions using the
+//! assignments. When only `Or` expressions are left, each alternative of the first one is tried in
+//! turn, backtracking when an alternative leads to a conflict. The assignments made by a failed
+//! alternative are undone using a trail of the group ids it assigned.
+//!
+//! The search can take exponential time, so it gives up with a DepError::Other after trying
+//! `SolverPolicy::max_decisions` alternatives.
+//!
+//! Packages that must not be installed, eg. from a recipe's `exclude` list, are passed as
+//! top-level `Not` expressions from [exclude_packages](../depclose/fn.exclude_packages.html). When
+//! one of them is the reason the expressions can't be solved the error names the excluded package.
+//!
+//! The order the alternatives are tried in is set by a [SolverPolicy](struct.SolverPolicy.html).
+//! The default policy prefers packages whose name matches the requirement, then packages built
+//! for the requested architectures over noarch, then the newest version of a package, and then
+//! the alternative that adds the fewest new packages to the install set.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use db::*;
 use depclose::*;
+use rpm::*;
 
 use rusqlite::Connection;
-use std::collections::HashMap;
-use std::ops::Index;
-
-pub fn solve_dependencies(_conn: &Connection, exprs: &mut Vec<DepExpression>) -> Result<Vec<i64>, String> {
-    let mut assignments = HashMap::new();
-
-    unit_propagation(exprs, &mut assignments);
-
-    // FIXME:  For now, only return results if unit_propagation was able to do everything.  This
-    // should handle most basic cases.  More complicated cases will require real dependency
-    // solving.
-    if exprs.is_empty() {
-        // Take the GroupId -> bool hash map and convert it to just a list of i64.  We only care
-        // about the GroupId for packages that will be installed.
-        let results = assignments.into_iter().filter_map(|x| match x {
-            (i, true) => Some(i),
-            _ => None
-        }).collect();
+use std::collections::{HashMap, HashSet};
+
+type Assignments = HashMap<GroupId, bool>;
 
-        Ok(results)
-    } else {
-        Err(String::from("Unsolved expressions"))
+/// The default number of alternatives to try before giving up
+pub const MAX_DECISIONS: usize = 10000;
+
+/// The preferences used to pick between alternative providers
+#[derive(Debug, Clone)]
+pub struct SolverPolicy {
+    /// The package architectures to select, most preferred first. noarch is always allowed.
+    pub arches: Vec<String>,
+    /// Prefer the providers whose name is the name of the requirement
+    pub name_match: bool,
+    /// Prefer the newest version of a package
+    pub newest: bool,
+    /// Prefer the alternatives that add the fewest packages to the install set
+    pub fewest_packages: bool,
+    /// The number of alternatives to try before giving up
+    pub max_decisions: usize
+}
+
+impl Default for SolverPolicy {
+    fn default() -> SolverPolicy {
+        SolverPolicy {
+            arches:          vec!["x86_64".to_string()],
+            name_match:      true,
+            newest:          true,
+            fewest_packages: true,
+            max_decisions:   MAX_DECISIONS
+        }
     }
 }
 
-fn unit_propagation(exprs: &mut Vec<DepExpression>, assignments: &mut HashMap<GroupId, bool>) -> bool {
-    unit_propagation_helper(exprs, assignments, true)
+/// Find the packages that satisfy a list of dependency expressions
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `policy` - The preferences used to pick between alternatives
+/// * `exprs` - The expressions to solve, eg. from `close_dependencies`
+///
+/// # Returns
+///
+/// * On success, the sorted group ids of the packages to install
+///
+/// * On error, a DepError describing the conflict that could not be resolved, or DepError::Other
+///   if no solution was found within `policy.max_decisions` alternatives
+///
+/// The expressions are moved out of `exprs`, it is empty when the function returns.
+///
+/// A `Not` in the top level of `exprs` is an excluded package, and the error is a
+/// DepError::Excluded when the solve fails because it is needed.
+///
+pub fn solve_dependencies(conn: &Connection, policy: &SolverPolicy, exprs: &mut Vec<DepExpression>) -> Result<Vec<i64>, DepError> {
+    let excluded: HashSet<GroupId> = exprs.iter().filter_map(|e| match *e {
+        DepExpression::Not(id) => Some(id),
+        _ => None
+    }).collect();
+
+    let mut search = Search {
+        conn:        conn,
+        policy:      policy,
+        assignments: Assignments::new(),
+        trail:       Vec::new(),
+        decisions:   0
+    };
+    let all_exprs: Vec<DepExpression> = exprs.drain(..).collect();
+    match solve(&mut search, &all_exprs) {
+        Ok(_) => (),
+        Err(SolveError::TooComplex) => return Err(DepError::Other(format!("Dependencies are too complex, no solution was found after trying {} alternatives", policy.max_decisions))),
+        Err(SolveError::Conflict(Conflict { group_id: Some(id), .. })) if excluded.contains(&id) => return Err(explain_exclusion(conn, id)),
+        Err(SolveError::Conflict(Conflict { group_id: Some(id), installed, .. })) => return Err(explain_conflict(conn, &policy.arches, id, &installed)),
+        Err(SolveError::Conflict(Conflict { expr, .. })) => return Err(DepError::Other(format!("Conflict resolving {}", expr)))
+    };
+
+    // Take the GroupId -> bool hash map and convert it to just a list of i64.  We only care
+    // about the GroupId for packages that will be installed.
+    let mut results: Vec<i64> = search.assignments.into_iter().filter_map(|x| match x {
+        (i, true) => Some(i),
+        _ => None
+    }).collect();
+    results.sort();
+
+    Ok(results)
 }
 
-// if assign is true, when a unit is found, process it and add it to the cache so that it will be propagated
-// if assign is false, propagate units from the cache but do not add new units to the cache
-// In short, assign is false when processing an OR. Given an expression like:
+// Where the search failed
 //
-//    And(A, Not(B), Or(A, And(D, C), E), Or(D, E))
+// The conflicting group, if there is one, and the packages that were installed at the time are
+// used to explain the failure once the search is over.
+#[derive(Debug)]
+struct Conflict {
+    group_id:  Option<GroupId>,
+    installed: Vec<GroupId>,
+    expr:      String
+}
+
+// Why the search stopped without a solution
+#[derive(Debug)]
+enum SolveError {
+    Conflict(Conflict),
+    TooComplex
+}
+
+impl From<Conflict> for SolveError {
+    fn from(conflict: Conflict) -> SolveError {
+        SolveError::Conflict(conflict)
+    }
+}
+
+// The state of the search
 //
-// the first pass might result in something like:
+// Every group id that is assigned is pushed onto the trail, so that a failed alternative can be
+// undone by popping the trail back to where it was when the alternative was picked.
+struct Search<'a> {
+    conn:        &'a Connection,
+    policy:      &'a SolverPolicy,
+    assignments: Assignments,
+    trail:       Vec<GroupId>,
+    decisions:   usize
+}
+
+impl<'a> Search<'a> {
+    fn assign(&mut self, id: GroupId, value: bool) {
+        self.assignments.insert(id, value);
+        self.trail.push(id);
+    }
+
+    // Remove the assignments made after the trail was `mark` long
+    fn undo(&mut self, mark: usize) {
+        for id in self.trail.drain(mark..) {
+            self.assignments.remove(&id);
+        }
+    }
+
+    fn conflict(&self, expr: &DepExpression) -> Conflict {
+        let mut installed: Vec<GroupId> = self.assignments.iter().filter(|&(_, v)| *v).map(|(id, _)| *id).collect();
+        installed.sort();
+        Conflict {
+            group_id:  falsified_group(expr, &self.assignments),
+            installed: installed,
+            expr:      expr.to_string()
+        }
+    }
+}
+
+// Find an atom that makes an expression false
+fn falsified_group(expr: &DepExpression, assignments: &Assignments) -> Option<GroupId> {
+    match *expr {
+        DepExpression::Atom(id) if assignments.get(&id) == Some(&false) => Some(id),
+        DepExpression::Not(id) if assignments.get(&id) == Some(&true) => Some(id),
+        DepExpression::And(ref lst) | DepExpression::Or(ref lst) => lst.iter().filter_map(|e| falsified_group(e, assignments)).next(),
+        _ => None
+    }
+}
+
+// The result of simplifying an expression using the current assignments
+#[derive(Debug)]
+enum Simplified {
+    True,
+    False,
+    Expr(DepExpression)
+}
+
+// Replace the assigned atoms in an expression with their values
 //
-//    A=true, B=false;
-//    And(Or(A, And(D, C), E), Or(D, E))
+// An And is false as soon as one of its members is false, an Or is true as soon as one of its
+// members is true. Members that no longer change the value are dropped, and lists with a single
+// member are replaced by that member.
+fn simplify(expr: &DepExpression, assignments: &Assignments) -> Simplified {
+    match *expr {
+        DepExpression::Atom(ref id) => match assignments.get(id) {
+            Some(&true)  => Simplified::True,
+            Some(&false) => Simplified::False,
+            None         => Simplified::Expr(expr.clone())
+        },
+
+        DepExpression::Not(ref id) => match assignments.get(id) {
+            Some(&true)  => Simplified::False,
+            Some(&false) => Simplified::True,
+            None         => Simplified::Expr(expr.clone())
+        },
+
+        DepExpression::And(ref and_list) => {
+            let mut members = Vec::new();
+            for e in and_list {
+                match simplify(e, assignments) {
+                    Simplified::True  => (),
+                    Simplified::False => return Simplified::False,
+                    // Nested Ands are just more members of this one
+                    Simplified::Expr(DepExpression::And(nested)) => members.extend(nested),
+                    Simplified::Expr(e) => members.push(e)
+                }
+            }
+
+            match members.len() {
+                0 => Simplified::True,
+                1 => Simplified::Expr(members.remove(0)),
+                _ => Simplified::Expr(DepExpression::And(members))
+            }
+        },
+
+        DepExpression::Or(ref or_list) => {
+            let mut members = Vec::new();
+            for e in or_list {
+                match simplify(e, assignments) {
+                    Simplified::True  => return Simplified::True,
+                    Simplified::False => (),
+                    Simplified::Expr(DepExpression::Or(nested)) => members.extend(nested),
+                    Simplified::Expr(e) => members.push(e)
+                }
+            }
+
+            match members.len() {
+                0 => Simplified::False,
+                1 => Simplified::Expr(members.remove(0)),
+                _ => Simplified::Expr(DepExpression::Or(members))
+            }
+        }
+    }
+}
+
+// Assign every unit in the expressions until nothing changes
 //
-// Then, when processing the Or(A, And(D, C), E), the A can be removed because we know it's true,
-// but we shouldn't do anything with And(D, C), since messing with D will mess with the Or(D, E)
-// branch. The And(D, C) portion could be false and the parent Or() expression can still be true,
-// so don't try to infer anything from those values.
-fn unit_propagation_helper(exprs: &mut Vec<DepExpression>, assignments: &mut HashMap<GroupId, bool>, assign: bool) -> bool {
-    let mut ever_changed = false;
+// The expressions are not modified, the simplified copies are returned. Returns the expressions
+// that still need a decision, which are all Ors, or an error if an expression became false.
+fn unit_propagation<'e, I>(exprs: I, search: &mut Search) -> Result<Vec<DepExpression>, Conflict>
+    where I: IntoIterator<Item=&'e DepExpression> {
+    let mut simplified_exprs = Vec::new();
+    for expr in exprs {
+        match simplify(expr, &search.assignments) {
+            Simplified::True  => (),
+            Simplified::False => return Err(search.conflict(expr)),
+            Simplified::Expr(simplified) => simplified_exprs.push(simplified)
+        }
+    }
+    let mut exprs = simplified_exprs;
 
     loop {
-        let mut indices_to_remove = Vec::new();
-        let mut indices_to_replace = Vec::new();
         let mut changed = false;
+        let mut remaining = Vec::new();
+        let mut work = exprs;
 
-        for (i, val) in exprs.into_iter().enumerate() {
-            match *val {
-                DepExpression::Atom(ref id) => {
-                    if !assignments.contains_key(id) {
-                        if assign {
-                            assignments.insert(*id, true);
-                            changed = true;
-                            indices_to_remove.push(i);
-                        }
-                    } else if assignments.get(id) == Some(&true) {
-                        changed = true;
-                        indices_to_remove.push(i);
-                    } else {
-                        panic!("conflict resolving {}", id);
-                    }
-                },
-
-                DepExpression::Not(ref id) => {
-                    if !assignments.contains_key(id) {
-                        if assign {
-                            assignments.insert(*id, false);
-                            changed = true;
-                            indices_to_remove.push(i);
-                        }
-                    } else if assignments.get(id) == Some(&false) {
-                        changed = true;
-                        indices_to_remove.push(i);
-                    } else {
-                        panic!("conflict resolving {}", id);
-                    }
-                    // TODO else?
-                },
-
-                DepExpression::And(ref mut and_list) => {
-                    // recurse on this list of expressions
-                    if unit_propagation_helper(and_list, assignments, assign) {
-                        changed = true;
-                    }
-
-                    // if there's only one thing left in the list, the And is actually just that
-                    // thing.
-                    if and_list.len() == 1 {
-                        indices_to_replace.push(i);
-                        changed = true;
-                    } else if and_list.is_empty() {
-                        indices_to_remove.push(i);
-                        changed = true;
-                    }
-                },
-
-                DepExpression::Or(ref mut or_list) => {
-                    // For or, check if there's only one thing first, so we don't waste time
-                    // processing the child with assign=false just to (potentially) redo it with
-                    // assign=true
-                    if or_list.len() == 1 {
-                        indices_to_replace.push(i);
-                        changed = true;
-                    } else if or_list.is_empty() {
-                        indices_to_remove.push(i);
+        while let Some(expr) = work.pop() {
+            match simplify(&expr, &search.assignments) {
+                Simplified::True  => (),
+                Simplified::False => return Err(search.conflict(&expr)),
+                Simplified::Expr(simplified) => match simplified {
+                    DepExpression::Atom(id) => {
+                        search.assign(id, true);
                         changed = true;
-                    // Can't get rid of it completely, so look for known units inside the Or that can be removed
-                    } else if unit_propagation_helper(or_list, assignments, false) {
+                    },
+                    DepExpression::Not(id) => {
+                        search.assign(id, false);
                         changed = true;
-                    }
+                    },
+                    DepExpression::And(and_list) => work.extend(and_list),
+                    or => remaining.push(or)
                 }
             }
         }
 
-        for i in indices_to_replace {
-            let expr = match *exprs.index(i) {
-                DepExpression::And(ref and_list) => and_list.index(0).clone(),
-                DepExpression::Or(ref or_list)   => or_list.index(0).clone(),
-                _ => unreachable!()
-            };
-            exprs.remove(i);
-            exprs.insert(i, expr);
+        // Assignments made while processing later expressions may simplify the earlier ones
+        remaining.reverse();
+        if !changed {
+            return Ok(remaining);
+        }
+        exprs = remaining;
+    }
+}
+
+// The group id of the package an alternative installs
+//
+// close_dependencies puts the package's own Atom after its requirements.
+fn alternative_group(expr: &DepExpression) -> Option<GroupId> {
+    match *expr {
+        DepExpression::Atom(id) => Some(id),
+        DepExpression::And(ref and_list) => and_list.iter().rev().filter_map(|e| match *e {
+            DepExpression::Atom(id) => Some(id),
+            _ => None
+        }).next(),
+        _ => None
+    }
+}
+
+// Collect the unassigned group ids an alternative would install
+fn new_atoms(expr: &DepExpression, assignments: &Assignments, atoms: &mut HashSet<GroupId>) {
+    match *expr {
+        DepExpression::Atom(id) => if !assignments.contains_key(&id) {
+            atoms.insert(id);
+        },
+        DepExpression::Not(_) => (),
+        DepExpression::And(ref lst) | DepExpression::Or(ref lst) => for e in lst {
+            new_atoms(e, assignments, atoms);
         }
+    }
+}
+
+// The names of the rpm-provides of a group
+fn group_provides(conn: &Connection, group_id: GroupId) -> HashSet<String> {
+    match get_groups_kv_group_id(conn, group_id) {
+        Ok(kvs) => kvs.iter()
+                      .filter(|kv| kv.key_value == "TextKey \"rpm-provide\"")
+                      .filter_map(|kv| kv.ext_value.as_ref())
+                      .map(|ext_value| Requirement::from(ext_value.as_str()).name)
+                      .collect(),
+        Err(_) => HashSet::new()
+    }
+}
 
-        indices_to_remove.sort_by(|a, b| b.cmp(a));
-        for i in indices_to_remove {
-            exprs.remove(i);
+fn nevra_evr(nevra: &PackageNEVRA) -> EVR {
+    EVR {
+        epoch:   if nevra.epoch == 0 { None } else { Some(nevra.epoch as u32) },
+        version: nevra.version.clone(),
+        release: nevra.release.clone()
+    }
+}
+
+// Sort the alternatives of an Or into the order they should be tried in
+//
+// The sort is stable, so alternatives that the policy has no preference between are tried in the
+// order close_dependencies returned them.
+fn order_alternatives(conn: &Connection, policy: &SolverPolicy, alternatives: Vec<DepExpression>, assignments: &Assignments) -> Vec<DepExpression> {
+    let nevras: Vec<Option<PackageNEVRA>> = alternatives.iter()
+                                                        .map(|alt| alternative_group(alt).and_then(|id| pkg_nevra_group_id(conn, id)))
+                                                        .collect();
+
+    // The requirement is something every alternative provides. Every package provides its own name,
+    // so the providers whose name is in all of the provides are the ones named for the requirement.
+    let mut common_provides: Option<HashSet<String>> = None;
+    if policy.name_match {
+        for id in alternatives.iter().filter_map(alternative_group) {
+            let provides = group_provides(conn, id);
+            common_provides = Some(match common_provides {
+                Some(common) => common.intersection(&provides).cloned().collect(),
+                None => provides
+            });
         }
+    }
 
-        if !changed {
-            break;
+    let mut keyed: Vec<((bool, usize, usize, usize), DepExpression)> = alternatives.into_iter().enumerate().map(|(i, alt)| {
+        let name_match = match (&nevras[i], &common_provides) {
+            (&Some(ref nevra), &Some(ref common)) => common.contains(&nevra.name),
+            _ => false
+        };
+
+        let arch_rank = match nevras[i] {
+            Some(ref nevra) if nevra.arch == "noarch" => policy.arches.len(),
+            Some(ref nevra) => policy.arches.iter().position(|a| *a == nevra.arch).unwrap_or(policy.arches.len() + 1),
+            None => policy.arches.len() + 1
+        };
+
+        // How many versions of the same package are newer than this one
+        let newer = match nevras[i] {
+            Some(ref nevra) if policy.newest => {
+                let evr = nevra_evr(nevra);
+                nevras.iter()
+                      .filter_map(|n| n.as_ref())
+                      .filter(|n| n.name == nevra.name && nevra_evr(n) > evr)
+                      .count()
+            },
+            _ => 0
+        };
+
+        let added = if policy.fewest_packages {
+            let mut atoms = HashSet::new();
+            new_atoms(&alt, assignments, &mut atoms);
+            atoms.len()
         } else {
-            ever_changed = true;
+            0
+        };
+
+        ((!name_match, arch_rank, newer, added), alt)
+    }).collect();
+
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|(_, alt)| alt).collect()
+}
+
+// An Or that the search picked an alternative for
+//
+// The alternatives that have not been tried yet are popped from the end of `alternatives`. Each
+// one is propagated along with `rest`, the expressions that were left after the Or.
+struct Choice {
+    mark:         usize,
+    alternatives: Vec<DepExpression>,
+    rest:         Vec<DepExpression>
+}
+
+// Propagate the units, then pick an alternative for the first remaining Or and repeat
+//
+// The open choices are kept on a stack rather than by recursing, so the depth of the search is
+// only limited by max_decisions. When an alternative leads to a conflict its assignments are
+// undone using the trail and the next alternative is tried, and when a choice runs out of
+// alternatives the conflict fails the alternative that led to it. On success the solution is left
+// in search.assignments. Picking the alternative that adds the fewest packages at each step keeps
+// the install set small, but it is not guaranteed to be the smallest possible set.
+fn solve(search: &mut Search, exprs: &[DepExpression]) -> Result<(), SolveError> {
+    let mut choices: Vec<Choice> = Vec::new();
+    let mut propagated = unit_propagation(exprs.iter(), search);
+
+    loop {
+        match propagated {
+            Ok(mut remaining) => {
+                if remaining.is_empty() {
+                    return Ok(());
+                }
+
+                let alternatives = match remaining.remove(0) {
+                    DepExpression::Or(or_list) => order_alternatives(search.conn, search.policy, or_list, &search.assignments),
+                    _ => unreachable!()
+                };
+                choices.push(Choice {
+                    mark:         search.trail.len(),
+                    alternatives: alternatives.into_iter().rev().collect(),
+                    rest:         remaining
+                });
+            },
+            Err(conflict) => loop {
+                // Undo the failed alternative, and drop the choices that have none left
+                match choices.last_mut() {
+                    Some(choice) => {
+                        search.undo(choice.mark);
+                        if !choice.alternatives.is_empty() {
+                            break;
+                        }
+                    },
+                    None => return Err(SolveError::Conflict(conflict))
+                }
+                choices.pop();
+            }
+        }
+
+        search.decisions += 1;
+        if search.decisions > search.policy.max_decisions {
+            return Err(SolveError::TooComplex);
         }
+
+        // An Or always has more than one alternative, and exhausted choices were dropped above
+        let choice = choices.last_mut().unwrap();
+        let alternative = choice.alternatives.pop().unwrap();
+        propagated = unit_propagation(Some(&alternative).into_iter().chain(choice.rest.iter()), search);
     }
+}
 
-    ever_changed
+#[cfg(test)]
+mod tests {
+    use depclose::DepExpression::*;
+    use depsolve::*;
+    use test_helper::*;
+
+    fn solve_exprs(exprs: Vec<DepExpression>) -> Result<Vec<i64>, DepError> {
+        let conn = create_test_db(&[]).unwrap();
+        let mut exprs = exprs;
+        solve_dependencies(&conn, &SolverPolicy::default(), &mut exprs)
+    }
+
+    #[test]
+    fn test_solve_units() {
+        assert_eq!(solve_exprs(vec![And(vec![Atom(1), Not(2), Atom(3)])]), Ok(vec![1, 3]));
+        assert_eq!(solve_exprs(vec![]), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_solve_or() {
+        // Either provider will do, the first one is picked
+        assert_eq!(solve_exprs(vec![And(vec![Atom(1), Or(vec![Atom(2), Atom(3)])])]), Ok(vec![1, 2]));
+
+        // An Or that is already satisfied by a unit doesn't add anything
+        assert_eq!(solve_exprs(vec![And(vec![Or(vec![Atom(2), Atom(3)]), Atom(3)])]), Ok(vec![3]));
+    }
+
+    #[test]
+    fn test_solve_backtrack() {
+        // 2 requires 4, which conflicts with 1, so 3 has to be picked instead
+        let exprs = vec![And(vec![Not(4),
+                                  Atom(1),
+                                  Or(vec![And(vec![Atom(2), Atom(4)]),
+                                          And(vec![Atom(3), Atom(5)])])])];
+        assert_eq!(solve_exprs(exprs), Ok(vec![1, 3, 5]));
+
+        // Two Ors that have to agree with each other
+        let exprs = vec![Or(vec![And(vec![Atom(1), Not(3)]), Atom(2)]),
+                         Or(vec![Atom(3), Not(2)]),
+                         Or(vec![Not(1), Atom(4)])];
+        assert_eq!(solve_exprs(exprs), Ok(vec![1, 4]));
+    }
+
+    #[test]
+    fn test_solve_many_decisions() {
+        // Every Or needs its own decision, the search must not run out of stack
+        let exprs: Vec<DepExpression> = (0..5000).map(|i| Or(vec![Atom(i * 2 + 1), Atom(i * 2 + 2)])).collect();
+        let expected: Vec<i64> = (0..5000).map(|i| i * 2 + 1).collect();
+        assert_eq!(solve_exprs(exprs), Ok(expected));
+    }
+
+    #[test]
+    fn test_solve_too_complex() {
+        let conn = create_test_db(&[]).unwrap();
+        let policy = SolverPolicy { max_decisions: 1, .. SolverPolicy::default() };
+
+        // Picking 1 needs a second decision to find out that 3 and 4 can't be satisfied
+        let exprs = vec![Or(vec![Atom(1), Atom(2)]),
+                         Or(vec![Not(1), Atom(3), Atom(4)]),
+                         Or(vec![Not(1), Atom(3), Not(4)]),
+                         Or(vec![Not(1), Not(3), Atom(4)]),
+                         Or(vec![Not(1), Not(3), Not(4)])];
+        match solve_dependencies(&conn, &policy, &mut exprs.clone()) {
+            Err(DepError::Other(ref e)) => assert!(e.contains("too complex")),
+            r => panic!("Unexpected result {:?}", r)
+        }
+        assert_eq!(solve_dependencies(&conn, &SolverPolicy::default(), &mut exprs.clone()), Ok(vec![2]));
+
+        // A solution found on the first try is within the budget
+        let mut exprs = vec![And(vec![Atom(1), Or(vec![Atom(2), Atom(3)])])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_solve_policy() {
+        let conn = create_test_packages(&[
+            testpkg("libfoo", None, "1.0", "1", "x86_64", &["libfoo = 1.0-1", "libfoo.so.1"], &[], &[], &[]),
+            testpkg("libfoo", None, "1.2", "1", "x86_64", &["libfoo = 1.2-1", "libfoo.so.1"], &[], &[], &[]),
+            testpkg("compat-libfoo", None, "1.0", "1", "x86_64", &["compat-libfoo = 1.0-1", "libfoo.so.1", "libfoo"], &[], &[], &[]),
+            testpkg("foo-data", None, "2.0", "1", "noarch", &["foo-data = 2.0-1", "foo-data"], &[], &[], &[]),
+            testpkg("foo-data", None, "1.0", "1", "x86_64", &["foo-data = 1.0-1", "foo-data"], &[], &[], &[]),
+            testpkg("bar", None, "1.0", "1", "x86_64", &["bar = 1.0-1"], &[], &[], &[])
+        ]).unwrap();
+        let policy = SolverPolicy::default();
+
+        // The newest version is picked
+        let mut exprs = vec![Or(vec![Atom(1), Atom(2)])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![2]));
+
+        // compat-libfoo provides libfoo too, but the package named libfoo is preferred
+        let mut exprs = vec![Or(vec![Atom(3), Atom(1)])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![1]));
+
+        // The requested arch is preferred over noarch, even when noarch is newer
+        let mut exprs = vec![Or(vec![Atom(4), Atom(5)])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![5]));
+
+        // The alternative with fewer packages is picked
+        let mut exprs = vec![Or(vec![And(vec![Atom(6), Atom(5)]), Atom(3)])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![3]));
+
+        // Without any preferences, the first alternative is tried first
+        let policy = SolverPolicy {
+            arches:          vec!["x86_64".to_string()],
+            name_match:      false,
+            newest:          false,
+            fewest_packages: false,
+            max_decisions:   MAX_DECISIONS
+        };
+        let mut exprs = vec![Or(vec![Atom(3), Atom(1)])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![3]));
+        let mut exprs = vec![Or(vec![Atom(1), Atom(2)])];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_solve_conflict() {
+        assert!(solve_exprs(vec![And(vec![Atom(1), Not(1)])]).is_err());
+
+        let exprs = vec![And(vec![Atom(1),
+                                  Or(vec![And(vec![Atom(2), Not(1)]),
+                                          And(vec![Atom(3), Not(1)])])])];
+        assert!(solve_exprs(exprs).is_err());
+    }
+
+    #[test]
+    fn test_solve_excluded() {
+        let conn = create_test_packages(&[
+            testpkg("test-package-A", None, "1.0", "1", "x86_64",
+                    &["test-package-A = 1.0-1"],
+                    &["test-package-B"],
+                    &[],
+                    &[]),
+
+            testpkg("test-package-B", None, "1.0", "1", "x86_64",
+                    &["test-package-B = 1.0-1"],
+                    &[],
+                    &[],
+                    &[]),
+
+            testpkg("test-package-C", None, "1.0", "1", "x86_64",
+                    &["test-package-C = 1.0-1"],
+                    &[],
+                    &[],
+                    &[])
+        ]).unwrap();
+        let policy = SolverPolicy::default();
+
+        // Excluding a package that isn't needed doesn't change the result
+        let mut exprs = vec![close_dependencies(&conn, &policy.arches, &[PackageSpec::from("test-package-A")]).unwrap()];
+        exprs.extend(exclude_packages(&conn, &["test-package-C".to_string()]).unwrap());
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs), Ok(vec![1, 2]));
+
+        // B is required by A
+        let mut exprs = vec![close_dependencies(&conn, &policy.arches, &[PackageSpec::from("test-package-A")]).unwrap()];
+        exprs.extend(exclude_packages(&conn, &["test-package-B".to_string()]).unwrap());
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs),
+                   Err(DepError::Excluded("test-package-B-1.0-1.x86_64".to_string())));
+
+        // The names are globs
+        let mut exprs = vec![close_dependencies(&conn, &policy.arches, &[PackageSpec::from("test-package-C")]).unwrap()];
+        exprs.extend(exclude_packages(&conn, &["test-package-[BC]".to_string()]).unwrap());
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs),
+                   Err(DepError::Excluded("test-package-C-1.0-1.x86_64".to_string())));
+    }
+
+    #[test]
+    fn test_solve_explain_conflict() {
+        let conn = create_test_packages(&[
+            testpkg("test-package-A", None, "1.0", "1", "x86_64",
+                    &["test-package-A = 1.0-1"],
+                    &[],
+                    &[],
+                    &["test-package-B"]),
+
+            testpkg("test-package-B", None, "1.0", "1", "x86_64",
+                    &["test-package-B = 1.0-1"],
+                    &[],
+                    &[],
+                    &[])
+        ]).unwrap();
+        let policy = SolverPolicy::default();
+
+        let mut exprs = vec![close_dependencies(&conn, &policy.arches, &[PackageSpec::from("test-package-A"), PackageSpec::from("test-package-B")]).unwrap()];
+        assert_eq!(solve_dependencies(&conn, &policy, &mut exprs),
+                   Err(DepError::Conflict {
+                       package:     "test-package-A-1.0-1.x86_64".to_string(),
+                       other:       "test-package-B-1.0-1.x86_64".to_string(),
+                       key:         "rpm-conflict".to_string(),
+                       requirement: "test-package-B".to_string()
+                   }));
+    }
 }
@@ -0,0 +1,357 @@
+//! Export packages from the content store
+//!
+//! ## Overview
+//!
+//! These functions take the group ids of a depsolved set of packages, look up their files in the
+//! metadata database, and write them, with their owner, group, mode and mtime, to an image.
+//!
+//! Each type of image is created by an [Exporter](trait.Exporter.html). The exporters are
+//! registered with the [ComposeQueue](../compose/struct.ComposeQueue.html) when the server starts,
+//! and `/compose/types` lists the registered exporters.
+//!
+//! The file contents are read from the content store. It is an ostree repository in `bare` mode,
+//! so the content of a file with the checksum `cs_object` is found in
+//! `<bdcs_path>/objects/<first 2 characters>/<remaining characters>.file`.
+//! Directories do not have a content store object.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{File, read_link, symlink_metadata};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{self, Connection};
+use tar::{Builder, EntryType, Header};
+
+use db::{Files, PackageNEVRA, get_group_files, get_group_id_nevra};
+use recipe::Recipe;
+
+
+/// Export function errors
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    SQLite(rusqlite::Error),
+    MissingPackage(String),
+    MissingObject(String, String),
+    Canceled,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExportError::Io(ref e) => write!(f, "IO error: {}", e),
+            ExportError::SQLite(ref e) => write!(f, "Database error: {}", e),
+            ExportError::MissingPackage(ref nevra) => write!(f, "Package {} is not in the database", nevra),
+            ExportError::MissingObject(ref path, ref object) => write!(f, "Content for {} ({}) is not in the content store", path, object),
+            ExportError::Canceled => write!(f, "Canceled"),
+        }
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> ExportError {
+        ExportError::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for ExportError {
+    fn from(err: rusqlite::Error) -> ExportError {
+        ExportError::SQLite(err)
+    }
+}
+
+
+/// An output type for composes
+pub trait Exporter: Send + Sync {
+    /// The compose type, eg. 'tar'
+    fn name(&self) -> &str;
+
+    /// A short description of the output
+    fn description(&self) -> &str;
+
+    /// Create the output
+    ///
+    /// # Arguments
+    ///
+    /// * `recipe` - The recipe, with the versions of its modules and packages frozen
+    /// * `nevras` - The depsolved packages to install
+    /// * `out_dir` - The directory to write the output to
+    /// * `canceled` - Returns true when the compose has been canceled. Exporters should check it
+    ///                regularly and return `ExportError::Canceled` when it is set.
+    ///
+    fn run(&self, recipe: &Recipe, nevras: &[PackageNEVRA], out_dir: &Path, canceled: &Fn() -> bool) -> Result<(), ExportError>;
+}
+
+
+/// Export the packages as a tar archive of the root filesystem
+pub struct TarExporter {
+    db_path:   String,
+    bdcs_path: PathBuf
+}
+
+impl TarExporter {
+    /// Create a new TarExporter
+    ///
+    /// # Arguments
+    ///
+    /// * `db_path` - Path to the metadata database
+    /// * `bdcs_path` - Path to the content store
+    ///
+    pub fn new(db_path: &str, bdcs_path: &str) -> TarExporter {
+        TarExporter {
+            db_path:   db_path.to_string(),
+            bdcs_path: PathBuf::from(bdcs_path)
+        }
+    }
+}
+
+impl Exporter for TarExporter {
+    fn name(&self) -> &str {
+        "tar"
+    }
+
+    fn description(&self) -> &str {
+        "Tar archive of the root filesystem"
+    }
+
+    /// Write the packages' files to `<out_dir>/root.tar`
+    fn run(&self, _recipe: &Recipe, nevras: &[PackageNEVRA], out_dir: &Path, canceled: &Fn() -> bool) -> Result<(), ExportError> {
+        let conn = try!(Connection::open(&self.db_path));
+        let group_ids = try!(nevras_group_ids(&conn, nevras));
+
+        let tar_file = try!(File::create(out_dir.join("root.tar")));
+        let mut output = try!(export_tar(&conn, &self.bdcs_path, &group_ids, BufWriter::new(tar_file), canceled));
+        try!(output.flush());
+        Ok(())
+    }
+}
+
+
+/// Find the group ids of a list of packages
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `nevras` - The packages, eg. from a depsolved recipe
+///
+/// # Returns
+///
+/// * A Vector of group ids, or an ExportError if a package is not in the database
+///
+pub fn nevras_group_ids(conn: &Connection, nevras: &[PackageNEVRA]) -> Result<Vec<i64>, ExportError> {
+    let mut group_ids = Vec::new();
+    for nevra in nevras {
+        match try!(get_group_id_nevra(conn, nevra)) {
+            Some(id) => group_ids.push(id),
+            None => return Err(ExportError::MissingPackage(nevra.to_string()))
+        }
+    }
+    Ok(group_ids)
+}
+
+/// Return the path of a file's object in the content store
+///
+/// `cs_object` must be a hex checksum of more than 2 characters, otherwise it cannot be in the
+/// content store and ExportError::MissingObject is returned for `path`.
+fn object_path(bdcs_path: &Path, path: &str, cs_object: &str) -> Result<PathBuf, ExportError> {
+    if cs_object.len() <= 2 || !cs_object.chars().all(|c| c.is_digit(16)) {
+        return Err(ExportError::MissingObject(path.to_string(), cs_object.to_string()));
+    }
+    let (prefix, rest) = cs_object.split_at(2);
+    Ok(bdcs_path.join("objects").join(prefix).join(format!("{}.file", rest)))
+}
+
+/// Create a tar header for a file
+fn file_header(file: &Files, entry_type: EntryType, mode: u32, size: u64) -> Result<Header, ExportError> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    header.set_mtime(file.mtime as u64);
+    header.set_uid(0);
+    header.set_gid(0);
+    try!(header.set_username(&file.file_user));
+    try!(header.set_groupname(&file.file_group));
+    Ok(header)
+}
+
+/// Write the files of a list of packages to a tar archive
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `bdcs_path` - Path to the content store
+/// * `group_ids` - The packages to export, eg. from `solve_dependencies`
+/// * `output` - Where to write the archive
+/// * `canceled` - Checked before each file is written, the export stops when it returns true
+///
+/// # Returns
+///
+/// * The output, after the archive has been finished, or an ExportError. ExportError::Canceled
+///   is returned if the export was canceled.
+///
+/// Files are owned by uid and gid 0 in the archive, the owner and group names are set from the
+/// database. Paths that are in more than one package are only written once.
+///
+pub fn export_tar<W: Write>(conn: &Connection, bdcs_path: &Path, group_ids: &[i64], output: W, canceled: &Fn() -> bool) -> Result<W, ExportError> {
+    let mut files = Vec::new();
+    for group_id in group_ids {
+        files.extend(try!(get_group_files(conn, *group_id)));
+    }
+    // Parent directories sort before their contents
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut seen = HashSet::new();
+    let mut archive = Builder::new(output);
+    for file in files {
+        if canceled() {
+            return Err(ExportError::Canceled);
+        }
+        if !seen.insert(file.path.clone()) {
+            continue;
+        }
+        let archive_path = file.path.trim_left_matches('/').to_string();
+        if archive_path.is_empty() {
+            continue;
+        }
+
+        let cs_object = match file.cs_object {
+            Some(ref cs_object) => cs_object.clone(),
+            None => {
+                let mut header = try!(file_header(&file, EntryType::Directory, 0o755, 0));
+                try!(archive.append_data(&mut header, &archive_path, io::empty()));
+                continue;
+            }
+        };
+
+        let object = try!(object_path(bdcs_path, &file.path, &cs_object));
+        let metadata = match symlink_metadata(&object) {
+            Ok(metadata) => metadata,
+            Err(_) => return Err(ExportError::MissingObject(file.path.clone(), cs_object))
+        };
+
+        if metadata.file_type().is_symlink() {
+            let target = try!(read_link(&object));
+            let mut header = try!(file_header(&file, EntryType::Symlink, 0o777, 0));
+            try!(header.set_link_name(&target));
+            try!(archive.append_data(&mut header, &archive_path, io::empty()));
+        } else {
+            let mode = metadata.permissions().mode() & 0o7777;
+            let mut header = try!(file_header(&file, EntryType::Regular, mode, metadata.len()));
+            try!(archive.append_data(&mut header, &archive_path, try!(File::open(&object))));
+        }
+    }
+
+    Ok(try!(archive.into_inner()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::{Cursor, Read, Write};
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+
+    use tar::Archive;
+    use uuid::Uuid;
+
+    use export::*;
+    use test_helper::*;
+
+    #[test]
+    fn test_object_path() {
+        let bdcs_path = Path::new("/cs.repo");
+        assert_eq!(object_path(bdcs_path, "/usr/bin/bash", "71d2cf42f6").unwrap(),
+                   PathBuf::from("/cs.repo/objects/71/d2cf42f6.file"));
+
+        // Short or non-hex checksums are not in the content store
+        for cs_object in &["", "7", "71", "../../etc/passwd", "71d2cf42fé"] {
+            match object_path(bdcs_path, "/usr/bin/bash", cs_object) {
+                Err(ExportError::MissingObject(ref path, ref object)) => {
+                    assert_eq!(path, "/usr/bin/bash");
+                    assert_eq!(object, *cs_object);
+                },
+                _ => panic!("{} is not a valid object", cs_object)
+            }
+        }
+    }
+
+    #[test]
+    fn test_export_tar() {
+        let conn = create_test_db(&[
+            TestData::Groups(TestGroups {
+                files: vec![test_file("/usr/bin", None),
+                            test_file("/usr/bin/bash", Some("71d2cf42f6")),
+                            test_file("/usr/bin/sh", Some("0a1b2c3d4e"))],
+                .. test_group("bash", "rpm")
+            })]).unwrap();
+
+        // Make a small content store
+        let bdcs_path = env::temp_dir().join(format!("bdcs-export-test-{}", Uuid::new_v4()));
+        create_dir_all(bdcs_path.join("objects/71")).unwrap();
+        create_dir_all(bdcs_path.join("objects/0a")).unwrap();
+        File::create(bdcs_path.join("objects/71/d2cf42f6.file")).unwrap()
+            .write_all(b"#!/bin/bash\n").unwrap();
+        symlink("bash", bdcs_path.join("objects/0a/1b2c3d4e.file")).unwrap();
+
+        let output = export_tar(&conn, &bdcs_path, &[1], Vec::new(), &|| false).unwrap();
+        let mut archive = Archive::new(Cursor::new(output));
+        let mut paths = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            assert_eq!(entry.header().username().unwrap(), Some("root"));
+            assert_eq!(entry.header().mtime().unwrap(), 1501708260);
+
+            match path.as_str() {
+                "usr/bin" => assert_eq!(entry.header().entry_type(), EntryType::Directory),
+                "usr/bin/bash" => {
+                    let mut contents = String::new();
+                    entry.read_to_string(&mut contents).unwrap();
+                    assert_eq!(contents, "#!/bin/bash\n");
+                },
+                "usr/bin/sh" => {
+                    assert_eq!(entry.header().entry_type(), EntryType::Symlink);
+                    assert_eq!(entry.link_name().unwrap().unwrap().to_string_lossy(), "bash");
+                },
+                _ => panic!("Unexpected path {}", path)
+            }
+            paths.push(path);
+        }
+        assert_eq!(paths, vec!["usr/bin", "usr/bin/bash", "usr/bin/sh"]);
+
+        // A canceled export stops before writing anything
+        match export_tar(&conn, &bdcs_path, &[1], Vec::new(), &|| true) {
+            Err(ExportError::Canceled) => (),
+            _ => panic!("The export was not canceled")
+        }
+
+        // A missing object is an error
+        remove_dir_all(bdcs_path.join("objects/71")).unwrap();
+        assert!(export_tar(&conn, &bdcs_path, &[1], Vec::new(), &|| false).is_err());
+
+        remove_dir_all(bdcs_path).unwrap();
+    }
+}
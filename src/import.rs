@@ -0,0 +1,671 @@
+//! Import RPMs into the metadata database
+//!
+//! ## Overview
+//!
+//! Fill the metadata database from the headers of local `.rpm` files, or from a repository's
+//! [repodata](../repodata/index.html), the same way the bdcs importer does, so that test and
+//! development databases can be made without it. Each [Package](struct.Package.html) adds:
+//!
+//! * A project named after its source rpm, and a source for the project's version
+//! * A build, with its signatures and files
+//! * An `rpm` group holding the build's files, with `name`, `epoch`, `version`, `release`, and
+//!   `arch` key/values
+//! * `rpm-provide`, `rpm-requires`, `rpm-conflict`, and `rpm-obsolete` key/values on the group.
+//!   The val_value is the name, and the ext_value is the full expression, eg. `bash = 4.2.46-28.el7`
+//! * The requires as group requirements, which are used by the depsolver
+//!
+//! The payload is not read, so the files have no content store object.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use rusqlite::{self, Connection};
+use xml;
+
+use rpmfile::*;
+
+// Dependency flags, from rpmds.h
+const RPMSENSE_LESS: u32 = 1 << 1;
+const RPMSENSE_GREATER: u32 = 1 << 2;
+const RPMSENSE_EQUAL: u32 = 1 << 3;
+const RPMSENSE_POSTTRANS: u32 = 1 << 5;
+const RPMSENSE_PRETRANS: u32 = 1 << 7;
+const RPMSENSE_SCRIPT_PRE: u32 = 1 << 9;
+const RPMSENSE_SCRIPT_POST: u32 = 1 << 10;
+const RPMSENSE_SCRIPT_PREUN: u32 = 1 << 11;
+const RPMSENSE_SCRIPT_POSTUN: u32 = 1 << 12;
+const RPMSENSE_RPMLIB: u32 = 1 << 24;
+
+
+/// Import Errors
+#[derive(Debug)]
+pub enum ImportError {
+    IO(io::Error),
+    SQLite(rusqlite::Error),
+    RPM(RPMError),
+    XML(xml::reader::Error),
+    /// The package is missing a required tag
+    MissingTag(&'static str),
+    /// The repodata is incomplete
+    Repodata(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::IO(ref e) => write!(f, "IO error: {}", e),
+            ImportError::SQLite(ref e) => write!(f, "SQLite error: {}", e),
+            ImportError::RPM(ref e) => write!(f, "{}", e),
+            ImportError::XML(ref e) => write!(f, "Error parsing the XML: {}", e),
+            ImportError::MissingTag(tag) => write!(f, "The package has no {}", tag),
+            ImportError::Repodata(ref msg) => write!(f, "Repodata error: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> ImportError {
+        ImportError::IO(err)
+    }
+}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(err: rusqlite::Error) -> ImportError {
+        ImportError::SQLite(err)
+    }
+}
+
+impl From<RPMError> for ImportError {
+    fn from(err: RPMError) -> ImportError {
+        ImportError::RPM(err)
+    }
+}
+
+impl From<xml::reader::Error> for ImportError {
+    fn from(err: xml::reader::Error) -> ImportError {
+        ImportError::XML(err)
+    }
+}
+
+
+/// A provide, require, conflict, or obsolete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    /// The full expression, eg. `bash = 4.2.46-28.el7`
+    pub expr: String,
+    /// The requirement's context, eg. `Runtime` or `ScriptPre`, as used by the bdcs importer
+    pub context: &'static str
+}
+
+impl Dependency {
+    /// Make a dependency from its name, comparison operator, and version
+    ///
+    /// The expression is just the name if there is no operator or version.
+    pub fn new(name: &str, operator: &str, version: &str, context: &'static str) -> Dependency {
+        let expr = if operator.is_empty() || version.is_empty() {
+            name.to_string()
+        } else {
+            format!("{} {} {}", name, operator, version)
+        };
+        Dependency { name: name.to_string(), expr: expr, context: context }
+    }
+}
+
+// The requirement's context for the rpm dependency flags
+fn rpm_context(flags: u32) -> &'static str {
+    if flags & RPMSENSE_RPMLIB != 0 {
+        "Feature"
+    } else if flags & RPMSENSE_SCRIPT_PRE != 0 {
+        "ScriptPre"
+    } else if flags & RPMSENSE_SCRIPT_POST != 0 {
+        "ScriptPost"
+    } else if flags & RPMSENSE_SCRIPT_PREUN != 0 {
+        "ScriptPreUn"
+    } else if flags & RPMSENSE_SCRIPT_POSTUN != 0 {
+        "ScriptPostUn"
+    } else if flags & RPMSENSE_PRETRANS != 0 {
+        "ScriptPreTrans"
+    } else if flags & RPMSENSE_POSTTRANS != 0 {
+        "ScriptPostTrans"
+    } else {
+        "Runtime"
+    }
+}
+
+// Read the dependencies from the name, flags, and version tags
+fn rpm_dependencies(header: &Header, name_tag: u32, flags_tag: u32, version_tag: u32) -> Vec<Dependency> {
+    let flags = header.get_numbers(flags_tag);
+    let versions = header.get_strings(version_tag);
+
+    header.get_strings(name_tag).iter().enumerate().map(|(i, name)| {
+        let flag = flags.get(i).cloned().unwrap_or(0);
+        let version = versions.get(i).map(String::as_str).unwrap_or("");
+        let operator = match flag & (RPMSENSE_LESS | RPMSENSE_GREATER | RPMSENSE_EQUAL) {
+            f if f == RPMSENSE_LESS => "<",
+            f if f == RPMSENSE_GREATER => ">",
+            f if f == RPMSENSE_EQUAL => "=",
+            f if f == RPMSENSE_LESS | RPMSENSE_EQUAL => "<=",
+            f if f == RPMSENSE_GREATER | RPMSENSE_EQUAL => ">=",
+            _ => ""
+        };
+        Dependency::new(name, operator, version, rpm_context(flag))
+    }).collect()
+}
+
+/// A file in a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFile {
+    pub path: String,
+    pub user: String,
+    pub group: String,
+    pub mtime: i64
+}
+
+impl PackageFile {
+    /// A file owned by root, used when only the path is known
+    pub fn new(path: &str) -> PackageFile {
+        PackageFile {
+            path:  path.to_string(),
+            user:  "root".to_string(),
+            group: "root".to_string(),
+            mtime: 0
+        }
+    }
+}
+
+/// The details of a package that are imported
+#[derive(Debug, Clone, Default)]
+pub struct Package {
+    pub name: String,
+    pub epoch: Option<u32>,
+    pub version: String,
+    pub release: String,
+    pub arch: String,
+    pub summary: String,
+    pub description: String,
+    pub url: Option<String>,
+    pub license: String,
+    /// The name of the source package, used as the project's name
+    pub source_name: String,
+    /// In seconds since the epoch
+    pub build_time: i64,
+    pub changelog: String,
+    /// The type, eg. `SHA1`, and data of each signature
+    pub signatures: Vec<(String, Vec<u8>)>,
+    pub provides: Vec<Dependency>,
+    pub requires: Vec<Dependency>,
+    pub conflicts: Vec<Dependency>,
+    pub obsoletes: Vec<Dependency>,
+    pub files: Vec<PackageFile>
+}
+
+impl Package {
+    /// Read a package from an rpm's headers
+    pub fn from_rpm(rpm: &RPMFile) -> Result<Package, ImportError> {
+        let header = &rpm.header;
+        let name = try!(required_string(header, RPMTAG_NAME, "name"));
+
+        let mut signatures = Vec::new();
+        if let Some(sha1) = rpm.signature.get_string(SIGTAG_SHA1) {
+            signatures.push(("SHA1".to_string(), sha1.into_bytes()));
+        }
+        if let Some(rsa) = rpm.signature.get_bin(SIGTAG_RSA) {
+            signatures.push(("RSA".to_string(), rsa));
+        }
+
+        let users = header.get_strings(RPMTAG_FILEUSERNAME);
+        let groups = header.get_strings(RPMTAG_FILEGROUPNAME);
+        let mtimes = header.get_numbers(RPMTAG_FILEMTIMES);
+        let files = header.file_paths().iter().enumerate().map(|(i, path)| PackageFile {
+            path:  path.clone(),
+            user:  users.get(i).cloned().unwrap_or_else(|| "root".to_string()),
+            group: groups.get(i).cloned().unwrap_or_else(|| "root".to_string()),
+            mtime: i64::from(mtimes.get(i).cloned().unwrap_or(0))
+        }).collect();
+
+        Ok(Package {
+            source_name: source_name(header).unwrap_or_else(|| name.clone()),
+            name:        name,
+            epoch:       header.get_number(RPMTAG_EPOCH),
+            version:     try!(required_string(header, RPMTAG_VERSION, "version")),
+            release:     try!(required_string(header, RPMTAG_RELEASE, "release")),
+            arch:        try!(required_string(header, RPMTAG_ARCH, "arch")),
+            summary:     header.get_string(RPMTAG_SUMMARY).unwrap_or_default(),
+            description: header.get_string(RPMTAG_DESCRIPTION).unwrap_or_default(),
+            url:         header.get_string(RPMTAG_URL),
+            license:     header.get_string(RPMTAG_LICENSE).unwrap_or_default(),
+            build_time:  i64::from(header.get_number(RPMTAG_BUILDTIME).unwrap_or(0)),
+            // Only the newest changelog entry is kept
+            changelog:   header.get_string(RPMTAG_CHANGELOGTEXT).unwrap_or_default(),
+            signatures:  signatures,
+            provides:    rpm_dependencies(header, RPMTAG_PROVIDENAME, RPMTAG_PROVIDEFLAGS, RPMTAG_PROVIDEVERSION),
+            requires:    rpm_dependencies(header, RPMTAG_REQUIRENAME, RPMTAG_REQUIREFLAGS, RPMTAG_REQUIREVERSION),
+            conflicts:   rpm_dependencies(header, RPMTAG_CONFLICTNAME, RPMTAG_CONFLICTFLAGS, RPMTAG_CONFLICTVERSION),
+            obsoletes:   rpm_dependencies(header, RPMTAG_OBSOLETENAME, RPMTAG_OBSOLETEFLAGS, RPMTAG_OBSOLETEVERSION),
+            files:       files
+        })
+    }
+}
+
+/// Return the name of a package from its source rpm's filename
+///
+/// eg. `bash` from `bash-4.2.46-28.el7.src.rpm`
+pub fn srpm_name(srpm: &str) -> Option<String> {
+    let mut parts = srpm.rsplitn(3, '-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(_), Some(name)) => Some(name.to_string()),
+        _ => None
+    }
+}
+
+fn source_name(header: &Header) -> Option<String> {
+    header.get_string(RPMTAG_SOURCERPM).and_then(|srpm| srpm_name(&srpm))
+}
+
+fn required_string(header: &Header, tag: u32, tag_name: &'static str) -> Result<String, ImportError> {
+    header.get_string(tag).ok_or(ImportError::MissingTag(tag_name))
+}
+
+
+/// Import a package, in a single transaction
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `package` - The package's details
+///
+/// # Returns
+///
+/// * The id of the package's group, or None if the package has already been imported
+///
+pub fn import_package(conn: &Connection, package: &Package) -> Result<Option<i64>, ImportError> {
+    try!(conn.execute_batch("BEGIN;"));
+    match insert_package(conn, package) {
+        Ok(group_id) => {
+            try!(conn.execute_batch("COMMIT;"));
+            Ok(group_id)
+        },
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK;");
+            Err(From::from(e))
+        }
+    }
+}
+
+/// Import an rpm file
+///
+/// # Returns
+///
+/// * The id of the package's group, or None if the package has already been imported
+///
+pub fn import_file<P: AsRef<Path>>(conn: &Connection, path: P) -> Result<Option<i64>, ImportError> {
+    let rpm = try!(RPMFile::open(path));
+    import_package(conn, &try!(Package::from_rpm(&rpm)))
+}
+
+fn insert_package(conn: &Connection, package: &Package) -> rusqlite::Result<Option<i64>> {
+    let project_id = try!(find_or_insert_project(conn, package));
+    let source_id = try!(find_or_insert_source(conn, package, project_id));
+    let build_id = match try!(insert_build(conn, package, source_id)) {
+        Some(build_id) => build_id,
+        None => return Ok(None)
+    };
+    let package_name = try!(insert_key_val(conn, "packageName", &package.name, None));
+    try!(conn.execute_named("insert into build_key_values (build_id, key_val_id) values (:build_id, :key_val_id)",
+                            &[(":build_id", &build_id), (":key_val_id", &package_name)]));
+
+    try!(conn.execute_named("insert into groups (name, group_type, build_id) values (:name, 'rpm', :build_id)",
+                            &[(":name", &package.name), (":build_id", &build_id)]));
+    let group_id = conn.last_insert_rowid();
+
+    let mut key_vals = vec![("name", package.name.as_str(), None),
+                            ("version", package.version.as_str(), None),
+                            ("release", package.release.as_str(), None),
+                            ("arch", package.arch.as_str(), None)];
+    let epoch = package.epoch.map(|e| e.to_string());
+    if let Some(ref epoch) = epoch {
+        key_vals.push(("epoch", epoch.as_str(), None));
+    }
+    let deps = [("rpm-provide", &package.provides),
+                ("rpm-requires", &package.requires),
+                ("rpm-conflict", &package.conflicts),
+                ("rpm-obsolete", &package.obsoletes)];
+    for &(key, list) in &deps {
+        for dep in list {
+            key_vals.push((key, dep.name.as_str(), Some(dep.expr.as_str())));
+        }
+    }
+    for (key, val, ext) in key_vals {
+        let key_val_id = try!(insert_key_val(conn, key, val, ext));
+        try!(conn.execute_named("insert into group_key_values (group_id, key_val_id) values (:group_id, :key_val_id)",
+                                &[(":group_id", &group_id), (":key_val_id", &key_val_id)]));
+    }
+
+    for dep in &package.requires {
+        try!(conn.execute_named("
+            insert into requirements (req_language, req_context, req_strength, req_expr)
+            values ('RPM', :req_context, 'Must', :req_expr)",
+            &[(":req_context", &dep.context), (":req_expr", &dep.expr)]));
+        let req_id = conn.last_insert_rowid();
+        try!(conn.execute_named("insert into group_requirements (group_id, req_id) values (:group_id, :req_id)",
+                                &[(":group_id", &group_id), (":req_id", &req_id)]));
+    }
+
+    try!(insert_files(conn, &package.files, build_id, group_id));
+
+    Ok(Some(group_id))
+}
+
+fn find_or_insert_project(conn: &Connection, package: &Package) -> rusqlite::Result<i64> {
+    match conn.query_row_named("select id from projects where name == :name",
+                               &[(":name", &package.source_name)], |row| row.get(0)) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => (),
+        result => return result
+    }
+
+    try!(conn.execute_named("
+        insert into projects (name, summary, description, homepage, upstream_vcs)
+        values (:name, :summary, :description, :homepage, 'UPSTREAM_VCS')",
+        &[(":name", &package.source_name),
+          (":summary", &package.summary),
+          (":description", &package.description),
+          (":homepage", &package.url)]));
+    Ok(conn.last_insert_rowid())
+}
+
+fn find_or_insert_source(conn: &Connection, package: &Package, project_id: i64) -> rusqlite::Result<i64> {
+    match conn.query_row_named("select id from sources where project_id == :project_id and version == :version",
+                               &[(":project_id", &project_id), (":version", &package.version)], |row| row.get(0)) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => (),
+        result => return result
+    }
+
+    try!(conn.execute_named("
+        insert into sources (project_id, license, version, source_ref)
+        values (:project_id, :license, :version, 'SOURCE_REF')",
+        &[(":project_id", &project_id),
+          (":license", &package.license),
+          (":version", &package.version)]));
+    Ok(conn.last_insert_rowid())
+}
+
+// Insert the build and its signatures, returning None if the build is already in the database
+fn insert_build(conn: &Connection, package: &Package, source_id: i64) -> rusqlite::Result<Option<i64>> {
+    let epoch = i64::from(package.epoch.unwrap_or(0));
+    // Subpackages of the same source rpm share the source, epoch, release and arch, so the
+    // package's name is also needed to tell the builds apart.
+    match conn.query_row_named("
+        select builds.id from builds, build_key_values, key_val
+        on build_key_values.build_id == builds.id and key_val.id == build_key_values.key_val_id
+        where builds.source_id == :source_id and builds.epoch == :epoch and builds.release == :release and
+              builds.arch == :arch and key_val.key_value == 'TextKey \"packageName\"' and key_val.val_value == :name",
+        &[(":source_id", &source_id), (":epoch", &epoch), (":release", &package.release), (":arch", &package.arch),
+          (":name", &package.name)],
+        |row| row.get::<i32, i64>(0)) {
+        Ok(_) => return Ok(None),
+        Err(rusqlite::Error::QueryReturnedNoRows) => (),
+        Err(e) => return Err(e)
+    }
+
+    let build_time = NaiveDateTime::from_timestamp(package.build_time, 0).format("%Y-%m-%dT%H:%M:%S").to_string();
+    try!(conn.execute_named("
+        insert into builds (source_id, epoch, release, arch, build_time, changelog, build_config_ref, build_env_ref)
+        values (:source_id, :epoch, :release, :arch, :build_time, :changelog, 'BUILD_CONFIG_REF', 'BUILD_ENV_REF')",
+        &[(":source_id", &source_id),
+          (":epoch", &epoch),
+          (":release", &package.release),
+          (":arch", &package.arch),
+          (":build_time", &build_time),
+          (":changelog", &package.changelog.as_bytes())]));
+    let build_id = conn.last_insert_rowid();
+
+    for &(ref signature_type, ref data) in &package.signatures {
+        try!(conn.execute_named("
+            insert into build_signatures (build_id, signature_type, signature_data)
+            values (:build_id, :signature_type, :signature_data)",
+            &[(":build_id", &build_id), (":signature_type", signature_type), (":signature_data", data)]));
+    }
+
+    Ok(Some(build_id))
+}
+
+fn insert_files(conn: &Connection, files: &[PackageFile], build_id: i64, group_id: i64) -> rusqlite::Result<()> {
+    for file in files {
+        try!(conn.execute_named("
+            insert into files (path, file_user, file_group, mtime, cs_object)
+            values (:path, :file_user, :file_group, :mtime, NULL)",
+            &[(":path", &file.path),
+              (":file_user", &file.user),
+              (":file_group", &file.group),
+              (":mtime", &file.mtime)]));
+        let file_id = conn.last_insert_rowid();
+
+        try!(conn.execute_named("insert into build_files (build_id, file_id) values (:build_id, :file_id)",
+                                &[(":build_id", &build_id), (":file_id", &file_id)]));
+        try!(conn.execute_named("insert into group_files (group_id, file_id) values (:group_id, :file_id)",
+                                &[(":group_id", &group_id), (":file_id", &file_id)]));
+    }
+    Ok(())
+}
+
+fn insert_key_val(conn: &Connection, key: &str, val: &str, ext: Option<&str>) -> rusqlite::Result<i64> {
+    try!(conn.execute_named("
+        insert into key_val (key_value, val_value, ext_value)
+        values (:key_value, :val_value, :ext_value)",
+        &[(":key_value", &format!("TextKey \"{}\"", key)),
+          (":val_value", &val),
+          (":ext_value", &ext)]));
+    Ok(conn.last_insert_rowid())
+}
+
+/// Return the binary rpm files in a directory, sorted by name
+///
+/// Source rpms are skipped.
+pub fn rpm_files<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in try!(fs::read_dir(path)) {
+        let path = try!(entry).path();
+        let is_rpm = path.file_name()
+                         .and_then(|name| name.to_str())
+                         .map(|name| name.ends_with(".rpm") && !name.ends_with(".src.rpm"))
+                         .unwrap_or(false);
+        if is_rpm && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use db::*;
+    use import::*;
+    use rpmfile::*;
+    use rusqlite::Connection;
+    use test_helper::*;
+
+    fn strings(values: &[&str]) -> TestTag {
+        TestTag::StringArray(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    fn bash_rpm() -> Vec<u8> {
+        create_test_rpm(&[(SIGTAG_SHA1, TestTag::String("ed5c44b6d81784129138935e009a5d71fcca2e22".to_string()))],
+                        &[(RPMTAG_NAME, TestTag::String("bash".to_string())),
+                          (RPMTAG_VERSION, TestTag::String("4.2.46".to_string())),
+                          (RPMTAG_RELEASE, TestTag::String("28.el7".to_string())),
+                          (RPMTAG_ARCH, TestTag::String("x86_64".to_string())),
+                          (RPMTAG_SUMMARY, TestTag::I18NString("The GNU Bourne Again shell".to_string())),
+                          (RPMTAG_DESCRIPTION, TestTag::I18NString("The GNU Bourne Again shell (Bash)".to_string())),
+                          (RPMTAG_BUILDTIME, TestTag::Int32(vec![1501708401])),
+                          (RPMTAG_LICENSE, TestTag::String("GPLv3+".to_string())),
+                          (RPMTAG_URL, TestTag::String("http://www.gnu.org/software/bash".to_string())),
+                          (RPMTAG_SOURCERPM, TestTag::String("bash-4.2.46-28.el7.src.rpm".to_string())),
+                          (RPMTAG_PROVIDENAME, strings(&["/bin/sh", "bash"])),
+                          (RPMTAG_PROVIDEFLAGS, TestTag::Int32(vec![0, 8])),
+                          (RPMTAG_PROVIDEVERSION, strings(&["", "4.2.46-28.el7"])),
+                          (RPMTAG_REQUIRENAME, strings(&["/bin/sh", "rpmlib(FileDigests)", "libc.so.6()(64bit)"])),
+                          (RPMTAG_REQUIREFLAGS, TestTag::Int32(vec![1 << 9, (1 << 24) | 8 | 2, 0])),
+                          (RPMTAG_REQUIREVERSION, strings(&["", "4.6.0-1", ""])),
+                          (RPMTAG_CONFLICTNAME, strings(&["filesystem"])),
+                          (RPMTAG_CONFLICTFLAGS, TestTag::Int32(vec![2])),
+                          (RPMTAG_CONFLICTVERSION, strings(&["3"])),
+                          (RPMTAG_FILEMTIMES, TestTag::Int32(vec![1501708260, 1501708261])),
+                          (RPMTAG_FILEUSERNAME, strings(&["root", "root"])),
+                          (RPMTAG_FILEGROUPNAME, strings(&["root", "wheel"])),
+                          (RPMTAG_DIRINDEXES, TestTag::Int32(vec![0, 0])),
+                          (RPMTAG_BASENAMES, strings(&["bash", "sh"])),
+                          (RPMTAG_DIRNAMES, strings(&["/usr/bin/"]))])
+    }
+
+    fn write_rpm(dir: &TestDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path.join(name);
+        File::create(&path).unwrap().write_all(data).unwrap();
+        path
+    }
+
+    fn group_key_vals(conn: &Connection, group_id: i64, key: &str) -> Vec<(String, Option<String>)> {
+        let mut stmt = conn.prepare("
+            select key_val.val_value, key_val.ext_value
+            from group_key_values, key_val
+            on key_val.id == group_key_values.key_val_id
+            where group_key_values.group_id == :group_id and key_val.key_value == :key
+            order by key_val.id").unwrap();
+        let rows = stmt.query_map_named(&[(":group_id", &group_id), (":key", &format!("TextKey \"{}\"", key))],
+                                        |row| (row.get(0), row.get(1))).unwrap();
+        rows.map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_import_rpm() {
+        let conn = create_test_db(&[]).unwrap();
+        let dir = TestDir::new("bdcs-import-test");
+        let path = write_rpm(&dir, "bash-4.2.46-28.el7.x86_64.rpm", &bash_rpm());
+        let group_id = import_file(&conn, &path).unwrap().unwrap();
+
+        let (total, projects) = get_projects_name(&conn, "bash", 0, 10).unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(projects[0].summary, "The GNU Bourne Again shell");
+        assert_eq!(projects[0].homepage, Some("http://www.gnu.org/software/bash".to_string()));
+
+        let group = get_groups_id(&conn, &group_id).unwrap().unwrap();
+        assert_eq!((group.name.as_str(), group.group_type.as_str()), ("bash", "rpm"));
+
+        assert_eq!(group_key_vals(&conn, group_id, "rpm-provide"),
+                   vec![("/bin/sh".to_string(), Some("/bin/sh".to_string())),
+                        ("bash".to_string(), Some("bash = 4.2.46-28.el7".to_string()))]);
+        assert_eq!(group_key_vals(&conn, group_id, "rpm-conflict"),
+                   vec![("filesystem".to_string(), Some("filesystem < 3".to_string()))]);
+        assert_eq!(group_key_vals(&conn, group_id, "rpm-requires").len(), 3);
+        assert!(group_key_vals(&conn, group_id, "epoch").is_empty());
+
+        let requirements: Vec<(String, String)> = conn.prepare("select req_context, req_expr from requirements order by id").unwrap()
+                                                      .query_map(&[], |row| (row.get(0), row.get(1))).unwrap()
+                                                      .map(|r| r.unwrap()).collect();
+        assert_eq!(requirements, vec![("ScriptPre".to_string(), "/bin/sh".to_string()),
+                                      ("Feature".to_string(), "rpmlib(FileDigests) <= 4.6.0-1".to_string()),
+                                      ("Runtime".to_string(), "libc.so.6()(64bit)".to_string())]);
+
+        let files: Vec<(String, String, i64)> = conn.prepare("select path, file_group, mtime from files order by id").unwrap()
+                                                    .query_map(&[], |row| (row.get(0), row.get(1), row.get(2))).unwrap()
+                                                    .map(|r| r.unwrap()).collect();
+        assert_eq!(files, vec![("/usr/bin/bash".to_string(), "root".to_string(), 1501708260),
+                               ("/usr/bin/sh".to_string(), "wheel".to_string(), 1501708261)]);
+
+        let build_time: String = conn.query_row("select build_time from builds", &[], |row| row.get(0)).unwrap();
+        assert_eq!(build_time, "2017-08-02T21:13:21");
+
+        // Importing it again does nothing
+        assert_eq!(import_file(&conn, &path).unwrap(), None);
+        let builds: i64 = conn.query_row("select count(*) from builds", &[], |row| row.get(0)).unwrap();
+        assert_eq!(builds, 1);
+    }
+
+    #[test]
+    fn test_import_subpackage() {
+        let conn = create_test_db(&[]).unwrap();
+        let dir = TestDir::new("bdcs-import-test");
+        let bash_id = import_file(&conn, &write_rpm(&dir, "bash-4.2.46-28.el7.x86_64.rpm", &bash_rpm())).unwrap().unwrap();
+
+        // A subpackage of the same source rpm is a different build
+        let path = write_rpm(&dir, "bash-doc-4.2.46-28.el7.x86_64.rpm",
+                             &create_test_rpm(&[],
+                                              &[(RPMTAG_NAME, TestTag::String("bash-doc".to_string())),
+                                                (RPMTAG_VERSION, TestTag::String("4.2.46".to_string())),
+                                                (RPMTAG_RELEASE, TestTag::String("28.el7".to_string())),
+                                                (RPMTAG_ARCH, TestTag::String("x86_64".to_string())),
+                                                (RPMTAG_SOURCERPM, TestTag::String("bash-4.2.46-28.el7.src.rpm".to_string()))]));
+        let doc_id = import_file(&conn, &path).unwrap().unwrap();
+        assert!(doc_id != bash_id);
+        assert_eq!(get_groups_id(&conn, &doc_id).unwrap().unwrap().name, "bash-doc");
+
+        let (builds, sources): (i64, i64) = conn.query_row("select count(*), count(distinct source_id) from builds", &[],
+                                                           |row| (row.get(0), row.get(1))).unwrap();
+        assert_eq!((builds, sources), (2, 1));
+
+        // Importing it again does nothing
+        assert_eq!(import_file(&conn, &path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let conn = create_test_db(&[]).unwrap();
+        let dir = TestDir::new("bdcs-import-test");
+
+        // The name, version, release, and arch are required
+        let rpm = create_test_rpm(&[], &[(RPMTAG_NAME, TestTag::String("bash".to_string())),
+                                         (RPMTAG_SOURCERPM, TestTag::String("bash-4.2.46-28.el7.src.rpm".to_string()))]);
+        let path = write_rpm(&dir, "bash-4.2.46-28.el7.x86_64.rpm", &rpm);
+        match import_file(&conn, &path) {
+            Err(ImportError::MissingTag(tag)) => assert_eq!(tag, "version"),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+        let projects: i64 = conn.query_row("select count(*) from projects", &[], |row| row.get(0)).unwrap();
+        assert_eq!(projects, 0);
+
+        let path = write_rpm(&dir, "bash-4.2.46-28.el7.x86_64.rpm", b"Not an rpm");
+        match import_file(&conn, &path) {
+            Err(ImportError::RPM(RPMError::NotAnRPM)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_rpm_files() {
+        let test_dir = TestDir::new("bdcs-import-test");
+        let path = write_rpm(&test_dir, "bash-4.2.46-28.el7.x86_64.rpm", &bash_rpm());
+        let dir = &test_dir.path;
+        File::create(dir.join("bash-4.2.46-28.el7.src.rpm")).unwrap();
+        File::create(dir.join("README")).unwrap();
+        File::create(dir.join("acl-2.2.51-12.el7.x86_64.rpm")).unwrap();
+
+        assert_eq!(rpm_files(dir).unwrap(), vec![dir.join("acl-2.2.51-12.el7.x86_64.rpm"), path.clone()]);
+    }
+}
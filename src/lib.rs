@@ -29,6 +29,7 @@
 
 extern crate chrono;
 #[macro_use] extern crate clap;
+extern crate flate2;
 extern crate git2;
 extern crate glob;
 extern crate hyper;
@@ -41,11 +42,16 @@ extern crate rusqlite;
 extern crate semver;
 extern crate serde;
 extern crate serde_json;
+extern crate tar;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate slog;
 #[macro_use] extern crate slog_scope;
+extern crate slog_json;
+extern crate slog_stream;
 extern crate toml;
 extern crate itertools;
+extern crate uuid;
+extern crate xml;
 
 
 // A macro to extract the T from an Option<T> or do an early return with a user-specified value
@@ -72,11 +78,20 @@ macro_rules! err_opt {
 
 
 pub mod api;
+pub mod auth;
+pub mod compose;
+pub mod comps;
 pub mod db;
 pub mod depclose;
 pub mod depsolve;
+pub mod export;
+pub mod import;
+pub mod migrate;
 pub mod recipe;
+pub mod repodata;
 pub mod rpm;
+pub mod rpmfile;
+pub mod validate;
 pub mod workspace;
 
 // FIXME figure out how to conditionally compile this but also have it
@@ -97,7 +112,10 @@ pub struct RocketConfig {
     pub db_path: String,
     pub recipe_path: String,
     pub log_path: String,
-    pub mockfiles_path: String
+    pub mockfiles_path: String,
+    pub cors_origins: Vec<String>,
+    pub cors_credentials: bool,
+    pub cors_max_age: u32
 }
 
 /// Unit tests for bdcs
@@ -0,0 +1,273 @@
+//! Database Schema Migrations
+//!
+//! ## Overview
+//!
+//! The version of the metadata database's schema is stored in sqlite's `user_version` pragma.
+//! Each [Migration](struct.Migration.html) upgrades a database from the previous version, and
+//! [MIGRATIONS](static.MIGRATIONS.html) lists them in order. The `bdcs-dbmigrate` command applies
+//! them to a database, each one in its own transaction so that a failed migration leaves the
+//! database at the previous version.
+//!
+//! The server requires the database to be at [DB_VERSION](../db/constant.DB_VERSION.html).
+//!
+//! Databases made by older versions of the Haskell importer have the version 1 tables, but no
+//! `user_version`. Migrating one sets its version to 1 before applying the migrations.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use rusqlite::{self, Connection};
+
+use db::DB_VERSION;
+
+
+/// Migration Errors
+#[derive(Debug)]
+pub enum MigrateError {
+    SQLite(rusqlite::Error),
+    /// The database has no schema version or tables, it was not created by bdcs
+    Unversioned,
+    /// The database's schema is newer than this code supports
+    TooNew(u32),
+    /// There is no migration to this version
+    UnknownVersion(u32),
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrateError::SQLite(ref e) => write!(f, "SQLite error: {}", e),
+            MigrateError::Unversioned => write!(f, "The database has no schema version"),
+            MigrateError::TooNew(version) => write!(f, "Schema version {} is newer than the supported version {}", version, DB_VERSION),
+            MigrateError::UnknownVersion(version) => write!(f, "Unknown schema version {}", version),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrateError {
+    fn from(err: rusqlite::Error) -> MigrateError {
+        MigrateError::SQLite(err)
+    }
+}
+
+
+/// A change to the database schema
+#[derive(Debug)]
+pub struct Migration {
+    /// The schema version after the migration
+    pub version: u32,
+    /// A short description of the change
+    pub description: &'static str,
+    /// The SQL that upgrades the database from the previous version
+    pub upgrade: &'static str,
+}
+
+// Sets the version of a database that has the version 1 tables, but no user_version
+static SET_VERSION_1: Migration = Migration {
+    version: 1,
+    description: "Set the schema version of an unversioned database",
+    upgrade: ""
+};
+
+/// The schema migrations, in order
+///
+/// Schema version 1 is created by [create_schema](../db/fn.create_schema.html), there are no
+/// migrations yet.
+pub static MIGRATIONS: &'static [Migration] = &[];
+
+
+/// Return the database's schema version
+pub fn get_user_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("pragma user_version", &[], |row| row.get(0))
+}
+
+/// Upgrade the database's schema
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `target` - The schema version to upgrade to, usually [DB_VERSION](../db/constant.DB_VERSION.html)
+///
+/// # Returns
+///
+/// * A Vector of the [Migrations](struct.Migration.html) that were applied, empty if the database
+///   was already at the target version
+///
+/// A database newer than the target is left alone, schema changes are never undone. A database
+/// without a schema version is set to version 1 if it has the version 1 tables.
+///
+pub fn migrate(conn: &Connection, target: u32) -> Result<Vec<&'static Migration>, MigrateError> {
+    migrate_with(conn, MIGRATIONS, DB_VERSION, target)
+}
+
+// Apply the migrations up to target, latest is the newest version that they support
+fn migrate_with(conn: &Connection,
+                migrations: &'static [Migration],
+                latest: u32,
+                target: u32) -> Result<Vec<&'static Migration>, MigrateError> {
+    if target == 0 || target > latest {
+        return Err(MigrateError::UnknownVersion(target));
+    }
+
+    let mut applied = Vec::new();
+    let mut version = try!(get_user_version(conn));
+    if version == 0 {
+        if !try!(has_table(conn, "projects")) {
+            return Err(MigrateError::Unversioned);
+        }
+        try!(apply_migration(conn, &SET_VERSION_1));
+        applied.push(&SET_VERSION_1);
+        version = 1;
+    }
+    if version > latest {
+        return Err(MigrateError::TooNew(version));
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > version && m.version <= target) {
+        try!(apply_migration(conn, migration));
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+// Run the migration's SQL and set the new version in a single transaction
+fn apply_migration(conn: &Connection, migration: &Migration) -> rusqlite::Result<()> {
+    try!(conn.execute_batch("BEGIN;"));
+    let result = conn.execute_batch(migration.upgrade)
+                     .and_then(|_| conn.execute_batch(&format!("pragma user_version = {};", migration.version)));
+    match result {
+        Ok(_) => conn.execute_batch("COMMIT;"),
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK;");
+            Err(e)
+        }
+    }
+}
+
+// Return true if the database has the table
+fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    let count: i64 = try!(conn.query_row("select count(*) from sqlite_master where type = 'table' and name = ?",
+                                         &[&name], |row| row.get(0)));
+    Ok(count > 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use db::DB_VERSION;
+    use migrate::*;
+    use migrate::migrate_with;
+    use rusqlite::{self, Connection};
+    use test_helper::*;
+
+    // A migration past DB_VERSION, to exercise the framework
+    static TEST_MIGRATIONS: &'static [Migration] = &[
+        Migration {
+            version: DB_VERSION + 1,
+            description: "Add a test table",
+            upgrade: "
+create table test_migration (
+    id integer primary key,
+    name text not null
+);
+"
+        },
+    ];
+
+    fn set_user_version(conn: &Connection, version: u32) {
+        conn.execute_batch(&format!("pragma user_version = {};", version)).unwrap();
+    }
+
+    fn count(conn: &Connection, table: &str) -> rusqlite::Result<i64> {
+        conn.query_row(&format!("select count(*) from {}", table), &[], |row| row.get(0))
+    }
+
+    #[test]
+    fn test_migrate() {
+        // The current schema needs no migrations
+        let conn = create_test_db(&[]).unwrap();
+        assert!(migrate(&conn, DB_VERSION).unwrap().is_empty());
+        assert_eq!(get_user_version(&conn).unwrap(), DB_VERSION);
+
+        assert!(count(&conn, "test_migration").is_err());
+        let applied = migrate_with(&conn, TEST_MIGRATIONS, DB_VERSION + 1, DB_VERSION + 1).unwrap();
+        assert_eq!(applied.iter().map(|m| m.version).collect::<Vec<u32>>(), vec![DB_VERSION + 1]);
+        assert_eq!(get_user_version(&conn).unwrap(), DB_VERSION + 1);
+        assert_eq!(count(&conn, "test_migration").unwrap(), 0);
+
+        // Nothing left to do
+        assert!(migrate_with(&conn, TEST_MIGRATIONS, DB_VERSION + 1, DB_VERSION + 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_failure() {
+        // A failed migration leaves the database at the previous version
+        static BAD_MIGRATIONS: &'static [Migration] = &[
+            Migration {
+                version: DB_VERSION + 1,
+                description: "Fail halfway",
+                upgrade: "create table test_migration (id integer primary key); not sql;"
+            },
+        ];
+
+        let conn = create_test_db(&[]).unwrap();
+        assert!(migrate_with(&conn, BAD_MIGRATIONS, DB_VERSION + 1, DB_VERSION + 1).is_err());
+        assert_eq!(get_user_version(&conn).unwrap(), DB_VERSION);
+        assert!(count(&conn, "test_migration").is_err());
+    }
+
+    #[test]
+    fn test_migrate_errors() {
+        let conn = Connection::open_in_memory().unwrap();
+        match migrate(&conn, DB_VERSION) {
+            Err(MigrateError::Unversioned) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        let conn = create_test_db(&[]).unwrap();
+        set_user_version(&conn, DB_VERSION + 1);
+        match migrate(&conn, DB_VERSION) {
+            Err(MigrateError::TooNew(version)) => assert_eq!(version, DB_VERSION + 1),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        match migrate(&conn, DB_VERSION + 1) {
+            Err(MigrateError::UnknownVersion(version)) => assert_eq!(version, DB_VERSION + 1),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_migrate_unversioned() {
+        // The version 1 tables without a user_version, like the Haskell importer made
+        let conn = create_test_db(&[]).unwrap();
+        set_user_version(&conn, 0);
+
+        let applied = migrate(&conn, DB_VERSION).unwrap();
+        assert_eq!(applied.iter().map(|m| m.version).collect::<Vec<u32>>(), vec![1]);
+        assert_eq!(get_user_version(&conn).unwrap(), DB_VERSION);
+        assert!(migrate(&conn, DB_VERSION).unwrap().is_empty());
+
+        match migrate(&conn, 0) {
+            Err(MigrateError::UnknownVersion(version)) => assert_eq!(version, 0),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+}
@@ -25,6 +25,7 @@
 
 use std::clone::Clone;
 use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
@@ -56,6 +57,34 @@ impl RecipeRepo {
 }
 
 
+/// The author of the commits and tags made to the recipe repository
+///
+/// The default is the server itself, which is used when the server imports the example recipes
+/// and when requests are not authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Author {
+    pub name: String,
+    pub email: String
+}
+
+impl Author {
+    pub fn new<S: Into<String>>(name: S, email: S) -> Author {
+        Author { name: name.into(), email: email.into() }
+    }
+
+    /// Return a git signature for the author, with the current time
+    pub fn signature(&self) -> Result<Signature<'static>, git2::Error> {
+        Signature::now(&self.name, &self.email)
+    }
+}
+
+impl Default for Author {
+    fn default() -> Author {
+        Author::new("bdcs-api-server", "user-email")
+    }
+}
+
+
 /// Recipe Errors
 #[derive(Debug)]
 pub enum RecipeError {
@@ -69,7 +98,27 @@ pub enum RecipeError {
     RegexError(regex::Error),
     RecipeName,
     Branch,
-    ParseTOML
+    ParseTOML,
+    Include(String)
+}
+
+impl fmt::Display for RecipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecipeError::Io(ref e) => write!(f, "IO error: {}", e),
+            RecipeError::Git2(ref e) => write!(f, "Git error: {}", e.message()),
+            RecipeError::Glob(ref e) => write!(f, "Glob pattern error: {}", e),
+            RecipeError::Utf8(ref e) => write!(f, "UTF-8 error: {}", e),
+            RecipeError::TomlSer(ref e) => write!(f, "TOML serialization error: {}", e),
+            RecipeError::TomlDe(ref e) => write!(f, "TOML parse error: {}", e),
+            RecipeError::SemVerError(semver::SemVerError::ParseError(ref e)) => write!(f, "Invalid recipe version: {}", e),
+            RecipeError::RegexError(ref e) => write!(f, "Regex error: {}", e),
+            RecipeError::RecipeName => f.write_str("Invalid recipe name"),
+            RecipeError::Branch => f.write_str("Unknown branch"),
+            RecipeError::ParseTOML => f.write_str("Error parsing the recipe TOML"),
+            RecipeError::Include(ref e) => write!(f, "Include error: {}", e),
+        }
+    }
 }
 
 impl From<io::Error> for RecipeError {
@@ -128,16 +177,42 @@ impl From<regex::Error> for RecipeError {
 ///
 /// Empty modules or packages are represented as an empty list.
 ///
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+/// `arch` is the list of package architectures to depsolve the recipe for, eg. `["aarch64"]`. It
+/// is left out of the recipe when it is empty, and the server's default is used.
+///
+/// `include` is a list of other recipes in the same branch, eg. `include = ["base"]`, whose
+/// modules, packages and groups are added to this recipe by [flatten](fn.flatten.html).
+///
+/// `exclude` is a list of package names, eg. `["sendmail", "kernel-debug*"]`, that must never be
+/// installed. The names are globs, and every build of a matching package is excluded when the
+/// recipe is depsolved.
+///
+/// `groups` is the list of package groups, eg. `development-tools`, that are expanded into their
+/// packages when the recipe is depsolved. See the [comps](../comps/index.html) module.
+///
+/// `customizations` is the optional `[customizations]` table, with the settings for the system
+/// that are not packages. See [Customizations](struct.Customizations.html).
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Recipe {
     pub name: String,
     pub description: Option<String>,
     #[serde(default)]
     pub version: String,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub arch: Vec<String>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub include: Vec<String>,
     #[serde(default)]
     pub modules: Vec<Modules>,
     #[serde(default)]
-    pub packages: Vec<Packages>
+    pub packages: Vec<Packages>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub groups: Vec<Group>,
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub customizations: Option<Customizations>
 }
 
 impl Recipe {
@@ -195,6 +270,11 @@ impl Recipe {
         names
     }
 
+    /// Return the names of the package groups
+    pub fn group_names(&self) -> Vec<String> {
+        self.groups.iter().map(|g| g.name.clone()).collect()
+    }
+
     /// Return the matching module entry
     #[cfg_attr(feature="cargo-clippy", allow(ptr_arg))]
     pub fn find_module(&self, name: &String) -> Option<Modules> {
@@ -237,6 +317,149 @@ pub struct Packages {
 }
 
 
+/// Recipe Package Groups
+///
+/// This is used for the Recipe's `groups` section. The name may start with an `@`, like the
+/// groups in a kickstart, eg. `@development-tools`.
+///
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Group {
+    pub name: String
+}
+
+
+/// Recipe Customizations
+///
+/// This is used for the Recipe's optional `[customizations]` table. Everything in it is optional,
+/// and unset fields are left out of the recipe. eg.
+///
+/// ```toml
+/// [customizations]
+/// hostname = "web01"
+/// timezone = "US/Eastern"
+///
+/// [customizations.kernel]
+/// append = "nosmt=force"
+///
+/// [customizations.services]
+/// enabled = ["httpd", "sshd"]
+/// disabled = ["postfix"]
+///
+/// [[customizations.users]]
+/// name = "admin"
+/// key = "ssh-rsa AAAA... admin@example.com"
+/// groups = ["wheel"]
+///
+/// [[customizations.groups]]
+/// name = "widget"
+/// gid = 1100
+///
+/// [[customizations.sshkeys]]
+/// user = "root"
+/// key = "ssh-rsa AAAA... root@example.com"
+///
+/// [[customizations.files]]
+/// path = "/etc/motd"
+/// mode = "0644"
+/// data = "Welcome to web01"
+/// ```
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Customizations {
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub kernel: Option<Kernel>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub services: Option<Services>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub users: Vec<User>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub groups: Vec<UserGroup>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub sshkeys: Vec<SshKey>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub files: Vec<CustomFile>
+}
+
+/// Extra kernel arguments, appended to the kernel command line
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Kernel {
+    pub append: String
+}
+
+/// Services to enable or disable
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Services {
+    #[serde(default)]
+    pub enabled: Vec<String>,
+    #[serde(default)]
+    pub disabled: Vec<String>
+}
+
+/// A user account to create
+///
+/// `key` is an ssh public key to add to the user's authorized_keys, and `groups` is the list of
+/// supplementary groups to add the user to.
+///
+/// `password` must be a crypt(3) hash, eg. from `openssl passwd -6`, never the plaintext password.
+/// Recipes with any other password are rejected, see
+/// [is_crypted_password](../validate/fn.is_crypted_password.html) for the accepted formats.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct User {
+    pub name: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub home: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if="Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub gid: Option<u32>
+}
+
+/// A user group to create
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct UserGroup {
+    pub name: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub gid: Option<u32>
+}
+
+/// An ssh public key to add to an existing user, eg. root
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct SshKey {
+    pub user: String,
+    pub key: String
+}
+
+/// A file to write to the system
+///
+/// `mode` is an octal string, eg. `"0644"`, and `data` is the file's contents.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct CustomFile {
+    pub path: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub data: String
+}
+
+
 /// Recipe Diff Types
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
 pub enum RecipeDiffValue {
@@ -244,7 +467,19 @@ pub enum RecipeDiffValue {
     Package(Packages),
     Name(String),
     Description(Option<String>),
-    Version(String)
+    Version(String),
+    Arch(Vec<String>),
+    Include(Vec<String>),
+    Exclude(Vec<String>),
+    Group(Group),
+    Hostname(Option<String>),
+    Timezone(Option<String>),
+    Kernel(Option<Kernel>),
+    Services(Option<Services>),
+    User(User),
+    UserGroup(UserGroup),
+    SshKey(SshKey),
+    File(CustomFile)
 }
 
 /// Differences between two recipes
@@ -259,6 +494,23 @@ pub struct RecipeDiff {
     pub diff: Vec<RecipeDiffEntry>
 }
 
+/// A recipe change made on both branches of a merge
+///
+/// `ours` is the change on the branch being merged into, and `theirs` is the change on the
+/// branch being merged from. Both are relative to the merge base.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct RecipeMergeConflict {
+    pub ours: RecipeDiffEntry,
+    pub theirs: RecipeDiffEntry
+}
+
+/// The merged recipe, and the changes that could not be merged
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct RecipeMerge {
+    pub recipe: Recipe,
+    pub conflicts: Vec<RecipeMergeConflict>
+}
+
 
 // From 24 days of Rust
 /// Find master branch's HEAD and return it
@@ -377,7 +629,7 @@ pub fn init_repo(path: &str) -> Result<Repository, RecipeError> {
     let repo = try!(Repository::init_bare(&repo_path));
     {
         // Make an initial empty commit
-        let sig = try!(Signature::now("bdcs-api-server", "user-email"));
+        let sig = try!(Author::default().signature());
         let tree_id = {
             let mut index = try!(repo.index());
             try!(index.write_tree())
@@ -453,7 +705,7 @@ pub fn add_file(repo: &Repository, file: &str, branch: &str, replace: bool) -> R
         return Ok(false);
     }
 
-    write(repo, &recipe, branch, None)
+    write(repo, &recipe, branch, None, &Author::default())
 }
 
 
@@ -465,6 +717,8 @@ pub fn add_file(repo: &Repository, file: &str, branch: &str, replace: bool) -> R
 /// * `name` - Recipe name to write to (just the name, no path)
 /// * `contents` - An array of bytes to write
 /// * `branch` - Name of the branch to add to
+/// * `message` - Optional extra text for the commit message
+/// * `author` - The author of the commit
 ///
 /// # Return
 ///
@@ -474,7 +728,14 @@ pub fn add_file(repo: &Repository, file: &str, branch: &str, replace: bool) -> R
 /// If the branch does not exist, it will be created. By convention the `master`
 /// branch is used for example recipes.
 ///
-pub fn write(repo: &Repository, recipe: &Recipe, branch: &str, message: Option<&str>) -> Result<bool, RecipeError> {
+pub fn write(repo: &Repository, recipe: &Recipe, branch: &str, message: Option<&str>, author: &Author) -> Result<bool, RecipeError> {
+    write_commit(repo, recipe, branch, message, author, None)
+}
+
+/// Write a recipe to a branch, optionally as a merge of another commit
+///
+/// The commit's parents are the head of `branch`, and `merged` if it is set.
+fn write_commit(repo: &Repository, recipe: &Recipe, branch: &str, message: Option<&str>, author: &Author, merged: Option<Oid>) -> Result<bool, RecipeError> {
     // Does the branch exist? If not, create it based on master
     match repo.find_branch(branch, BranchType::Local) {
         Ok(_) => {}
@@ -498,9 +759,10 @@ pub fn write(repo: &Repository, recipe: &Recipe, branch: &str, message: Option<&
     // If it has an invalid semver in version, return an error.
     let new_version = try!(semver::Version::parse(&recipe.version));
 
-    // Save it with sorted packages and modules
+    // Save it with sorted packages, modules and groups
     recipe.packages.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
     recipe.modules.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    recipe.groups.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
 
     // Read the previous version of this recipe, compare its .version to the new one.
     // If they are the same bump the patch level before saving the new one.
@@ -524,7 +786,7 @@ pub fn write(repo: &Repository, recipe: &Recipe, branch: &str, message: Option<&
         tree.write().unwrap()
     };
     let tree = try!(repo.find_tree(tree_id));
-    let sig = try!(Signature::now("bdcs-api-server", "user-email"));
+    let sig = try!(author.signature());
     let commit_msg = {
         match message {
             Some(msg) => {
@@ -535,8 +797,16 @@ pub fn write(repo: &Repository, recipe: &Recipe, branch: &str, message: Option<&
             }
         }
     };
+    let merged_commit = match merged {
+        Some(id) => Some(try!(repo.find_commit(id))),
+        None => None
+    };
+    let mut parents = vec![&parent_commit];
+    if let Some(ref merged_commit) = merged_commit {
+        parents.push(merged_commit);
+    }
     let branch_ref = format!("refs/heads/{}", branch);
-    try!(repo.commit(Some(&branch_ref), &sig, &sig, &commit_msg, &tree, &[&parent_commit]));
+    try!(repo.commit(Some(&branch_ref), &sig, &sig, &commit_msg, &tree, &parents));
     debug!("Recipe commit:"; "branch" => branch, "recipe_name" => recipe.name, "commit_msg" => commit_msg);
 
     Ok(true)
@@ -574,6 +844,133 @@ pub fn read(repo: &Repository, name: &str, branch: &str, commit: Option<&str>) -
 }
 
 
+/// Read a recipe from a branch, and flatten the recipes it includes into it
+///
+/// # Arguments
+///
+/// * `repo` - An open Repository
+/// * `name` - Recipe name to read
+/// * `branch` - Name of the branch
+/// * `commit` - Commit to read from, or None for HEAD
+///
+/// # Return
+///
+/// * A Result with the flattened Recipe, or a RecipeError
+///
+/// See [flatten](fn.flatten.html) for details.
+///
+pub fn read_flattened(repo: &Repository, name: &str, branch: &str, commit: Option<&str>) -> Result<Recipe, RecipeError> {
+    let recipe = try!(read(repo, name, branch, commit));
+    flatten(repo, &recipe, branch, commit)
+}
+
+
+/// Add the modules, packages and groups of the included recipes to a recipe
+///
+/// # Arguments
+///
+/// * `repo` - An open Repository
+/// * `recipe` - The recipe to flatten
+/// * `branch` - Name of the branch to read the included recipes from
+/// * `commit` - Commit to read the included recipes from, or None for HEAD
+///
+/// # Return
+///
+/// * A Result with the flattened Recipe, with an empty `include` list, or a RecipeError
+///
+/// The included recipes are flattened first, and are added in the order they are listed. When
+/// more than one of them has a module or package with the same name, the later one is used, and
+/// the recipe's own modules and packages override all of them. The groups and excluded packages
+/// of all of the recipes are used. The name, description, version, arch and customizations are
+/// not changed.
+///
+/// It is an error if an included recipe cannot be read, or if a recipe includes itself.
+///
+pub fn flatten(repo: &Repository, recipe: &Recipe, branch: &str, commit: Option<&str>) -> Result<Recipe, RecipeError> {
+    flatten_includes(repo, recipe, branch, commit, &mut vec![recipe.name.clone()])
+}
+
+// Flatten a recipe, `parents` is the list of recipes that included this one
+fn flatten_includes(repo: &Repository, recipe: &Recipe, branch: &str, commit: Option<&str>,
+                    parents: &mut Vec<String>) -> Result<Recipe, RecipeError> {
+    let mut flat = Recipe {
+        include:  Vec::new(),
+        modules:  Vec::new(),
+        packages: Vec::new(),
+        exclude:  Vec::new(),
+        groups:   Vec::new(),
+        ..recipe.clone()
+    };
+
+    for name in &recipe.include {
+        if parents.contains(name) {
+            return Err(RecipeError::Include(format!("Recipe {} includes itself", name)));
+        }
+        let included = try!(read(repo, name, branch, commit).map_err(|e| {
+            RecipeError::Include(format!("Unable to read recipe {} included by {}: {}", name, recipe.name, e))
+        }));
+
+        parents.push(name.clone());
+        let included = try!(flatten_includes(repo, &included, branch, commit, parents));
+        parents.pop();
+
+        add_entries(&mut flat, &included);
+    }
+    add_entries(&mut flat, recipe);
+
+    flat.packages.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    flat.modules.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    flat.groups.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    flat.exclude.sort();
+    Ok(flat)
+}
+
+// Add the modules, packages, excludes and groups of `from` to a recipe, replacing the modules and
+// packages with the same name
+fn add_entries(recipe: &mut Recipe, from: &Recipe) {
+    for module in &from.modules {
+        recipe.modules.retain(|m| m.name != module.name);
+        recipe.modules.push(module.clone());
+    }
+    for package in &from.packages {
+        recipe.packages.retain(|p| p.name != package.name);
+        recipe.packages.push(package.clone());
+    }
+    for name in &from.exclude {
+        if !recipe.exclude.contains(name) {
+            recipe.exclude.push(name.clone());
+        }
+    }
+    for group in &from.groups {
+        if !recipe.groups.contains(group) {
+            recipe.groups.push(group.clone());
+        }
+    }
+}
+
+
+/// Return the branch to read recipes from
+///
+/// # Arguments
+///
+/// * `repo` - An open Repository
+/// * `branch` - Name of the branch
+///
+/// # Return
+///
+/// * The branch, or `master` if the branch does not exist yet
+///
+/// New branches are created from master when a recipe is first written to them, so until then
+/// their recipes are the ones in master.
+///
+pub fn branch_or_master<'a>(repo: &Repository, branch: &'a str) -> &'a str {
+    match repo.find_branch(branch, BranchType::Local) {
+        Ok(_) => branch,
+        Err(_) => "master"
+    }
+}
+
+
 /// List the recipes in a branch
 ///
 /// # Arguments
@@ -614,14 +1011,15 @@ pub fn list(repo: &Repository, branch: &str, _commit: Option<&str>) -> Result<Ve
 /// * `repo` - An open Repository
 /// * `name` - Recipe name to delete
 /// * `branch` - Name of the branch to add to
+/// * `author` - The author of the commit
 ///
 /// # Return
 ///
-/// * Result with () or a RecipeError
+/// * Result with true, false if the recipe is not in the branch, or a RecipeError
 ///
-/// Branch and filename must exist otherwise a RecipeError will be returned.
+/// The branch must exist otherwise a RecipeError will be returned.
 ///
-pub fn delete(repo: &Repository, recipe_name: &str, branch: &str) -> Result<bool, RecipeError> {
+pub fn delete(repo: &Repository, recipe_name: &str, branch: &str, author: &Author) -> Result<bool, RecipeError> {
     // Does the branch exist? If not, it's an error
     match repo.find_branch(branch, BranchType::Local) {
         Ok(_) => {}
@@ -637,11 +1035,15 @@ pub fn delete(repo: &Repository, recipe_name: &str, branch: &str) -> Result<bool
     let parent_commit = try!(repo.find_commit(branch_id));
     let tree_id = {
         let mut tree = repo.treebuilder(Some(&parent_commit.tree().unwrap())).unwrap();
+        // There is nothing to delete if the recipe is not in the branch
+        if try!(tree.get(&filename)).is_none() {
+            return Ok(false);
+        }
         try!(tree.remove(&filename));
         tree.write().unwrap()
     };
     let tree = try!(repo.find_tree(tree_id));
-    let sig = try!(Signature::now("bdcs-api-server", "user-email"));
+    let sig = try!(author.signature());
     let commit_msg = format!("Recipe {} deleted", filename);
     let branch_ref = format!("refs/heads/{}", branch);
     try!(repo.commit(Some(&branch_ref), &sig, &sig, &commit_msg, &tree, &[&parent_commit]));
@@ -660,6 +1062,7 @@ pub fn delete(repo: &Repository, recipe_name: &str, branch: &str) -> Result<bool
 /// * `name` - Recipe name to revert
 /// * `commit` - Commit hash to revert to
 /// * `branch` - Name of the branch
+/// * `author` - The author of the commit
 ///
 /// # Return
 ///
@@ -667,7 +1070,7 @@ pub fn delete(repo: &Repository, recipe_name: &str, branch: &str) -> Result<bool
 ///
 /// Branch and filename must exist otherwise a RecipeError will be returned.
 ///
-pub fn revert(repo: &Repository, recipe_name: &str, branch: &str, commit: &str) -> Result<bool, RecipeError> {
+pub fn revert(repo: &Repository, recipe_name: &str, branch: &str, commit: &str, author: &Author) -> Result<bool, RecipeError> {
     // Does the branch exist? If not, it's an error
     match repo.find_branch(branch, BranchType::Local) {
         Ok(_) => {}
@@ -694,7 +1097,7 @@ pub fn revert(repo: &Repository, recipe_name: &str, branch: &str, commit: &str)
         tree.write().unwrap()
     };
     let tree = try!(repo.find_tree(tree_id));
-    let sig = try!(Signature::now("bdcs-api-server", "user-email"));
+    let sig = try!(author.signature());
     let commit_msg = format!("Recipe {} reverted to commit {}", filename, commit);
     let branch_ref = format!("refs/heads/{}", branch);
     try!(repo.commit(Some(&branch_ref), &sig, &sig, &commit_msg, &tree, &[&parent_commit]));
@@ -892,6 +1295,7 @@ pub fn old_diff(repo: &Repository,
 ///
 /// * `repo` - An open Repository
 /// * `name` - Recipe name
+/// * `author` - The tagger
 ///
 /// # Return
 ///
@@ -902,7 +1306,7 @@ pub fn old_diff(repo: &Repository,
 /// Revisions start at 1 and increment for each new commit that is tagged.
 /// If the commit has already been tagged it will return false.
 ///
-pub fn tag(repo: &Repository, recipe_name: &str, branch: &str) -> Result<bool, RecipeError> {
+pub fn tag(repo: &Repository, recipe_name: &str, branch: &str, author: &Author) -> Result<bool, RecipeError> {
     let commits = try!(commits(repo, recipe_name, branch));
     if commits.len() < 1 {
         return Ok(false);
@@ -928,7 +1332,7 @@ pub fn tag(repo: &Repository, recipe_name: &str, branch: &str) -> Result<bool, R
     // Create a new commit with the next revision
     let filename = try!(recipe_filename(recipe_name));
     let name = format!("{}/{}/r{}", branch, filename, last_rev+1);
-    let sig = try!(Signature::now("bdcs-api-server", "user-email"));
+    let sig = try!(author.signature());
     let commit_oid = try!(Oid::from_str(&commits[0].commit));
     let target = try!(repo.find_object(commit_oid, Some(git2::ObjectType::Commit)));
 
@@ -982,6 +1386,29 @@ pub fn diff(old: &Recipe, new: &Recipe) -> Vec<RecipeDiffEntry> {
         });
     }
 
+    // arch is an empty list when it isn't set, so it is always just different
+    if old.arch != new.arch {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Arch(old.arch.clone())),
+            new: Some(RecipeDiffValue::Arch(new.arch.clone()))
+        });
+    }
+
+    // include is an empty list when it isn't set, so it is always just different
+    if old.include != new.include {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Include(old.include.clone())),
+            new: Some(RecipeDiffValue::Include(new.include.clone()))
+        });
+    }
+
+    if old.exclude != new.exclude {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Exclude(old.exclude.clone())),
+            new: Some(RecipeDiffValue::Exclude(new.exclude.clone()))
+        });
+    }
+
     // TODO If the recipe modules and packages are converted to the same struct
     // the following code duplication could be simplified somewhat. eg. Use a
     // NEVRA struct for both.
@@ -1078,5 +1505,375 @@ pub fn diff(old: &Recipe, new: &Recipe) -> Vec<RecipeDiffEntry> {
         }
     }
 
+    // groups can be added or removed
+    diffs.extend(diff_named(&old.groups, &new.groups, |g| g.name.clone(), RecipeDiffValue::Group));
+
+    // A missing customizations table is the same as an empty one
+    let old_customizations = old.customizations.clone().unwrap_or_default();
+    let new_customizations = new.customizations.clone().unwrap_or_default();
+    diffs.extend(diff_customizations(&old_customizations, &new_customizations));
+
+    diffs
+}
+
+
+/// Find the differences between two recipe customizations
+fn diff_customizations(old: &Customizations, new: &Customizations) -> Vec<RecipeDiffEntry> {
+    let mut diffs = Vec::new();
+
+    // hostname, timezone, kernel and services are set or unset, so they are always just different
+    if old.hostname != new.hostname {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Hostname(old.hostname.clone())),
+            new: Some(RecipeDiffValue::Hostname(new.hostname.clone()))
+        });
+    }
+
+    if old.timezone != new.timezone {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Timezone(old.timezone.clone())),
+            new: Some(RecipeDiffValue::Timezone(new.timezone.clone()))
+        });
+    }
+
+    if old.kernel != new.kernel {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Kernel(old.kernel.clone())),
+            new: Some(RecipeDiffValue::Kernel(new.kernel.clone()))
+        });
+    }
+
+    if old.services != new.services {
+        diffs.push(RecipeDiffEntry {
+            old: Some(RecipeDiffValue::Services(old.services.clone())),
+            new: Some(RecipeDiffValue::Services(new.services.clone()))
+        });
+    }
+
+    // users, groups, ssh keys and files can be added, removed, or changed.
+    diffs.extend(diff_named(&old.users, &new.users, |u| u.name.clone(), RecipeDiffValue::User));
+    diffs.extend(diff_named(&old.groups, &new.groups, |g| g.name.clone(), RecipeDiffValue::UserGroup));
+    // A user may have several keys, so each key is added or removed on its own
+    diffs.extend(diff_named(&old.sshkeys, &new.sshkeys, |k| format!("{} {}", k.user, k.key), RecipeDiffValue::SshKey));
+    diffs.extend(diff_named(&old.files, &new.files, |f| f.path.clone(), RecipeDiffValue::File));
+
     diffs
 }
+
+
+/// Find the added, removed, and changed entries in two lists of named items
+///
+/// `name` returns the name that identifies an item, and `wrap` converts it to a RecipeDiffValue.
+/// The entries are returned in the same order as the modules and packages from
+/// [diff](fn.diff.html), added then removed then changed, each sorted by name.
+fn diff_named<T, F, W>(old: &[T], new: &[T], name: F, wrap: W) -> Vec<RecipeDiffEntry>
+    where T: Clone + PartialEq,
+          F: Fn(&T) -> String,
+          W: Fn(T) -> RecipeDiffValue
+{
+    let mut names: Vec<String> = old.iter().chain(new.iter()).map(|i| name(i)).collect();
+    names.sort();
+    names.dedup();
+    names.sort_by_key(|n| n.to_lowercase());
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for n in &names {
+        let old_item = old.iter().find(|i| name(i) == *n);
+        let new_item = new.iter().find(|i| name(i) == *n);
+        match (old_item, new_item) {
+            (None, Some(new_item)) => added.push(RecipeDiffEntry {
+                old: None,
+                new: Some(wrap(new_item.clone()))
+            }),
+            (Some(old_item), None) => removed.push(RecipeDiffEntry {
+                old: Some(wrap(old_item.clone())),
+                new: None
+            }),
+            (Some(old_item), Some(new_item)) => if old_item != new_item {
+                changed.push(RecipeDiffEntry {
+                    old: Some(wrap(old_item.clone())),
+                    new: Some(wrap(new_item.clone()))
+                })
+            },
+            (None, None) => ()
+        }
+    }
+
+    added.into_iter().chain(removed).chain(changed).collect()
+}
+
+
+/// Merge a recipe from one branch into another
+///
+/// # Arguments
+///
+/// * `repo` - An open Repository
+/// * `name` - Recipe name
+/// * `from` - Name of the branch to merge from
+/// * `to` - Name of the branch to merge into
+/// * `author` - The author of the merge commit
+///
+/// # Return
+///
+/// * A RecipeMerge with the recipe in `to`, and any conflicts, or a RecipeError
+///
+/// This is a three-way merge of the recipe. The merge base is the recipe in the newest commit
+/// that is in the history of both branches, as found by `git merge-base`. If the recipe is not in
+/// that commit it is merged with an empty recipe.
+///
+/// The merged recipe is only written to `to` when there are no conflicts. If the recipe does
+/// not exist in `to` yet it is copied from `from`. Like a git merge, the new commit has the head
+/// of `from` as its second parent, so it is the merge base the next time the branches are merged.
+///
+pub fn merge(repo: &Repository, name: &str, from: &str, to: &str, author: &Author) -> Result<RecipeMerge, RecipeError> {
+    let message = format!("Merged from branch {}", from);
+    let theirs = try!(read(repo, name, from, None));
+    let from_id = try!(branch_head(repo, from));
+    let ours = match read(repo, name, to, None) {
+        Ok(recipe) => recipe,
+        Err(ref e) if is_not_found(e) => {
+            try!(write_commit(repo, &theirs, to, Some(&message), author, Some(from_id)));
+            return Ok(RecipeMerge {
+                recipe: try!(read(repo, name, to, None)),
+                conflicts: Vec::new()
+            });
+        },
+        Err(e) => return Err(e)
+    };
+
+    let base = match try!(read_merge_base(repo, name, from_id, try!(branch_head(repo, to)))) {
+        Some(recipe) => recipe,
+        None => Recipe { name: name.to_string(), ..Default::default() }
+    };
+    debug!("merge"; "name" => name, "from" => from, "to" => to, "base" => format!("{:?}", base));
+
+    let mut merged = merge_recipes(&base, &ours, &theirs);
+    if merged.conflicts.is_empty() && merged.recipe != ours {
+        try!(write_commit(repo, &merged.recipe, to, Some(&message), author, Some(from_id)));
+        // Read the new commit, the version may have been bumped when it was written
+        merged.recipe = try!(read(repo, name, to, None));
+    }
+
+    Ok(merged)
+}
+
+
+/// Return true if the error is a missing branch, commit or file
+fn is_not_found(err: &RecipeError) -> bool {
+    match *err {
+        RecipeError::Git2(ref e) => e.code() == git2::ErrorCode::NotFound,
+        _ => false
+    }
+}
+
+/// Return the id of the commit at the head of a branch
+fn branch_head(repo: &Repository, branch: &str) -> Result<Oid, RecipeError> {
+    let object = try!(repo.revparse_single(branch));
+    Ok(object.id())
+}
+
+/// Read a recipe from the merge base of two commits
+///
+/// Returns None if the commits have no common ancestor, or the recipe is not in it.
+fn read_merge_base(repo: &Repository, name: &str, from_id: Oid, to_id: Oid) -> Result<Option<Recipe>, RecipeError> {
+    let base_id = match repo.merge_base(from_id, to_id) {
+        Ok(id) => id,
+        Err(ref e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(RecipeError::from(e))
+    };
+
+    match read(repo, name, "", Some(&base_id.to_string())) {
+        Ok(recipe) => Ok(Some(recipe)),
+        Err(ref e) if is_not_found(e) => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+
+/// Three-way merge of two recipes
+///
+/// # Arguments
+///
+/// * `base` - The common version of the recipe
+/// * `ours` - The recipe being merged into
+/// * `theirs` - The recipe being merged from
+///
+/// # Return
+///
+/// * A RecipeMerge with the merged recipe and the conflicting changes
+///
+/// The changes made by each side are found using [diff](fn.diff.html). Changes to the
+/// description, arch, include, exclude, or to a single module, package or group are merged when
+/// only one side made them, or when both sides made the same change. Otherwise it is a conflict
+/// and `ours` is kept. The customizations are merged the same way, eg. each user or file is
+/// merged separately.
+///
+/// The version is not merged, the newest version of the two is used.
+///
+pub fn merge_recipes(base: &Recipe, ours: &Recipe, theirs: &Recipe) -> RecipeMerge {
+    let our_changes = diff(base, ours);
+    let mut merged = ours.clone();
+    let mut conflicts = Vec::new();
+
+    for their_change in diff(base, theirs) {
+        let key = diff_entry_key(&their_change);
+        match our_changes.iter().find(|c| diff_entry_key(c) == key) {
+            None => apply_diff_entry(&mut merged, &their_change),
+            Some(our_change) => {
+                if key != "version" && *our_change != their_change {
+                    conflicts.push(RecipeMergeConflict {
+                        ours: our_change.clone(),
+                        theirs: their_change.clone()
+                    });
+                }
+            }
+        }
+    }
+
+    let newer = match (ours.version(), theirs.version()) {
+        (Ok(our_version), Ok(their_version)) => their_version > our_version,
+        _ => false
+    };
+    if newer {
+        merged.version = theirs.version.clone();
+    }
+    merged.packages.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    merged.modules.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    merged.groups.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    if merged.customizations == Some(Customizations::default()) {
+        merged.customizations = None;
+    }
+
+    RecipeMerge {
+        recipe: merged,
+        conflicts: conflicts
+    }
+}
+
+
+/// Return the recipe field, module, or package that a diff entry changes
+fn diff_entry_key(entry: &RecipeDiffEntry) -> String {
+    match entry.new.as_ref().or(entry.old.as_ref()) {
+        Some(&RecipeDiffValue::Module(ref m)) => format!("module {}", m.name),
+        Some(&RecipeDiffValue::Package(ref p)) => format!("package {}", p.name),
+        Some(&RecipeDiffValue::Name(_)) => "name".to_string(),
+        Some(&RecipeDiffValue::Description(_)) => "description".to_string(),
+        Some(&RecipeDiffValue::Version(_)) => "version".to_string(),
+        Some(&RecipeDiffValue::Arch(_)) => "arch".to_string(),
+        Some(&RecipeDiffValue::Include(_)) => "include".to_string(),
+        Some(&RecipeDiffValue::Exclude(_)) => "exclude".to_string(),
+        Some(&RecipeDiffValue::Group(ref g)) => format!("group {}", g.name),
+        Some(&RecipeDiffValue::Hostname(_)) => "hostname".to_string(),
+        Some(&RecipeDiffValue::Timezone(_)) => "timezone".to_string(),
+        Some(&RecipeDiffValue::Kernel(_)) => "kernel".to_string(),
+        Some(&RecipeDiffValue::Services(_)) => "services".to_string(),
+        Some(&RecipeDiffValue::User(ref u)) => format!("user {}", u.name),
+        Some(&RecipeDiffValue::UserGroup(ref g)) => format!("usergroup {}", g.name),
+        Some(&RecipeDiffValue::SshKey(ref k)) => format!("sshkey {} {}", k.user, k.key),
+        Some(&RecipeDiffValue::File(ref f)) => format!("file {}", f.path),
+        None => String::new()
+    }
+}
+
+
+/// Apply a change from [diff](fn.diff.html) to a recipe
+///
+/// The name and version are not changed.
+fn apply_diff_entry(recipe: &mut Recipe, entry: &RecipeDiffEntry) {
+    match (&entry.old, &entry.new) {
+        (_, &Some(RecipeDiffValue::Description(ref description))) => recipe.description = description.clone(),
+        (_, &Some(RecipeDiffValue::Arch(ref arch))) => recipe.arch = arch.clone(),
+        (_, &Some(RecipeDiffValue::Include(ref include))) => recipe.include = include.clone(),
+        (_, &Some(RecipeDiffValue::Exclude(ref exclude))) => recipe.exclude = exclude.clone(),
+        (_, &Some(RecipeDiffValue::Module(ref module))) => {
+            recipe.modules.retain(|m| m.name != module.name);
+            recipe.modules.push(module.clone());
+        },
+        (&Some(RecipeDiffValue::Module(ref module)), &None) => recipe.modules.retain(|m| m.name != module.name),
+        (_, &Some(RecipeDiffValue::Package(ref package))) => {
+            recipe.packages.retain(|p| p.name != package.name);
+            recipe.packages.push(package.clone());
+        },
+        (&Some(RecipeDiffValue::Package(ref package)), &None) => recipe.packages.retain(|p| p.name != package.name),
+        (_, &Some(RecipeDiffValue::Group(ref group))) => {
+            recipe.groups.retain(|g| g.name != group.name);
+            recipe.groups.push(group.clone());
+        },
+        (&Some(RecipeDiffValue::Group(ref group)), &None) => recipe.groups.retain(|g| g.name != group.name),
+        (_, &Some(RecipeDiffValue::Hostname(ref hostname))) => customizations_mut(recipe).hostname = hostname.clone(),
+        (_, &Some(RecipeDiffValue::Timezone(ref timezone))) => customizations_mut(recipe).timezone = timezone.clone(),
+        (_, &Some(RecipeDiffValue::Kernel(ref kernel))) => customizations_mut(recipe).kernel = kernel.clone(),
+        (_, &Some(RecipeDiffValue::Services(ref services))) => customizations_mut(recipe).services = services.clone(),
+        (_, &Some(RecipeDiffValue::User(ref user))) => {
+            let customizations = customizations_mut(recipe);
+            customizations.users.retain(|u| u.name != user.name);
+            customizations.users.push(user.clone());
+        },
+        (&Some(RecipeDiffValue::User(ref user)), &None) => customizations_mut(recipe).users.retain(|u| u.name != user.name),
+        (_, &Some(RecipeDiffValue::UserGroup(ref group))) => {
+            let customizations = customizations_mut(recipe);
+            customizations.groups.retain(|g| g.name != group.name);
+            customizations.groups.push(group.clone());
+        },
+        (&Some(RecipeDiffValue::UserGroup(ref group)), &None) => customizations_mut(recipe).groups.retain(|g| g.name != group.name),
+        (_, &Some(RecipeDiffValue::SshKey(ref key))) => {
+            let customizations = customizations_mut(recipe);
+            customizations.sshkeys.retain(|k| k != key);
+            customizations.sshkeys.push(key.clone());
+        },
+        (&Some(RecipeDiffValue::SshKey(ref key)), &None) => customizations_mut(recipe).sshkeys.retain(|k| k != key),
+        (_, &Some(RecipeDiffValue::File(ref file))) => {
+            let customizations = customizations_mut(recipe);
+            customizations.files.retain(|f| f.path != file.path);
+            customizations.files.push(file.clone());
+        },
+        (&Some(RecipeDiffValue::File(ref file)), &None) => customizations_mut(recipe).files.retain(|f| f.path != file.path),
+        _ => ()
+    }
+}
+
+
+/// Return the recipe's customizations, adding an empty table if it doesn't have one
+fn customizations_mut(recipe: &mut Recipe) -> &mut Customizations {
+    if recipe.customizations.is_none() {
+        recipe.customizations = Some(Customizations::default());
+    }
+    // It was set above, so unwrap() is safe
+    recipe.customizations.as_mut().unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use recipe::*;
+
+    fn sshkeys_recipe(keys: &[(&str, &str)]) -> Recipe {
+        Recipe {
+            name: "sshkey-test".to_string(),
+            customizations: Some(Customizations {
+                sshkeys: keys.iter().map(|&(user, key)| SshKey { user: user.to_string(), key: key.to_string() }).collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_sshkeys() {
+        let old = sshkeys_recipe(&[("root", "ssh-rsa AAAA1 one"), ("root", "ssh-rsa AAAA2 two")]);
+        let new = sshkeys_recipe(&[("root", "ssh-rsa AAAA1 one"), ("root", "ssh-rsa AAAA3 three")]);
+
+        // Changing the second key of a user leaves the first one alone
+        let three = SshKey { user: "root".to_string(), key: "ssh-rsa AAAA3 three".to_string() };
+        let two = SshKey { user: "root".to_string(), key: "ssh-rsa AAAA2 two".to_string() };
+        assert_eq!(diff(&old, &new), vec![RecipeDiffEntry { old: None, new: Some(RecipeDiffValue::SshKey(three)) },
+                                          RecipeDiffEntry { old: Some(RecipeDiffValue::SshKey(two)), new: None }]);
+
+        // Applying the changes to the old recipe gives the new one
+        let merged = merge_recipes(&old, &old, &new);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.recipe, new);
+    }
+}
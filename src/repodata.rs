@@ -0,0 +1,624 @@
+//! Import Yum Repodata
+//!
+//! ## Overview
+//!
+//! Fill the metadata database from a local repository's `repodata`, without downloading the
+//! packages. `repodata/repomd.xml` points to the `primary` metadata, which has each package's
+//! details and dependencies, and to the `filelists` metadata, which has its files. Both may be
+//! gzipped. Each package is imported with [import_package](../import/fn.import_package.html), so
+//! the database looks the same as one made from the rpm files, except that:
+//!
+//! * The build's signature is the package's checksum from the repodata, eg. `SHA256`
+//! * The files are owned by root and have no mtime
+//! * The build has no changelog
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use rusqlite::Connection;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use import::{Dependency, ImportError, Package, PackageFile, import_package, srpm_name};
+
+
+/// The number of packages imported from the repodata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Packages that were already in the database
+    pub skipped: usize
+}
+
+impl ImportSummary {
+    // Count the result of import_package
+    fn add(&mut self, result: Option<i64>) {
+        if result.is_some() {
+            self.imported += 1;
+        } else {
+            self.skipped += 1;
+        }
+    }
+}
+
+/// Return true if the directory has a `repodata/repomd.xml`
+pub fn is_repo<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().join("repodata/repomd.xml").is_file()
+}
+
+/// Import the packages from a repository's repodata
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `repo` - The repository's directory, the one holding `repodata/`
+///
+/// # Returns
+///
+/// * An [ImportSummary](struct.ImportSummary.html) with the number of packages imported
+///
+/// Each package is imported in its own transaction. Packages without an entry in the filelists
+/// are imported without any files.
+///
+pub fn import_repodata<P: AsRef<Path>>(conn: &Connection, repo: P) -> Result<ImportSummary, ImportError> {
+    let repo = repo.as_ref();
+    let locations = try!(read_repomd(try!(File::open(repo.join("repodata/repomd.xml")))));
+    let primary = try!(locations.get("primary")
+                                .ok_or_else(|| ImportError::Repodata("repomd.xml has no primary data".to_string())));
+
+    let mut packages = try!(read_primary(try!(open_data(&repo.join(primary)))));
+    let mut summary = ImportSummary { imported: 0, skipped: 0 };
+
+    if let Some(filelists) = locations.get("filelists") {
+        let reader = try!(open_data(&repo.join(filelists)));
+        try!(read_filelists(reader, |pkgid, files| {
+            if let Some(mut package) = packages.take(pkgid) {
+                package.files = files;
+                summary.add(try!(import_package(conn, &package)));
+            }
+            Ok(())
+        }));
+    }
+
+    for package in packages.into_remaining() {
+        summary.add(try!(import_package(conn, &package)));
+    }
+
+    Ok(summary)
+}
+
+// Open a repodata file, decompressing it if it is gzipped
+fn open_data(path: &Path) -> Result<Box<Read>, ImportError> {
+    let file = BufReader::new(try!(File::open(path)));
+    if path.extension().map(|ext| ext == "gz").unwrap_or(false) {
+        Ok(Box::new(try!(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|a| a.name.local_name == name).map(|a| a.value.as_str())
+}
+
+
+/// Read the location of each type of data from repomd.xml
+///
+/// # Returns
+///
+/// * A HashMap of the data type, eg. `primary`, to its path relative to the repository
+///
+pub fn read_repomd<R: Read>(reader: R) -> Result<HashMap<String, String>, ImportError> {
+    let mut locations = HashMap::new();
+    let mut data_type = None;
+
+    for event in EventReader::new(reader) {
+        match try!(event) {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                match name.local_name.as_str() {
+                    "data" => data_type = attribute(&attributes, "type").map(String::from),
+                    "location" => {
+                        if let (Some(data_type), Some(href)) = (data_type.clone(), attribute(&attributes, "href")) {
+                            locations.insert(data_type, href.to_string());
+                        }
+                    },
+                    _ => ()
+                }
+            },
+            XmlEvent::EndElement { ref name } if name.local_name == "data" => data_type = None,
+            _ => ()
+        }
+    }
+    Ok(locations)
+}
+
+
+/// The packages read from the primary data, in order, by their pkgid
+#[derive(Debug, Default)]
+pub struct PrimaryPackages {
+    packages: Vec<Option<Package>>,
+    ids: HashMap<String, usize>
+}
+
+impl PrimaryPackages {
+    fn push(&mut self, pkgid: String, package: Package) {
+        self.ids.insert(pkgid, self.packages.len());
+        self.packages.push(Some(package));
+    }
+
+    /// The number of packages that have not been taken
+    pub fn len(&self) -> usize {
+        self.packages.iter().filter(|p| p.is_some()).count()
+    }
+
+    /// Return true if all of the packages have been taken
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove a package by its pkgid, the checksum used to match it with the filelists
+    pub fn take(&mut self, pkgid: &str) -> Option<Package> {
+        match self.ids.get(pkgid) {
+            Some(&i) => self.packages[i].take(),
+            None => None
+        }
+    }
+
+    /// The packages that have not been taken, in the order they were read
+    pub fn into_remaining(self) -> Vec<Package> {
+        self.packages.into_iter().filter_map(|p| p).collect()
+    }
+}
+
+// Make the dependency from a primary rpm:entry element
+//
+// The epoch is left out of the version when it is 0, like rpm does.
+fn entry_dependency(attributes: &[OwnedAttribute]) -> Option<Dependency> {
+    let name = try_opt!(attribute(attributes, "name"), None);
+    let operator = match attribute(attributes, "flags") {
+        Some("EQ") => "=",
+        Some("LT") => "<",
+        Some("LE") => "<=",
+        Some("GT") => ">",
+        Some("GE") => ">=",
+        _ => ""
+    };
+
+    let mut version = String::new();
+    match attribute(attributes, "epoch") {
+        Some(epoch) if epoch != "0" => version.push_str(&format!("{}:", epoch)),
+        _ => ()
+    }
+    version.push_str(attribute(attributes, "ver").unwrap_or(""));
+    if let Some(release) = attribute(attributes, "rel") {
+        version.push_str(&format!("-{}", release));
+    }
+
+    let context = if name.starts_with("rpmlib(") {
+        "Feature"
+    } else if attribute(attributes, "pre") == Some("1") {
+        "ScriptPre"
+    } else {
+        "Runtime"
+    };
+    Some(Dependency::new(name, operator, &version, context))
+}
+
+/// Read the packages from the primary data
+///
+/// The `<file>` elements are skipped, primary only lists some of the package's files. They are
+/// read from the filelists instead.
+///
+pub fn read_primary<R: Read>(reader: R) -> Result<PrimaryPackages, ImportError> {
+    let mut packages = PrimaryPackages::default();
+    let mut package: Option<Package> = None;
+    let mut pkgid: Option<String> = None;
+    let mut checksum_type = String::new();
+    let mut dependency_list: Option<String> = None;
+    let mut text = String::new();
+
+    for event in EventReader::new(reader) {
+        match try!(event) {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                text.clear();
+                if name.local_name == "package" {
+                    package = Some(Package::default());
+                    continue;
+                }
+                let package = match package {
+                    Some(ref mut package) => package,
+                    None => continue
+                };
+                match name.local_name.as_str() {
+                    "version" => {
+                        // An epoch of 0 is the same as no epoch
+                        package.epoch = match attribute(&attributes, "epoch").and_then(|e| e.parse().ok()) {
+                            Some(0) => None,
+                            epoch => epoch
+                        };
+                        package.version = attribute(&attributes, "ver").unwrap_or("").to_string();
+                        package.release = attribute(&attributes, "rel").unwrap_or("").to_string();
+                    },
+                    "checksum" => {
+                        checksum_type = attribute(&attributes, "type").unwrap_or("").to_uppercase();
+                    },
+                    "time" => {
+                        package.build_time = attribute(&attributes, "build").and_then(|t| t.parse().ok()).unwrap_or(0);
+                    },
+                    "provides" | "requires" | "conflicts" | "obsoletes" => {
+                        dependency_list = Some(name.local_name.clone());
+                    },
+                    "entry" => {
+                        let list = match dependency_list.as_ref().map(String::as_str) {
+                            Some("provides") => &mut package.provides,
+                            Some("requires") => &mut package.requires,
+                            Some("conflicts") => &mut package.conflicts,
+                            Some("obsoletes") => &mut package.obsoletes,
+                            _ => continue
+                        };
+                        if let Some(dependency) = entry_dependency(&attributes) {
+                            list.push(dependency);
+                        }
+                    },
+                    _ => ()
+                }
+            },
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => text.push_str(&s),
+            XmlEvent::EndElement { name } => {
+                if name.local_name == "package" {
+                    match (package.take(), pkgid.take()) {
+                        (Some(mut p), Some(id)) => {
+                            if p.source_name.is_empty() {
+                                p.source_name = p.name.clone();
+                            }
+                            packages.push(id, p);
+                        },
+                        _ => return Err(ImportError::Repodata("A package in primary has no pkgid checksum".to_string()))
+                    }
+                    continue;
+                }
+                let package = match package {
+                    Some(ref mut package) => package,
+                    None => continue
+                };
+                let value = text.trim().to_string();
+                match name.local_name.as_str() {
+                    "name" => package.name = value,
+                    "arch" => package.arch = value,
+                    "summary" => package.summary = value,
+                    "description" => package.description = value,
+                    "url" if !value.is_empty() => package.url = Some(value),
+                    "license" => package.license = value,
+                    "sourcerpm" => package.source_name = srpm_name(&value).unwrap_or_default(),
+                    "checksum" => {
+                        package.signatures.push((checksum_type.clone(), value.clone().into_bytes()));
+                        pkgid = Some(value);
+                    },
+                    "provides" | "requires" | "conflicts" | "obsoletes" => dependency_list = None,
+                    _ => ()
+                }
+                text.clear();
+            },
+            _ => ()
+        }
+    }
+    Ok(packages)
+}
+
+/// Read the files of each package from the filelists data
+///
+/// # Arguments
+///
+/// * `reader` - The filelists data
+/// * `f` - Called with the pkgid and files of each package, as it is read
+///
+pub fn read_filelists<R, F>(reader: R, mut f: F) -> Result<(), ImportError>
+    where R: Read,
+          F: FnMut(&str, Vec<PackageFile>) -> Result<(), ImportError>
+{
+    let mut pkgid: Option<String> = None;
+    let mut files = Vec::new();
+    let mut text = String::new();
+
+    for event in EventReader::new(reader) {
+        match try!(event) {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                text.clear();
+                if name.local_name == "package" {
+                    pkgid = attribute(&attributes, "pkgid").map(String::from);
+                    files.clear();
+                }
+            },
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => text.push_str(&s),
+            XmlEvent::EndElement { name } => {
+                match name.local_name.as_str() {
+                    "file" => files.push(PackageFile::new(text.trim())),
+                    "package" => {
+                        let id = try!(pkgid.take().ok_or_else(|| ImportError::Repodata("A package in filelists has no pkgid".to_string())));
+                        try!(f(&id, files.drain(..).collect()));
+                    },
+                    _ => ()
+                }
+                text.clear();
+            },
+            _ => ()
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use import::ImportError;
+    use repodata::*;
+    use rusqlite::Connection;
+    use test_helper::*;
+
+    const REPOMD: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm">
+  <revision>1501708401</revision>
+  <data type="primary">
+    <checksum type="sha256">0a1b</checksum>
+    <location href="repodata/0a1b-primary.xml.gz"/>
+  </data>
+  <data type="filelists">
+    <checksum type="sha256">2c3d</checksum>
+    <location href="repodata/2c3d-filelists.xml.gz"/>
+  </data>
+</repomd>
+"#;
+
+    const PRIMARY: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="2">
+<package type="rpm">
+  <name>bash</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="4.4.12" rel="5.fc26"/>
+  <checksum type="sha256" pkgid="YES">b4sh</checksum>
+  <summary>The GNU Bourne Again shell</summary>
+  <description><![CDATA[The GNU Bourne Again shell (Bash)]]></description>
+  <url>http://www.gnu.org/software/bash</url>
+  <time file="1501708500" build="1501708401"/>
+  <location href="Packages/b/bash-4.4.12-5.fc26.x86_64.rpm"/>
+  <format>
+    <rpm:license>GPLv3+</rpm:license>
+    <rpm:sourcerpm>bash-4.4.12-5.fc26.src.rpm</rpm:sourcerpm>
+    <rpm:provides>
+      <rpm:entry name="/bin/sh"/>
+      <rpm:entry name="bash" flags="EQ" epoch="0" ver="4.4.12" rel="5.fc26"/>
+    </rpm:provides>
+    <rpm:requires>
+      <rpm:entry name="/bin/sh" pre="1"/>
+      <rpm:entry name="filesystem" flags="GE" epoch="0" ver="3"/>
+      <rpm:entry name="rpmlib(FileDigests)" flags="LE" epoch="0" ver="4.6.0" rel="1"/>
+    </rpm:requires>
+    <rpm:conflicts>
+      <rpm:entry name="bash-old" flags="LT" epoch="1" ver="2"/>
+    </rpm:conflicts>
+    <file>/usr/bin/bash</file>
+  </format>
+</package>
+<package type="rpm">
+  <name>filesystem</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="3.2" rel="40.fc26"/>
+  <checksum type="sha256" pkgid="YES">f1l3</checksum>
+  <summary>The basic directory layout</summary>
+  <description>The filesystem package</description>
+  <url></url>
+  <time file="1501708500" build="1501708300"/>
+  <format>
+    <rpm:license>Public Domain</rpm:license>
+    <rpm:sourcerpm>filesystem-3.2-40.fc26.src.rpm</rpm:sourcerpm>
+  </format>
+</package>
+</metadata>
+"#;
+
+    // Two subpackages built from the same source rpm
+    const SUBPACKAGES: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="2">
+<package type="rpm">
+  <name>bash</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="4.4.12" rel="5.fc26"/>
+  <checksum type="sha256" pkgid="YES">b4sh</checksum>
+  <summary>The GNU Bourne Again shell</summary>
+  <time file="1501708500" build="1501708401"/>
+  <format>
+    <rpm:license>GPLv3+</rpm:license>
+    <rpm:sourcerpm>bash-4.4.12-5.fc26.src.rpm</rpm:sourcerpm>
+  </format>
+</package>
+<package type="rpm">
+  <name>bash-doc</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="4.4.12" rel="5.fc26"/>
+  <checksum type="sha256" pkgid="YES">d0c</checksum>
+  <summary>Documentation files for bash</summary>
+  <time file="1501708500" build="1501708401"/>
+  <format>
+    <rpm:license>GPLv3+</rpm:license>
+    <rpm:sourcerpm>bash-4.4.12-5.fc26.src.rpm</rpm:sourcerpm>
+  </format>
+</package>
+</metadata>
+"#;
+
+    const FILELISTS: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<filelists xmlns="http://linux.duke.edu/metadata/filelists" packages="1">
+<package pkgid="b4sh" name="bash" arch="x86_64">
+  <version epoch="0" ver="4.4.12" rel="5.fc26"/>
+  <file type="dir">/usr/share/doc/bash</file>
+  <file>/usr/bin/bash</file>
+  <file>/usr/bin/sh</file>
+</package>
+</filelists>
+"#;
+
+    fn write_gz(path: &PathBuf, data: &str) {
+        let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::Default);
+        encoder.write_all(data.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    fn create_repo() -> TestDir {
+        let repo = TestDir::new("bdcs-repodata-test");
+        create_dir_all(repo.path.join("repodata")).unwrap();
+        File::create(repo.path.join("repodata/repomd.xml")).unwrap().write_all(REPOMD.as_bytes()).unwrap();
+        write_gz(&repo.path.join("repodata/0a1b-primary.xml.gz"), PRIMARY);
+        write_gz(&repo.path.join("repodata/2c3d-filelists.xml.gz"), FILELISTS);
+        repo
+    }
+
+    fn strings(conn: &Connection, sql: &str) -> Vec<String> {
+        conn.prepare(sql).unwrap()
+            .query_map(&[], |row| row.get(0)).unwrap()
+            .map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_read_repomd() {
+        let locations = read_repomd(REPOMD.as_bytes()).unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations["primary"], "repodata/0a1b-primary.xml.gz");
+        assert_eq!(locations["filelists"], "repodata/2c3d-filelists.xml.gz");
+    }
+
+    #[test]
+    fn test_read_primary() {
+        let mut packages = read_primary(PRIMARY.as_bytes()).unwrap();
+        assert_eq!(packages.len(), 2);
+
+        let bash = packages.take("b4sh").unwrap();
+        assert_eq!((bash.name.as_str(), bash.epoch, bash.version.as_str(), bash.release.as_str(), bash.arch.as_str()),
+                   ("bash", None, "4.4.12", "5.fc26", "x86_64"));
+        assert_eq!(bash.description, "The GNU Bourne Again shell (Bash)");
+        assert_eq!(bash.license, "GPLv3+");
+        assert_eq!(bash.source_name, "bash");
+        assert_eq!(bash.build_time, 1501708401);
+        assert_eq!(bash.signatures, vec![("SHA256".to_string(), b"b4sh".to_vec())]);
+        // Primary only lists some of the files
+        assert!(bash.files.is_empty());
+
+        assert_eq!(bash.provides.iter().map(|d| d.expr.as_str()).collect::<Vec<&str>>(),
+                   vec!["/bin/sh", "bash = 4.4.12-5.fc26"]);
+        assert_eq!(bash.requires.iter().map(|d| (d.context, d.expr.as_str())).collect::<Vec<(&str, &str)>>(),
+                   vec![("ScriptPre", "/bin/sh"),
+                        ("Runtime", "filesystem >= 3"),
+                        ("Feature", "rpmlib(FileDigests) <= 4.6.0-1")]);
+        assert_eq!(bash.conflicts[0].expr, "bash-old < 1:2");
+        assert!(bash.obsoletes.is_empty());
+
+        assert!(packages.take("b4sh").is_none());
+        let remaining = packages.into_remaining();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].name, "filesystem");
+        assert_eq!(remaining[0].url, None);
+    }
+
+    #[test]
+    fn test_read_filelists() {
+        let mut packages = Vec::new();
+        read_filelists(FILELISTS.as_bytes(), |pkgid, files| {
+            packages.push((pkgid.to_string(), files.into_iter().map(|f| f.path).collect::<Vec<String>>()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(packages, vec![("b4sh".to_string(), vec!["/usr/share/doc/bash".to_string(),
+                                                             "/usr/bin/bash".to_string(),
+                                                             "/usr/bin/sh".to_string()])]);
+    }
+
+    #[test]
+    fn test_import_repodata() {
+        let conn = create_test_db(&[]).unwrap();
+        let dir = create_repo();
+        let repo = &dir.path;
+        assert!(is_repo(repo));
+
+        assert_eq!(import_repodata(&conn, repo).unwrap(), ImportSummary { imported: 2, skipped: 0 });
+        assert_eq!(strings(&conn, "select name from groups order by id"), vec!["bash", "filesystem"]);
+        assert_eq!(strings(&conn, "select req_expr from requirements order by id"),
+                   vec!["/bin/sh", "filesystem >= 3", "rpmlib(FileDigests) <= 4.6.0-1"]);
+        assert_eq!(strings(&conn, "
+            select files.path from files, group_files, groups
+            on files.id == group_files.file_id and group_files.group_id == groups.id
+            where groups.name == 'bash' order by files.id"),
+                   vec!["/usr/share/doc/bash", "/usr/bin/bash", "/usr/bin/sh"]);
+        assert_eq!(strings(&conn, "
+            select key_val.ext_value from key_val, group_key_values
+            on key_val.id == group_key_values.key_val_id
+            where key_val.key_value == 'TextKey \"rpm-provide\"' order by key_val.id"),
+                   vec!["/bin/sh", "bash = 4.4.12-5.fc26"]);
+
+        // Importing it again skips all of the packages
+        assert_eq!(import_repodata(&conn, repo).unwrap(), ImportSummary { imported: 0, skipped: 2 });
+    }
+
+    #[test]
+    fn test_import_repodata_subpackages() {
+        let conn = create_test_db(&[]).unwrap();
+        let dir = create_repo();
+        let repo = &dir.path;
+        write_gz(&repo.join("repodata/0a1b-primary.xml.gz"), SUBPACKAGES);
+
+        assert_eq!(import_repodata(&conn, repo).unwrap(), ImportSummary { imported: 2, skipped: 0 });
+        assert_eq!(strings(&conn, "select name from groups order by id"), vec!["bash", "bash-doc"]);
+        assert_eq!(strings(&conn, "select cast(count(distinct source_id) as text) from builds"), vec!["1"]);
+
+        assert_eq!(import_repodata(&conn, repo).unwrap(), ImportSummary { imported: 0, skipped: 2 });
+    }
+
+    #[test]
+    fn test_import_repodata_errors() {
+        let conn = create_test_db(&[]).unwrap();
+        let dir = TestDir::new("bdcs-repodata-test");
+        let repo = &dir.path;
+        assert!(!is_repo(repo));
+        match import_repodata(&conn, repo) {
+            Err(ImportError::IO(_)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        create_dir_all(repo.join("repodata")).unwrap();
+        File::create(repo.join("repodata/repomd.xml")).unwrap().write_all(b"<repomd></repomd>").unwrap();
+        match import_repodata(&conn, repo) {
+            Err(ImportError::Repodata(_)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        File::create(repo.join("repodata/repomd.xml")).unwrap().write_all(b"<repomd><data").unwrap();
+        match import_repodata(&conn, repo) {
+            Err(ImportError::XML(_)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+}
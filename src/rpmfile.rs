@@ -0,0 +1,399 @@
+//! RPM File Functions
+//!
+//! ## Overview
+//!
+//! Read the headers of an `.rpm` file. The file starts with a 96 byte lead, followed by the
+//! signature header, which is padded to a multiple of 8 bytes, and then the main header. The
+//! compressed payload is not read.
+//!
+//! Each header is an index of tags, pointing into a store of data. The tag values can be read
+//! with [Header](struct.Header.html)'s methods, using the tag numbers from `rpmtag.h`.
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const LEAD_SIZE: usize = 96;
+const HEADER_MAGIC: [u8; 4] = [0x8e, 0xad, 0xe8, 0x01];
+
+// Limits from rpm's header.c, so that a bad file can't make us allocate huge buffers
+const MAX_INDEX_ENTRIES: usize = 0xffff;
+const MAX_STORE_SIZE: usize = 256 * 1024 * 1024;
+
+// Tag data types
+const TYPE_CHAR: u32 = 1;
+const TYPE_INT8: u32 = 2;
+const TYPE_INT16: u32 = 3;
+const TYPE_INT32: u32 = 4;
+const TYPE_STRING: u32 = 6;
+const TYPE_BIN: u32 = 7;
+const TYPE_STRING_ARRAY: u32 = 8;
+const TYPE_I18NSTRING: u32 = 9;
+
+// Signature header tags
+pub const SIGTAG_RSA: u32 = 268;
+pub const SIGTAG_SHA1: u32 = 269;
+
+// Main header tags
+pub const RPMTAG_NAME: u32 = 1000;
+pub const RPMTAG_VERSION: u32 = 1001;
+pub const RPMTAG_RELEASE: u32 = 1002;
+pub const RPMTAG_EPOCH: u32 = 1003;
+pub const RPMTAG_SUMMARY: u32 = 1004;
+pub const RPMTAG_DESCRIPTION: u32 = 1005;
+pub const RPMTAG_BUILDTIME: u32 = 1006;
+pub const RPMTAG_LICENSE: u32 = 1014;
+pub const RPMTAG_URL: u32 = 1020;
+pub const RPMTAG_ARCH: u32 = 1022;
+pub const RPMTAG_OLDFILENAMES: u32 = 1027;
+pub const RPMTAG_FILEMTIMES: u32 = 1034;
+pub const RPMTAG_FILEUSERNAME: u32 = 1039;
+pub const RPMTAG_FILEGROUPNAME: u32 = 1040;
+pub const RPMTAG_SOURCERPM: u32 = 1044;
+pub const RPMTAG_PROVIDENAME: u32 = 1047;
+pub const RPMTAG_REQUIREFLAGS: u32 = 1048;
+pub const RPMTAG_REQUIRENAME: u32 = 1049;
+pub const RPMTAG_REQUIREVERSION: u32 = 1050;
+pub const RPMTAG_CONFLICTFLAGS: u32 = 1053;
+pub const RPMTAG_CONFLICTNAME: u32 = 1054;
+pub const RPMTAG_CONFLICTVERSION: u32 = 1055;
+pub const RPMTAG_CHANGELOGTEXT: u32 = 1082;
+pub const RPMTAG_OBSOLETENAME: u32 = 1090;
+pub const RPMTAG_PROVIDEFLAGS: u32 = 1112;
+pub const RPMTAG_PROVIDEVERSION: u32 = 1113;
+pub const RPMTAG_OBSOLETEFLAGS: u32 = 1114;
+pub const RPMTAG_OBSOLETEVERSION: u32 = 1115;
+pub const RPMTAG_DIRINDEXES: u32 = 1116;
+pub const RPMTAG_BASENAMES: u32 = 1117;
+pub const RPMTAG_DIRNAMES: u32 = 1118;
+
+
+/// RPM File Errors
+#[derive(Debug)]
+pub enum RPMError {
+    IO(io::Error),
+    /// The file does not start with the rpm lead
+    NotAnRPM,
+    /// A header is badly formed
+    BadHeader(String),
+}
+
+impl fmt::Display for RPMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RPMError::IO(ref e) => write!(f, "IO error: {}", e),
+            RPMError::NotAnRPM => write!(f, "Not an rpm file"),
+            RPMError::BadHeader(ref msg) => write!(f, "Bad rpm header: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for RPMError {
+    fn from(err: io::Error) -> RPMError {
+        RPMError::IO(err)
+    }
+}
+
+
+// An entry in the header's index
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    data_type: u32,
+    offset: usize,
+    count: usize
+}
+
+/// An rpm header
+#[derive(Debug, Default)]
+pub struct Header {
+    // The number of entries in the file's index, a tag may be repeated
+    entries: usize,
+    index: HashMap<u32, IndexEntry>,
+    store: Vec<u8>
+}
+
+impl Header {
+    /// Read a header, starting at its magic
+    pub fn read<R: Read>(reader: &mut R) -> Result<Header, RPMError> {
+        let mut intro = [0; 16];
+        try!(reader.read_exact(&mut intro));
+        if intro[0..4] != HEADER_MAGIC[..] {
+            return Err(RPMError::BadHeader("bad magic".to_string()));
+        }
+
+        let entries = be_u32(&intro[8..12]) as usize;
+        let store_size = be_u32(&intro[12..16]) as usize;
+        if entries > MAX_INDEX_ENTRIES || store_size > MAX_STORE_SIZE {
+            return Err(RPMError::BadHeader(format!("too large, {} entries and {} bytes", entries, store_size)));
+        }
+
+        let mut index_data = vec![0; entries * 16];
+        try!(reader.read_exact(&mut index_data));
+        let mut store = vec![0; store_size];
+        try!(reader.read_exact(&mut store));
+
+        let mut index = HashMap::new();
+        for entry in index_data.chunks(16) {
+            let tag = be_u32(&entry[0..4]);
+            let entry = IndexEntry {
+                data_type: be_u32(&entry[4..8]),
+                offset:    be_u32(&entry[8..12]) as usize,
+                count:     be_u32(&entry[12..16]) as usize
+            };
+            match data_end(&entry) {
+                Some(end) if end <= store.len() => (),
+                _ => return Err(RPMError::BadHeader(format!("tag {} is outside of the header", tag)))
+            }
+            index.insert(tag, entry);
+        }
+
+        Ok(Header { entries: entries, index: index, store: store })
+    }
+
+    /// The size of the header, as stored in the file
+    pub fn size(&self) -> usize {
+        16 + self.entries * 16 + self.store.len()
+    }
+
+    /// Return true if the header has the tag
+    pub fn has_tag(&self, tag: u32) -> bool {
+        self.index.contains_key(&tag)
+    }
+
+    /// Return a string tag
+    ///
+    /// For array and i18n tags this is the first string.
+    pub fn get_string(&self, tag: u32) -> Option<String> {
+        self.get_strings(tag).into_iter().next()
+    }
+
+    /// Return the strings of a string, string array, or i18n string tag
+    ///
+    /// An i18n tag has one string for each language, the first is the untranslated string.
+    pub fn get_strings(&self, tag: u32) -> Vec<String> {
+        let entry = try_opt!(self.index.get(&tag), vec![]);
+        let count = match entry.data_type {
+            TYPE_STRING => 1,
+            TYPE_STRING_ARRAY | TYPE_I18NSTRING => entry.count,
+            _ => return vec![]
+        };
+
+        let mut strings = Vec::new();
+        let mut data = &self.store[entry.offset..];
+        for _ in 0..count {
+            let end = try_opt!(data.iter().position(|b| *b == 0), strings);
+            strings.push(String::from_utf8_lossy(&data[..end]).into_owned());
+            data = &data[end + 1..];
+        }
+        strings
+    }
+
+    /// Return the numbers of an integer tag
+    pub fn get_numbers(&self, tag: u32) -> Vec<u32> {
+        let entry = try_opt!(self.index.get(&tag), vec![]);
+        let size = match entry.data_type {
+            TYPE_CHAR | TYPE_INT8 => 1,
+            TYPE_INT16 => 2,
+            TYPE_INT32 => 4,
+            _ => return vec![]
+        };
+        let data = try_opt!(self.get_data(entry, size), vec![]);
+
+        data.chunks(size).map(|n| match size {
+            1 => u32::from(n[0]),
+            2 => u32::from(n[0]) << 8 | u32::from(n[1]),
+            _ => be_u32(n)
+        }).collect()
+    }
+
+    /// Return the first number of an integer tag
+    pub fn get_number(&self, tag: u32) -> Option<u32> {
+        self.get_numbers(tag).into_iter().next()
+    }
+
+    /// Return the bytes of a binary tag
+    pub fn get_bin(&self, tag: u32) -> Option<Vec<u8>> {
+        let entry = try_opt!(self.index.get(&tag), None);
+        if entry.data_type != TYPE_BIN {
+            return None;
+        }
+        self.get_data(entry, 1).map(|data| data.to_vec())
+    }
+
+    /// Return the paths of the files in the package
+    ///
+    /// Newer packages store the directories and basenames separately, older packages store the
+    /// full paths.
+    pub fn file_paths(&self) -> Vec<String> {
+        let basenames = self.get_strings(RPMTAG_BASENAMES);
+        if basenames.is_empty() {
+            return self.get_strings(RPMTAG_OLDFILENAMES);
+        }
+
+        let dirnames = self.get_strings(RPMTAG_DIRNAMES);
+        let dirindexes = self.get_numbers(RPMTAG_DIRINDEXES);
+        basenames.iter().zip(dirindexes.iter())
+                 .filter_map(|(base, i)| dirnames.get(*i as usize).map(|dir| format!("{}{}", dir, base)))
+                 .collect()
+    }
+
+    // Return count items of size bytes from the store, or None if they run off the end
+    fn get_data(&self, entry: &IndexEntry, size: usize) -> Option<&[u8]> {
+        let end = try_opt!(entry.count.checked_mul(size).and_then(|len| entry.offset.checked_add(len)), None);
+        if end > self.store.len() {
+            return None;
+        }
+        Some(&self.store[entry.offset..end])
+    }
+}
+
+// Return the end of the entry's data in the store, or None if it overflows. The strings are NUL
+// terminated, so only their start is checked.
+fn data_end(entry: &IndexEntry) -> Option<usize> {
+    let size = match entry.data_type {
+        TYPE_CHAR | TYPE_INT8 | TYPE_BIN => 1,
+        TYPE_INT16 => 2,
+        TYPE_INT32 => 4,
+        _ => 0
+    };
+    entry.count.checked_mul(size).and_then(|len| entry.offset.checked_add(len))
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    u32::from(data[0]) << 24 | u32::from(data[1]) << 16 | u32::from(data[2]) << 8 | u32::from(data[3])
+}
+
+
+/// The headers of an rpm file
+#[derive(Debug)]
+pub struct RPMFile {
+    pub signature: Header,
+    pub header: Header
+}
+
+impl RPMFile {
+    /// Read the headers of an rpm file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RPMFile, RPMError> {
+        let mut reader = BufReader::new(try!(File::open(path)));
+        RPMFile::read(&mut reader)
+    }
+
+    /// Read the headers from the start of an rpm
+    pub fn read<R: Read>(reader: &mut R) -> Result<RPMFile, RPMError> {
+        let mut lead = [0; LEAD_SIZE];
+        if reader.read_exact(&mut lead).is_err() || lead[0..4] != LEAD_MAGIC[..] {
+            return Err(RPMError::NotAnRPM);
+        }
+
+        let signature = try!(Header::read(reader));
+        let padding = (8 - signature.size() % 8) % 8;
+        try!(reader.read_exact(&mut vec![0; padding]));
+        let header = try!(Header::read(reader));
+
+        Ok(RPMFile { signature: signature, header: header })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rpmfile::*;
+    use test_helper::*;
+
+    #[test]
+    fn test_read_rpm() {
+        let data = create_test_rpm(&[(SIGTAG_SHA1, TestTag::String("0123456789abcdef".to_string())),
+                                     (SIGTAG_RSA, TestTag::Bin(vec![1, 2, 3]))],
+                                   &[(RPMTAG_NAME, TestTag::String("bash".to_string())),
+                                     (RPMTAG_EPOCH, TestTag::Int32(vec![1])),
+                                     (RPMTAG_SUMMARY, TestTag::I18NString("The GNU Bourne Again shell".to_string())),
+                                     (RPMTAG_PROVIDENAME, TestTag::StringArray(vec!["/bin/sh".to_string(), "bash".to_string()])),
+                                     (RPMTAG_PROVIDEFLAGS, TestTag::Int32(vec![0, 8])),
+                                     (RPMTAG_DIRINDEXES, TestTag::Int32(vec![0, 1, 1])),
+                                     (RPMTAG_BASENAMES, TestTag::StringArray(vec!["bash".to_string(), "sh".to_string(), "bashbug".to_string()])),
+                                     (RPMTAG_DIRNAMES, TestTag::StringArray(vec!["/bin/".to_string(), "/usr/bin/".to_string()]))]);
+        let rpm = RPMFile::read(&mut data.as_slice()).unwrap();
+
+        assert_eq!(rpm.signature.get_string(SIGTAG_SHA1), Some("0123456789abcdef".to_string()));
+        assert_eq!(rpm.signature.get_bin(SIGTAG_RSA), Some(vec![1, 2, 3]));
+
+        assert_eq!(rpm.header.get_string(RPMTAG_NAME), Some("bash".to_string()));
+        assert_eq!(rpm.header.get_number(RPMTAG_EPOCH), Some(1));
+        assert_eq!(rpm.header.get_string(RPMTAG_SUMMARY), Some("The GNU Bourne Again shell".to_string()));
+        assert_eq!(rpm.header.get_strings(RPMTAG_PROVIDENAME), vec!["/bin/sh", "bash"]);
+        assert_eq!(rpm.header.get_numbers(RPMTAG_PROVIDEFLAGS), vec![0, 8]);
+        assert_eq!(rpm.header.file_paths(), vec!["/bin/bash", "/usr/bin/sh", "/usr/bin/bashbug"]);
+
+        // Missing tags, and tags of the wrong type
+        assert!(!rpm.header.has_tag(RPMTAG_URL));
+        assert_eq!(rpm.header.get_string(RPMTAG_URL), None);
+        assert_eq!(rpm.header.get_number(RPMTAG_NAME), None);
+        assert_eq!(rpm.header.get_bin(RPMTAG_NAME), None);
+    }
+
+    #[test]
+    fn test_repeated_tags() {
+        // The size of the signature header counts every entry, so the main header is still found
+        let data = create_test_rpm(&[(SIGTAG_SHA1, TestTag::String("0123456789abcdef".to_string())),
+                                     (SIGTAG_SHA1, TestTag::String("fedcba9876543210".to_string()))],
+                                   &[(RPMTAG_NAME, TestTag::String("bash".to_string()))]);
+        let rpm = RPMFile::read(&mut data.as_slice()).unwrap();
+        assert_eq!(rpm.signature.size(), 16 + 2 * 16 + 34);
+        assert_eq!(rpm.header.get_string(RPMTAG_NAME), Some("bash".to_string()));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        match RPMFile::read(&mut "Not an rpm".as_bytes()) {
+            Err(RPMError::NotAnRPM) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        // Cut off in the middle of the main header
+        let data = create_test_rpm(&[], &[(RPMTAG_NAME, TestTag::String("bash".to_string()))]);
+        match RPMFile::read(&mut &data[..data.len() - 2]) {
+            Err(RPMError::IO(_)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        // A tag's data runs past the end of the store
+        let mut data = create_test_rpm(&[], &[(RPMTAG_FILEMTIMES, TestTag::Int32(vec![1, 2]))]);
+        let len = data.len();
+        // The count of the main header's only index entry
+        data[len - 8 - 4..len - 8].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        match RPMFile::read(&mut data.as_slice()) {
+            Err(RPMError::BadHeader(_)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+
+        // The main header's magic is wrong
+        let mut data = create_test_rpm(&[], &[]);
+        let len = data.len();
+        data[len - 16] = 0;
+        match RPMFile::read(&mut data.as_slice()) {
+            Err(RPMError::BadHeader(_)) => (),
+            other => panic!("Unexpected result: {:?}", other)
+        }
+    }
+}
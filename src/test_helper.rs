@@ -17,11 +17,36 @@
 // You should have received a copy of the GNU General Public License
 // along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::{Path, PathBuf};
+
 use rusqlite::{self, Connection};
+use uuid::Uuid;
 
+use db::create_schema;
 use rpm::*;
 use std::str::FromStr;
 
+// A new directory under the system's temporary directory, removed when it is dropped
+pub struct TestDir {
+    pub path: PathBuf
+}
+
+impl TestDir {
+    pub fn new(prefix: &str) -> TestDir {
+        let path = env::temp_dir().join(format!("{}-{}", prefix, Uuid::new_v4()));
+        create_dir_all(&path).unwrap();
+        TestDir { path: path }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
+
 // structs and functions for creating in-memory databases
 // id-less data types for creating test data
 pub struct TestKeyValues {
@@ -35,6 +60,7 @@ pub struct TestFiles {
     pub file_user: String,
     pub file_group: String,
     pub mtime: i64,
+    pub cs_object: Option<String>,
     pub key_vals: Vec<TestKeyValues>
 }
 
@@ -97,7 +123,50 @@ pub enum TestData {
     Groups(TestGroups)
 }
 
+// shorthand for a TextKey key/value
+pub fn test_kv(key: &str, value: &str) -> TestKeyValues {
+    TestKeyValues {
+        key_value: format!("TextKey \"{}\"", key),
+        val_value: Some(value.to_string()),
+        ext_value: None
+    }
+}
+
+// a file owned by root, with the content store object if it has one
+pub fn test_file(path: &str, cs_object: Option<&str>) -> TestFiles {
+    TestFiles {
+        path:       path.to_string(),
+        file_user:  "root".to_string(),
+        file_group: "root".to_string(),
+        mtime:      1501708260,
+        cs_object:  cs_object.map(String::from),
+        key_vals:   vec![]
+    }
+}
+
+// a group without any files, children, key/values or requirements
+pub fn test_group(name: &str, group_type: &str) -> TestGroups {
+    TestGroups {
+        name:         name.to_string(),
+        group_type:   group_type.to_string(),
+        build_id:     0,
+        files:        vec![],
+        children:     vec![],
+        key_vals:     vec![],
+        requirements: vec![]
+    }
+}
+
 pub fn create_test_db(data: &[TestData]) -> rusqlite::Result<Connection> {
+    populate_test_db(try!(Connection::open_in_memory()), data)
+}
+
+// Same as create_test_db, but the database is written to a file
+pub fn create_test_db_file(path: &Path, data: &[TestData]) -> rusqlite::Result<Connection> {
+    populate_test_db(try!(Connection::open(path)), data)
+}
+
+fn populate_test_db(conn: Connection, data: &[TestData]) -> rusqlite::Result<Connection> {
     fn insert_record(conn: &Connection, data: &TestData) -> rusqlite::Result<()> {
         fn insert_key_val(conn: &Connection, keyval: &TestKeyValues) -> rusqlite::Result<i64> {
             try!(conn.execute_named("
@@ -111,12 +180,13 @@ pub fn create_test_db(data: &[TestData]) -> rusqlite::Result<Connection> {
 
         fn insert_file(conn: &Connection, file: &TestFiles) -> rusqlite::Result<i64> {
             try!(conn.execute_named("
-                insert into files (path, file_user, file_group, mtime)
-                values (:path, :file_user, :file_group, :mtime)",
+                insert into files (path, file_user, file_group, mtime, cs_object)
+                values (:path, :file_user, :file_group, :mtime, :cs_object)",
                 &[(":path", &file.path),
                   (":file_user", &file.file_user),
                   (":file_group", &file.file_group),
-                  (":mtime", &file.mtime)]));
+                  (":mtime", &file.mtime),
+                  (":cs_object", &file.cs_object)]));
             let file_id = conn.last_insert_rowid();
 
             for kv in &file.key_vals {
@@ -164,7 +234,7 @@ pub fn create_test_db(data: &[TestData]) -> rusqlite::Result<Connection> {
 
             for kv in &build.key_vals {
                 let key_val_id = try!(insert_key_val(conn, kv));
-                try!(conn.execute_named("insert into build_values (build_id, key_val_id) values (:build_id, :key_val_id)",
+                try!(conn.execute_named("insert into build_key_values (build_id, key_val_id) values (:build_id, :key_val_id)",
                                                 &[(":build_id", &build_id), (":key_val_id", &key_val_id)]));
             }
 
@@ -270,158 +340,7 @@ pub fn create_test_db(data: &[TestData]) -> rusqlite::Result<Connection> {
         Ok(())
     }
 
-    let conn = try!(Connection::open_in_memory());
-
-    // copied from bdcs/schema.sql
-    try!(conn.execute_batch("
-BEGIN;
-create table projects (
-    id integer primary key,
-    name text not null unique,
-    summary text not null,
-    description text not null,
-    homepage text,
-    upstream_vcs text not null
-);
-
-create table sources (
-    id integer primary key,
-    project_id integer references projects(id) not null,
-    license text not null,
-    version text not null,
-    source_ref text not null
-);
-create index sources_project_id_idx on sources(project_id);
-
-create table builds (
-    id integer primary key,
-    source_id integer references sources(id) not null,
-    epoch integer default 0,
-    release text not null,
-    arch text not null,
-    build_time text not null,
-    changelog blob not null,
-    build_config_ref text not null,
-    build_env_ref text not null
-);
-create index builds_source_id_idx on builds(source_id);
-
-create table build_signatures (
-    id integer primary key,
-    build_id integer references build(id) not null,
-    signature_type text not null,
-    signature_data blob not null
-);
-create index build_signatures_build_id_idx on build_signatures(build_id);
-
-create table files (
-    id integer primary key,
-    path text not null,
-    file_user text not null,
-    file_group text not null,
-    mtime integer not null
-);
-create index files_path_idx on files(path);
-
-create table build_files (
-    id integer primary key,
-    build_id integer references build(id) not null,
-    file_id integer references files(id) not null
-);
-create index build_files_build_id_idx on build_files(build_id);
-create index build_files_file_id_idx on build_files(file_id);
-
-create table key_val (
-    id integer primary key,
-    key_value text not null,
-    val_value text,
-    ext_value text
-);
-
-create index key_val_key_value_idx on key_val(key_value);
-create index key_val_val_value_idx on key_val(key_value, val_value);
-
-create table project_values (
-    id integer primary key,
-    project_id integer references projects(id) not null,
-    key_val_id integer references key_val(id) not null
-);
-create index project_values_project_id_idx on project_values(project_id);
-create index project_values_key_val_id_idx on project_values(key_val_id);
-
-create table source_key_values (
-    id integer primary key,
-    source_id integer references sources(id) not null,
-    key_val_id integer references key_val(id) not null
-);
-create index source_key_values_source_id_idx on source_key_values(source_id);
-create index source_key_values_key_val_id_idx on source_key_values(key_val_id);
-
-create table build_key_values (
-    id integer primary key,
-    build_id integer references builds(id) not null,
-    key_val_id integer references key_val(id) not null
-);
-create index build_key_values_build_id_idx on build_key_values(build_id);
-create index build_key_values_key_val_id_idx on build_key_values(key_val_id);
-
-create table file_key_values (
-    id integer primary key,
-    file_id integer references files(id) not null,
-    key_val_id integer references key_val(id) not null
-);
-create index file_key_values_file_id_idx on file_key_values(file_id);
-create index file_key_values_key_val_id_idx on file_key_values(key_val_id);
-
-create table groups (
-    id integer primary key,
-    name text not null,
-    group_type text not null,
-    build_id integer references builds(id) null
-);
-create index groups_name_idx on groups(name);
-
-create table group_files (
-    id integer primary key,
-    group_id integer references groups(id) not null,
-    file_id integer references files(id) not null
-);
-create index group_files_group_id_idx on group_files(group_id);
-create index group_files_file_id_idx on group_files(file_id);
-
-create table group_groups (
-    id integer primary key,
-    parent_group_id references groups(id) not null,
-    child_group_id references groups(id) not null
-);
-create index group_groups_parent_group_id_idx on group_groups(parent_group_id);
-create index group_groups_child_group_id_idx on group_groups(child_group_id);
-
-create table group_key_values (
-    id integer primary key,
-    group_id integer references groups(id) not null,
-    key_val_id integer references key_val(id) not null
-);
-create index group_key_values_group_id_idx on group_key_values(group_id);
-create index group_key_values_key_val_id_idx on group_key_values(key_val_id);
-
-create table requirements (
-    id integer primary key,
-    req_language text not null,
-    req_context text not null,
-    req_strength text not null,
-    req_expr text not null
-);
-
-create table group_requirements (
-    id integer primary key,
-    group_id integer references groups(id) not null,
-    req_id integer references requirements(id) not null
-);
-create index group_requirements_group_id_idx on group_requirements(group_id);
-create index group_requirements_req_id_idx on group_requirements(req_id);
-COMMIT;
-"));
+    try!(create_schema(&conn));
 
     for rec in data.iter() {
         try!(insert_record(&conn, rec));
@@ -459,40 +378,41 @@ pub fn testpkg(name: &str, epoch: Option<u32>, version: &str, release: &str, arc
 }
 
 pub fn create_test_packages(data: &[TestPkg]) -> rusqlite::Result<Connection> {
-    fn pkg_to_group(pkg: &TestPkg) -> TestData {
-        let mut key_vals: Vec<TestKeyValues> = vec![
-            TestKeyValues{key_value: "TextKey \"name\"".to_string(), val_value: Some(pkg.name.clone()), ext_value: None},
-            TestKeyValues{key_value: "TextKey \"version\"".to_string(), val_value: Some(pkg.evr.version.clone()), ext_value: None},
-            TestKeyValues{key_value: "TextKey \"release\"".to_string(), val_value: Some(pkg.evr.release.clone()), ext_value: None},
-            TestKeyValues{key_value: "TextKey \"arch\"".to_string(), val_value: Some(pkg.arch.clone()), ext_value: None}];
-
-        if let Some(epoch) = pkg.evr.epoch {
-            key_vals.push(TestKeyValues{key_value: "TextKey \"epoch\"".to_string(), val_value: Some(epoch.to_string()), ext_value: None});
-        }
+    create_test_db(&data.iter().map(pkg_to_group).collect::<Vec<TestData>>())
+}
 
-        for p in &pkg.provides {
-            key_vals.push(TestKeyValues{key_value: "TextKey \"rpm-provide\"".to_string(), val_value: Some(p.name.clone()), ext_value: Some(p.to_string())});
-        }
+// Same as create_test_packages, but the database is written to a file
+pub fn create_test_packages_file(path: &Path, data: &[TestPkg]) -> rusqlite::Result<Connection> {
+    create_test_db_file(path, &data.iter().map(pkg_to_group).collect::<Vec<TestData>>())
+}
 
-        for o in &pkg.obsoletes {
-            key_vals.push(TestKeyValues{key_value: "TextKey \"rpm-obsolete\"".to_string(), val_value: Some(o.name.clone()), ext_value: Some(o.to_string())});
-        }
+fn pkg_to_group(pkg: &TestPkg) -> TestData {
+    let mut key_vals: Vec<TestKeyValues> = vec![
+        test_kv("name", &pkg.name),
+        test_kv("version", &pkg.evr.version),
+        test_kv("release", &pkg.evr.release),
+        test_kv("arch", &pkg.arch)];
 
-        for c in &pkg.conflicts {
-            key_vals.push(TestKeyValues{key_value: "TextKey \"rpm-conflict\"".to_string(), val_value: Some(c.name.clone()), ext_value: Some(c.to_string())});
-        }
+    if let Some(epoch) = pkg.evr.epoch {
+        key_vals.push(test_kv("epoch", &epoch.to_string()));
+    }
 
-        let requirements: Vec<TestRequirements> = pkg.requires.iter().map(|r| TestRequirements{req_language: "RPM".to_string(), req_context: "Runtime".to_string(),
-                                                                                               req_strength: "Must".to_string(), req_expr: r.to_string()}).collect();
+    for p in &pkg.provides {
+        key_vals.push(TestKeyValues{key_value: "TextKey \"rpm-provide\"".to_string(), val_value: Some(p.name.clone()), ext_value: Some(p.to_string())});
+    }
 
-        TestData::Groups(TestGroups{name: pkg.name.clone(), group_type: "rpm".to_string(), build_id: 0,
-                                    files: vec![],
-                                    children: vec![],
-                                    key_vals: key_vals,
-                                    requirements: requirements})
+    for o in &pkg.obsoletes {
+        key_vals.push(TestKeyValues{key_value: "TextKey \"rpm-obsolete\"".to_string(), val_value: Some(o.name.clone()), ext_value: Some(o.to_string())});
     }
 
-    create_test_db(&data.iter().map(pkg_to_group).collect::<Vec<TestData>>())
+    for c in &pkg.conflicts {
+        key_vals.push(TestKeyValues{key_value: "TextKey \"rpm-conflict\"".to_string(), val_value: Some(c.name.clone()), ext_value: Some(c.to_string())});
+    }
+
+    let requirements: Vec<TestRequirements> = pkg.requires.iter().map(|r| TestRequirements{req_language: "RPM".to_string(), req_context: "Runtime".to_string(),
+                                                                                           req_strength: "Must".to_string(), req_expr: r.to_string()}).collect();
+
+    TestData::Groups(TestGroups{key_vals: key_vals, requirements: requirements, .. test_group(&pkg.name, "rpm")})
 }
 
 // something like this should probably be in db.rs at some point
@@ -518,3 +438,88 @@ pub fn get_nevra_group_id(conn: &Connection, name: &str, epoch: Option<u32>, ver
           (":arch", &(arch.to_string()))],
         |row| row.get(0)).unwrap()
 }
+
+// A tag value for a test rpm header
+pub enum TestTag {
+    Int32(Vec<u32>),
+    String(String),
+    StringArray(Vec<String>),
+    I18NString(String),
+    Bin(Vec<u8>)
+}
+
+// Return an rpm header with the tags
+pub fn create_test_rpm_header(tags: &[(u32, TestTag)]) -> Vec<u8> {
+    fn push_u32(data: &mut Vec<u8>, n: u32) {
+        data.extend_from_slice(&[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+    }
+
+    let mut index = Vec::new();
+    let mut store = Vec::new();
+    for &(tag, ref value) in tags {
+        // Numbers are aligned to their size, like rpm does
+        if let TestTag::Int32(_) = *value {
+            while store.len() % 4 != 0 {
+                store.push(0);
+            }
+        }
+
+        let offset = store.len() as u32;
+        let (data_type, count) = match *value {
+            TestTag::Int32(ref numbers) => {
+                for n in numbers {
+                    push_u32(&mut store, *n);
+                }
+                (4, numbers.len())
+            },
+            TestTag::String(ref s) => {
+                store.extend_from_slice(s.as_bytes());
+                store.push(0);
+                (6, 1)
+            },
+            TestTag::StringArray(ref strings) => {
+                for s in strings {
+                    store.extend_from_slice(s.as_bytes());
+                    store.push(0);
+                }
+                (8, strings.len())
+            },
+            TestTag::I18NString(ref s) => {
+                store.extend_from_slice(s.as_bytes());
+                store.push(0);
+                (9, 1)
+            },
+            TestTag::Bin(ref bytes) => {
+                store.extend_from_slice(bytes);
+                (7, bytes.len())
+            }
+        };
+        push_u32(&mut index, tag);
+        push_u32(&mut index, data_type);
+        push_u32(&mut index, offset);
+        push_u32(&mut index, count as u32);
+    }
+
+    let mut header = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+    push_u32(&mut header, tags.len() as u32);
+    push_u32(&mut header, store.len() as u32);
+    header.extend_from_slice(&index);
+    header.extend_from_slice(&store);
+    header
+}
+
+// Return the lead and headers of an rpm file, without a payload
+pub fn create_test_rpm(signature: &[(u32, TestTag)], header: &[(u32, TestTag)]) -> Vec<u8> {
+    let mut rpm = vec![0; 96];
+    rpm[0..4].copy_from_slice(&[0xed, 0xab, 0xee, 0xdb]);
+    rpm[4] = 3;
+    // The signature type is a header
+    rpm[79] = 5;
+
+    let signature = create_test_rpm_header(signature);
+    let padding = (8 - signature.len() % 8) % 8;
+    rpm.extend_from_slice(&signature);
+    rpm.extend(vec![0; padding]);
+    rpm.extend_from_slice(&create_test_rpm_header(header));
+    rpm
+}
@@ -0,0 +1,214 @@
+//! Recipe Validation
+//!
+//! ## Overview
+//!
+//! Check a recipe for problems before it is saved. The checks are:
+//!
+//! * The `version` must be empty or a valid semver, eg. `0.1.2`
+//! * Each module and package name may only be listed once
+//! * Each module and package must exist in the metadata.db
+//! * Each version expression must be satisfied by at least one build
+//! * Each package group must be defined, and must not include itself
+//! * User passwords in the customizations must be crypt(3) hashes, not plaintext
+//!
+
+// Copyright (C) 2017 Red Hat, Inc.
+//
+// This file is part of bdcs-api-server.
+//
+// bdcs-api-server is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// bdcs-api-server is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use rusqlite::{self, Connection};
+use semver;
+
+use comps::{Comps, CompsError};
+use db::{get_groups_name, get_projects_name};
+use depclose::PackageSpec;
+use recipe::Recipe;
+
+
+/// A problem found in a recipe
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RecipeProblem {
+    /// The recipe's version is not a valid semver
+    BadVersion(String),
+    /// A module or package name is listed more than once
+    DuplicateName(String),
+    /// There is no project or package with this name
+    UnknownName(String),
+    /// No build matches the module or package's version expression
+    UnsatisfiedVersion(PackageSpec),
+    /// There is no package group with this name
+    UnknownGroup(String),
+    /// The package group includes itself
+    GroupLoop(String),
+    /// The user's password is not a crypt(3) hash
+    PlaintextPassword(String),
+}
+
+impl RecipeProblem {
+    /// Return the problem's id, used in the API's error responses
+    pub fn id(&self) -> &'static str {
+        match *self {
+            RecipeProblem::BadVersion(_) => "BadVersion",
+            RecipeProblem::DuplicateName(_) => "DuplicateName",
+            RecipeProblem::UnknownName(_) => "UnknownName",
+            RecipeProblem::UnsatisfiedVersion(_) => "UnsatisfiedVersion",
+            RecipeProblem::UnknownGroup(_) => "UnknownGroup",
+            RecipeProblem::GroupLoop(_) => "GroupLoop",
+            RecipeProblem::PlaintextPassword(_) => "PlaintextPassword",
+        }
+    }
+}
+
+impl fmt::Display for RecipeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecipeProblem::BadVersion(ref version) => write!(f, "Invalid recipe version {}, it must be a semver like 0.1.2", version),
+            RecipeProblem::DuplicateName(ref name) => write!(f, "{} is listed more than once", name),
+            RecipeProblem::UnknownName(ref name) => write!(f, "Unknown module or package {}", name),
+            RecipeProblem::UnsatisfiedVersion(ref spec) => write!(f, "No build matches {}", spec),
+            RecipeProblem::UnknownGroup(ref name) => write!(f, "Unknown package group {}", name),
+            RecipeProblem::GroupLoop(ref name) => write!(f, "Package group {} includes itself", name),
+            RecipeProblem::PlaintextPassword(ref name) => write!(f, "The password for user {} must be a crypt(3) hash", name),
+        }
+    }
+}
+
+
+/// Check a recipe for problems
+///
+/// # Arguments
+///
+/// * `conn` - The database connection
+/// * `comps` - The package group definitions
+/// * `recipe` - The recipe to check
+///
+/// # Returns
+///
+/// * A Vector of the [RecipeProblems](enum.RecipeProblem.html) found, empty if the recipe is ok
+///
+/// The names are checked against both the projects and the groups in the database, the same way
+/// the depsolver looks them up.
+///
+pub fn validate_recipe(conn: &Connection, comps: &Comps, recipe: &Recipe) -> rusqlite::Result<Vec<RecipeProblem>> {
+    let mut problems = Vec::new();
+
+    if !recipe.version.is_empty() && semver::Version::parse(&recipe.version).is_err() {
+        problems.push(RecipeProblem::BadVersion(recipe.version.clone()));
+    }
+
+    let mut specs = Vec::new();
+    specs.extend(recipe.modules.iter().map(|m| PackageSpec::new(&m.name, m.version.as_ref().map(String::as_str))));
+    specs.extend(recipe.packages.iter().map(|p| PackageSpec::new(&p.name, p.version.as_ref().map(String::as_str))));
+
+    let mut seen = HashSet::new();
+    let mut duplicates = HashSet::new();
+    for spec in &specs {
+        if !seen.insert(spec.name.as_str()) {
+            if duplicates.insert(spec.name.as_str()) {
+                problems.push(RecipeProblem::DuplicateName(spec.name.clone()));
+            }
+            continue;
+        }
+
+        let groups = try!(get_groups_name(conn, &spec.name, 0, -1));
+        if groups.is_empty() {
+            let (total, _) = try!(get_projects_name(conn, &spec.name, 0, 1));
+            if total == 0 {
+                problems.push(RecipeProblem::UnknownName(spec.name.clone()));
+            }
+            continue;
+        }
+
+        // A bad version glob can't match anything, so treat it the same as no match
+        if !groups.iter().any(|grp| spec.matches(conn, grp.id).unwrap_or(false)) {
+            problems.push(RecipeProblem::UnsatisfiedVersion(spec.clone()));
+        }
+    }
+
+    // A group listed twice is harmless, it is only expanded once
+    let mut seen_groups = HashSet::new();
+    for name in recipe.group_names() {
+        if !seen_groups.insert(name.clone()) {
+            continue;
+        }
+
+        match comps.expand(conn, &[name.clone()]) {
+            Ok(_) => (),
+            Err(CompsError::SQLite(e)) => return Err(e),
+            Err(CompsError::Loop(_)) => problems.push(RecipeProblem::GroupLoop(name)),
+            Err(_) => problems.push(RecipeProblem::UnknownGroup(name))
+        }
+    }
+
+    problems.extend(password_problems(recipe));
+
+    Ok(problems)
+}
+
+/// Check that the passwords in a recipe's customizations are crypt(3) hashes
+///
+/// # Arguments
+///
+/// * `recipe` - The recipe to check
+///
+/// # Returns
+///
+/// * A Vector with a RecipeProblem::PlaintextPassword for each user whose password is not a hash
+///
+/// This doesn't need the database, so it is used to reject plaintext passwords whenever a recipe
+/// is saved, not only in strict mode.
+///
+pub fn password_problems(recipe: &Recipe) -> Vec<RecipeProblem> {
+    recipe.customizations.iter()
+         .flat_map(|customizations| customizations.users.iter())
+         .filter(|user| user.password.as_ref().map(|p| !is_crypted_password(p)).unwrap_or(false))
+         .map(|user| RecipeProblem::PlaintextPassword(user.name.clone()))
+         .collect()
+}
+
+/// The characters used in the salt and hash of a crypt(3) password
+const CRYPT_CHARS: &'static str = "./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Check that a password is a crypt(3) hash
+///
+/// MD5 (`$1$salt$hash`), bcrypt (`$2b$10$...`, also `$2a$` and `$2y$`), SHA-256 (`$5$salt$hash`)
+/// and SHA-512 (`$6$salt$hash`) hashes are accepted. The SHA hashes may include the number of
+/// rounds, eg. `$6$rounds=5000$salt$hash`.
+pub fn is_crypted_password(password: &str) -> bool {
+    if !password.starts_with('$') {
+        return false;
+    }
+    let fields: Vec<&str> = password[1..].split('$').collect();
+    let is_crypt = |s: &str| !s.is_empty() && s.chars().all(|c| CRYPT_CHARS.contains(c));
+
+    match fields[0] {
+        "1" => fields.len() == 3 && is_crypt(fields[1]) && is_crypt(fields[2]),
+        "2a" | "2b" | "2y" => fields.len() == 3 && fields[1].len() == 2 && fields[1].chars().all(|c| c.is_digit(10))
+                              && fields[2].len() == 53 && is_crypt(fields[2]),
+        "5" | "6" => {
+            let fields = if fields.len() == 4 && fields[1].starts_with("rounds=") && fields[1][7..].parse::<u32>().is_ok() {
+                &fields[2..]
+            } else {
+                &fields[1..]
+            };
+            fields.len() == 2 && is_crypt(fields[0]) && is_crypt(fields[1])
+        },
+        _ => false
+    }
+}
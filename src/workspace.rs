@@ -24,6 +24,7 @@
 // You should have received a copy of the GNU General Public License
 // along with bdcs-api-server.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
 use std::fs::{File, OpenOptions, create_dir_all, remove_file};
 use std::io;
 use std::io::prelude::*;
@@ -43,6 +44,17 @@ pub enum WorkspaceError {
     RecipeError(recipe::RecipeError)
 }
 
+impl fmt::Display for WorkspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WorkspaceError::Io(ref e) => write!(f, "Workspace IO error: {}", e),
+            WorkspaceError::TomlSer(ref e) => write!(f, "Workspace TOML serialization error: {}", e),
+            WorkspaceError::TomlDe(ref e) => write!(f, "Workspace TOML parse error: {}", e),
+            WorkspaceError::RecipeError(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
 impl From<io::Error> for WorkspaceError {
     fn from(err: io::Error) -> WorkspaceError {
         WorkspaceError::Io(err)
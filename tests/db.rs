@@ -23,6 +23,10 @@ extern crate rusqlite;
 
 use rusqlite::Connection;
 use std::collections::HashSet;
+use std::fs::{create_dir_all, remove_dir_all, rename, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 
 use bdcs::db::*;
 use bdcs::test_helper::*;
@@ -46,9 +50,9 @@ fn test_db_1() -> rusqlite::Result<Connection> {
                    TestData::Groups(TestGroups{name: "group-one".to_string(),
                                                group_type: "rpm".to_string(),
                                                build_id: 0,
-                                               files: vec![TestFiles{path: "/one/1".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0,
+                                               files: vec![TestFiles{path: "/one/1".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0, cs_object: None,
                                                                      key_vals: vec![TestKeyValues{key_value: "TextKey \"packageName\"".to_string(), val_value: Some("group-one".to_string()), ext_value: None}]},
-                                                           TestFiles{path: "/one/2".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0,
+                                                           TestFiles{path: "/one/2".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0, cs_object: None,
                                                                      key_vals: vec![TestKeyValues{key_value: "TextKey \"packageName\"".to_string(), val_value: Some("group-one".to_string()), ext_value: None}]}],
                                                children: vec![],
                                                key_vals: vec![],
@@ -56,9 +60,9 @@ fn test_db_1() -> rusqlite::Result<Connection> {
                    TestData::Groups(TestGroups{name: "group-two".to_string(),
                                                group_type: "rpm".to_string(),
                                                build_id: 0,
-                                               files: vec![TestFiles{path: "/two/1".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0,
+                                               files: vec![TestFiles{path: "/two/1".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0, cs_object: None,
                                                                      key_vals: vec![TestKeyValues{key_value: "TextKey \"packageName\"".to_string(), val_value: Some("group-two".to_string()), ext_value: None}]},
-                                                           TestFiles{path: "/two/2".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0,
+                                                           TestFiles{path: "/two/2".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0, cs_object: None,
                                                                      key_vals: vec![TestKeyValues{key_value: "TextKey \"packageName\"".to_string(), val_value: Some("group-two".to_string()), ext_value: None}]}],
                                                children: vec![],
                                                key_vals: vec![],
@@ -104,7 +108,7 @@ fn test_db_2() -> rusqlite::Result<Connection> {
                                                                                   build_time: "".to_string(), changelog: vec![], build_config_ref: "".to_string(),
                                                                                   build_env_ref: "".to_string(), signatures: vec![], key_vals: vec![],
                                                                                   files: vec![
-                                                                                      TestFiles{path: "/r1/1".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0,
+                                                                                      TestFiles{path: "/r1/1".to_string(), file_user: "".to_string(), file_group: "".to_string(), mtime: 0, cs_object: None,
                                                                                                 key_vals: vec![TestKeyValues{key_value: "TextKey \"packageName\"".to_string(), val_value: Some("project-one".to_string()), ext_value: None}]}
                                                                                               ]}
                                                                                ]}
@@ -154,3 +158,67 @@ fn test_get_group_by_name_empty() {
     let conn = test_db_3().unwrap();
     assert_eq!(get_groups_by_name(&conn, "no-package", "rpm").unwrap().len(), 0);
 }
+
+// Write a database with a single rpm group, and set its schema version
+fn write_db_file(path: &Path, name: &str, version: u32) {
+    let conn = create_test_db_file(path, &[TestData::Groups(test_group(name, "rpm"))]).unwrap();
+    conn.execute_batch(&format!("pragma user_version = {};", version)).unwrap();
+}
+
+#[test]
+fn test_dbpool_reload() {
+    let dir = PathBuf::from("/var/tmp/bdcs-dbpool-reload-test/");
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    let db_path = dir.join("metadata.db");
+
+    write_db_file(&db_path, "package-one", DB_VERSION);
+    let mut pool = DBPool::new(&db_path.to_string_lossy());
+    pool.set_reload_interval(Duration::from_secs(0));
+    assert_eq!(get_groups_name(&pool.conn(), "package-one", 0, -1).unwrap().len(), 1);
+    assert_eq!(pool.reload().unwrap(), false);
+
+    // Replacing the file swaps in the new database
+    write_db_file(&dir.join("new.db"), "package-two", DB_VERSION);
+    rename(dir.join("new.db"), &db_path).unwrap();
+    assert_eq!(get_groups_name(&pool.conn(), "package-two", 0, -1).unwrap().len(), 1);
+    assert_eq!(get_groups_name(&pool.conn(), "package-one", 0, -1).unwrap().len(), 0);
+
+    // An unsupported schema is refused, and the old database is kept
+    write_db_file(&dir.join("new.db"), "package-three", DB_VERSION + 1);
+    rename(dir.join("new.db"), &db_path).unwrap();
+    match pool.reload() {
+        Err(ReloadError::Unsupported(version)) => assert_eq!(version, DB_VERSION + 1),
+        other => panic!("Unexpected result: {:?}", other)
+    }
+    assert_eq!(pool.reload().unwrap(), false);
+    assert_eq!(get_groups_name(&pool.conn(), "package-two", 0, -1).unwrap().len(), 1);
+    assert_eq!(get_groups_name(&pool.conn(), "package-three", 0, -1).unwrap().len(), 0);
+
+    // So is a file that isn't a database, and the error is only returned once
+    File::create(dir.join("new.db")).unwrap().write_all(b"Not a database").unwrap();
+    rename(dir.join("new.db"), &db_path).unwrap();
+    assert!(pool.reload().is_err());
+    assert_eq!(pool.reload().unwrap(), false);
+    assert_eq!(get_groups_name(&pool.conn(), "package-two", 0, -1).unwrap().len(), 1);
+}
+
+#[test]
+fn test_dbpool_reload_interval() {
+    let dir = PathBuf::from("/var/tmp/bdcs-dbpool-interval-test/");
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    let db_path = dir.join("metadata.db");
+
+    write_db_file(&db_path, "package-one", DB_VERSION);
+    let pool = DBPool::new(&db_path.to_string_lossy());
+
+    // The file isn't checked again until the interval has passed
+    write_db_file(&dir.join("new.db"), "package-two", DB_VERSION);
+    rename(dir.join("new.db"), &db_path).unwrap();
+    assert_eq!(get_groups_name(&pool.conn(), "package-one", 0, -1).unwrap().len(), 1);
+
+    // But it can be reloaded explicitly
+    assert_eq!(pool.reload().unwrap(), true);
+    assert_eq!(get_groups_name(&pool.conn(), "package-two", 0, -1).unwrap().len(), 1);
+}
@@ -75,7 +75,10 @@ fn write_config() {
             db_path: DB_PATH.to_string(),
             recipe_path: RECIPE_PATH.to_string(),
             log_path: "/var/log/bdcs-api.log".to_string(),
-            mockfiles_path: "./tests/results/v0/".to_string()
+            mockfiles_path: "./tests/results/v0/".to_string(),
+            cors_origins: vec!["*".to_string()],
+            cors_credentials: false,
+            cors_max_age: 0
 
         }
     };